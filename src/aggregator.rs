@@ -1,4 +1,5 @@
-//! Operations on `Vec<R: IpRange>`: aggregate, reverse, difference, normalize.
+//! Operations on `Vec<R: IpRange>`: aggregate, reverse, difference, intersect,
+//! union, symmetric difference, normalize.
 //!
 //! Each operation exists in two forms:
 //! - **Consuming** (`aggregated()`, `reversed()`, etc.) — takes `self`, returns a new `Vec`.
@@ -28,6 +29,20 @@ pub trait Aggregator<R: IpRange> {
     #[must_use = "for in-place modification, use `difference`"]
     fn differenced(self, other: &[R]) -> Vec<R>;
 
+    /// Keep only addresses present in both `self` and `other`. Both inputs must
+    /// be sorted and aggregated.
+    #[must_use = "for in-place modification, use `intersect`"]
+    fn intersected(self, other: &[R]) -> Vec<R>;
+
+    /// Merge `other` into `self`. Both inputs must be sorted and aggregated.
+    #[must_use = "for in-place modification, use `union`"]
+    fn unioned(self, other: &[R]) -> Vec<R>;
+
+    /// Keep addresses present in exactly one of `self` and `other`. Both inputs
+    /// must be sorted and aggregated.
+    #[must_use = "for in-place modification, use `symmetric_difference`"]
+    fn symmetric_differenced(self, other: &[R]) -> Vec<R>;
+
     /// Split ranges into canonical CIDR blocks (power-of-two aligned).
     #[must_use = "for in-place modification, use `normalize`"]
     fn normalized(self) -> Vec<R>;
@@ -38,6 +53,12 @@ pub trait Aggregator<R: IpRange> {
 
     fn difference(&mut self, other: &[R]);
 
+    fn intersect(&mut self, other: &[R]);
+
+    fn union(&mut self, other: &[R]);
+
+    fn symmetric_difference(&mut self, other: &[R]);
+
     fn normalize(&mut self);
 
     /// Sum of address counts across all ranges.
@@ -60,6 +81,18 @@ impl<R: IpRange> Aggregator<R> for Vec<R> {
         difference(self, other)
     }
 
+    fn intersected(self, other: &[R]) -> Vec<R> {
+        intersection(self, other)
+    }
+
+    fn unioned(self, other: &[R]) -> Vec<R> {
+        union(self, other)
+    }
+
+    fn symmetric_differenced(self, other: &[R]) -> Vec<R> {
+        symmetric_difference(self, other)
+    }
+
     fn normalized(self) -> Vec<R> {
         normalized(self)
     }
@@ -79,6 +112,21 @@ impl<R: IpRange> Aggregator<R> for Vec<R> {
         *self = mem::take(self).differenced(other);
     }
 
+    #[inline(always)]
+    fn intersect(&mut self, other: &[R]) {
+        *self = mem::take(self).intersected(other);
+    }
+
+    #[inline(always)]
+    fn union(&mut self, other: &[R]) {
+        *self = mem::take(self).unioned(other);
+    }
+
+    #[inline(always)]
+    fn symmetric_difference(&mut self, other: &[R]) {
+        *self = mem::take(self).symmetric_differenced(other);
+    }
+
     #[inline(always)]
     fn normalize(&mut self) {
         *self = mem::take(self).normalized();
@@ -100,10 +148,12 @@ impl<R: IpRange> Aggregator<R> for Vec<R> {
 /// Merge overlapping and adjacent ranges into a minimal set.
 #[inline(always)]
 pub(crate) fn aggregated<R: IpRange>(mut ranges: Vec<R>) -> Vec<R> {
-    if ranges.is_empty() {
-        return ranges;
-    }
     ranges.sort();
+    coalesced(ranges)
+}
+
+/// Merge overlapping and adjacent ranges of an already sorted sequence.
+fn coalesced<R: IpRange>(ranges: impl IntoIterator<Item = R>) -> Vec<R> {
     let mut ranges_iter = ranges.into_iter().map(|range| {
         (
             range.first_address_as_decimal(),
@@ -111,7 +161,10 @@ pub(crate) fn aggregated<R: IpRange>(mut ranges: Vec<R>) -> Vec<R> {
         )
     });
     let mut aggregated_ranges = Vec::<R>::new();
-    let mut last_range = ranges_iter.next().unwrap();
+    let mut last_range = match ranges_iter.next() {
+        Some(range) => range,
+        None => return aggregated_ranges,
+    };
     for range in ranges_iter {
         if max(range.0, R::AddressDecimal::one()) - R::AddressDecimal::one() <= last_range.1 {
             // let length = (range.0 - last_range.0).wrapping_add(&range.1);
@@ -259,6 +312,58 @@ pub(crate) fn difference<R: IpRange>(mut a: Vec<R>, b: &[R]) -> Vec<R> {
     ds
 }
 
+/// Intersect `a` with `b`. Both inputs must be sorted and aggregated.
+///
+/// Walks both lists once, so it runs in `O(a.len() + b.len())` without
+/// materializing the complement of either side.
+#[inline(always)]
+pub(crate) fn intersection<R: IpRange>(a: Vec<R>, b: &[R]) -> Vec<R> {
+    let mut is = Vec::new();
+    let mut i = 0;
+    let mut j = 0;
+    while i < a.len() && j < b.len() {
+        let first = max(
+            a[i].first_address_as_decimal(),
+            b[j].first_address_as_decimal(),
+        );
+        let last = min(
+            a[i].last_address_as_decimal(),
+            b[j].last_address_as_decimal(),
+        );
+        if first <= last {
+            is.push(R::from_cidr_pair_decimal((first, last)));
+        }
+        // Advance whichever range ends first; the other may still overlap the
+        // next range on the opposite side.
+        if a[i].last_address_as_decimal() < b[j].last_address_as_decimal() {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    is
+}
+
+/// Merge `a` and `b`. Both inputs must be sorted and aggregated.
+///
+/// The two lists are interleaved in order and coalesced in a single pass, so
+/// no re-sorting is needed.
+#[inline(always)]
+pub(crate) fn union<R: IpRange>(a: Vec<R>, b: &[R]) -> Vec<R> {
+    if b.is_empty() {
+        return a;
+    }
+    coalesced(a.into_iter().merge(b.iter().copied()))
+}
+
+/// Compute `(a - b) ∪ (b - a)`. Both inputs must be sorted and aggregated.
+#[inline(always)]
+pub(crate) fn symmetric_difference<R: IpRange>(a: Vec<R>, b: &[R]) -> Vec<R> {
+    let b_minus_a = difference(b.to_vec(), &a);
+    let a_minus_b = difference(a, b);
+    union(a_minus_b, &b_minus_a)
+}
+
 /// Run the full pipeline: aggregate, optionally reverse, optionally exclude
 /// reserved addresses, then normalize.
 ///
@@ -543,6 +648,240 @@ mod tests {
         assert_eq!(overlap, result);
     }
 
+    // ---- intersection ----
+
+    #[test]
+    fn intersection_empty() {
+        let a: Vec<crate::Ipv4Range> = vec![];
+        let b = v4s(&["10.0.0.0/24"]);
+        assert_eq!(a.clone().intersected(&b), vec![]);
+        assert_eq!(b.intersected(&a), vec![]);
+    }
+
+    #[test]
+    fn intersection_no_overlap() {
+        let a = v4s(&["10.0.0.0/24"]);
+        let b = v4s(&["192.168.0.0/24"]);
+        assert_eq!(a.intersected(&b), vec![]);
+    }
+
+    #[test]
+    fn intersection_a_contains_b() {
+        let a = v4s(&["10.0.0.0/16"]);
+        let b = v4s(&["10.0.1.0/24", "10.0.3.0/24"]);
+        assert_eq!(a.intersected(&b), v4s(&["10.0.1.0/24", "10.0.3.0/24"]));
+    }
+
+    #[test]
+    fn intersection_partial_overlap() {
+        // a = [10.0.0.0, 10.0.1.255], b = [10.0.1.0, 10.0.2.255]
+        let a = v4s(&["10.0.0.0/23"]);
+        let b = vec![Ipv4Range::from_cidr_pair_decimal((0x0a000100, 0x0a0002ff))];
+        assert_eq!(a.intersected(&b), v4s(&["10.0.1.0/24"]));
+    }
+
+    #[test]
+    fn intersection_one_range_spans_many() {
+        let a = v4s(&["10.0.0.0/24", "10.0.2.0/24", "10.0.4.0/24"]);
+        let b = vec![Ipv4Range::from_cidr_pair_decimal((0x0a000080, 0x0a000280))];
+        let result = a.intersected(&b);
+        assert_eq!(
+            result,
+            vec![
+                Ipv4Range::from_cidr_pair_decimal((0x0a000080, 0x0a0000ff)),
+                Ipv4Range::from_cidr_pair_decimal((0x0a000200, 0x0a000280)),
+            ]
+        );
+    }
+
+    #[test]
+    fn intersection_is_commutative() {
+        let a = v4s(&["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"]).aggregated();
+        let b = v4s(&["10.1.0.0/16", "172.31.255.0/24", "192.0.0.0/8"]).aggregated();
+        assert_eq!(a.clone().intersected(&b), b.intersected(&a));
+    }
+
+    #[test]
+    fn intersection_matches_difference_of_complement() {
+        let a = v4s(&["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"]).aggregated();
+        let b = v4s(&["10.1.0.0/16", "172.31.255.0/24", "192.0.0.0/8"]).aggregated();
+        let expected = a.clone().differenced(&b.clone().reversed());
+        assert_eq!(a.intersected(&b), expected);
+    }
+
+    #[test]
+    fn intersection_with_full() {
+        let a = v4s(&["10.0.0.0/8", "255.255.255.255/32"]);
+        assert_eq!(a.clone().intersected(&[Ipv4Range::full()]), a);
+        assert_eq!(vec![Ipv4Range::full()].intersected(&a), a);
+        assert_eq!(
+            vec![Ipv4Range::full()].intersected(&[Ipv4Range::full()]),
+            vec![Ipv4Range::full()]
+        );
+    }
+
+    #[test]
+    fn intersection_at_max_value() {
+        let a = v4s(&["255.255.255.0/24"]);
+        let b = v4s(&["255.255.255.255/32"]);
+        assert_eq!(a.intersected(&b), v4s(&["255.255.255.255/32"]));
+    }
+
+    #[test]
+    fn intersection_ipv6() {
+        let a = v6s(&["2001:db8::/32", "fc00::/7"]);
+        let b = v6s(&["2001:db8:1::/48", "fd00::/8", "fe80::/10"]);
+        assert_eq!(a.intersected(&b), v6s(&["2001:db8:1::/48", "fd00::/8"]));
+    }
+
+    #[test]
+    fn intersection_ipv6_full() {
+        let a = v6s(&["::/128", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff/128"]);
+        assert_eq!(vec![Ipv6Range::full()].intersected(&a), a);
+    }
+
+    #[test]
+    fn intersect_in_place() {
+        let mut ranges = v4s(&["10.0.0.0/23"]);
+        ranges.intersect(&v4s(&["10.0.1.0/24", "192.168.0.0/16"]));
+        assert_eq!(ranges, v4s(&["10.0.1.0/24"]));
+    }
+
+    // ---- union ----
+
+    #[test]
+    fn union_empty() {
+        let a: Vec<crate::Ipv4Range> = vec![];
+        let b = v4s(&["10.0.0.0/24"]);
+        assert_eq!(a.clone().unioned(&b), b);
+        assert_eq!(b.clone().unioned(&a), b);
+    }
+
+    #[test]
+    fn union_disjoint_interleaved() {
+        let a = v4s(&["10.0.0.0/24", "10.0.4.0/24"]);
+        let b = v4s(&["10.0.2.0/24", "192.168.0.0/16"]);
+        assert_eq!(
+            a.unioned(&b),
+            v4s(&[
+                "10.0.0.0/24",
+                "10.0.2.0/24",
+                "10.0.4.0/24",
+                "192.168.0.0/16"
+            ])
+        );
+    }
+
+    #[test]
+    fn union_merges_adjacent_across_inputs() {
+        let a = v4s(&["10.0.0.0/24"]);
+        let b = v4s(&["10.0.1.0/24"]);
+        assert_eq!(a.unioned(&b), v4s(&["10.0.0.0/23"]));
+    }
+
+    #[test]
+    fn union_one_range_bridges_many() {
+        let a = v4s(&["10.0.0.0/24", "10.0.2.0/24", "10.0.4.0/24"]);
+        let b = vec![Ipv4Range::from_cidr_pair_decimal((0x0a000080, 0x0a000380))];
+        assert_eq!(
+            a.unioned(&b),
+            vec![
+                Ipv4Range::from_cidr_pair_decimal((0x0a000000, 0x0a000380)),
+                v4("10.0.4.0/24"),
+            ]
+        );
+    }
+
+    #[test]
+    fn union_matches_aggregated_concat() {
+        let a = v4s(&["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"]).aggregated();
+        let b = v4s(&["11.0.0.0/8", "172.31.255.0/24", "192.0.0.0/8"]).aggregated();
+        let mut concat = a.clone();
+        concat.extend_from_slice(&b);
+        assert_eq!(a.unioned(&b), concat.aggregated());
+    }
+
+    #[test]
+    fn union_reaches_full() {
+        let a = v4s(&["0.0.0.0/1"]);
+        let b = v4s(&["128.0.0.0/1"]);
+        assert_eq!(a.unioned(&b), vec![Ipv4Range::full()]);
+    }
+
+    #[test]
+    fn union_ipv6() {
+        let a = v6s(&["2001:db8::/33"]);
+        let b = v6s(&["2001:db8:8000::/33", "fc00::/7"]);
+        assert_eq!(a.unioned(&b), v6s(&["2001:db8::/32", "fc00::/7"]));
+    }
+
+    #[test]
+    fn union_in_place() {
+        let mut ranges = v4s(&["10.0.1.0/24"]);
+        ranges.union(&v4s(&["10.0.0.0/24"]));
+        assert_eq!(ranges, v4s(&["10.0.0.0/23"]));
+    }
+
+    // ---- symmetric difference ----
+
+    #[test]
+    fn symmetric_difference_empty() {
+        let a: Vec<crate::Ipv4Range> = vec![];
+        let b = v4s(&["10.0.0.0/24"]);
+        assert_eq!(a.clone().symmetric_differenced(&b), b);
+        assert_eq!(b.clone().symmetric_differenced(&a), b);
+    }
+
+    #[test]
+    fn symmetric_difference_identical_is_empty() {
+        let a = v4s(&["10.0.0.0/24", "192.168.0.0/16"]);
+        assert_eq!(a.clone().symmetric_differenced(&a), vec![]);
+    }
+
+    #[test]
+    fn symmetric_difference_partial_overlap() {
+        // a = [10.0.0.0, 10.0.1.255], b = [10.0.1.0, 10.0.2.255]
+        let a = v4s(&["10.0.0.0/23"]);
+        let b = vec![Ipv4Range::from_cidr_pair_decimal((0x0a000100, 0x0a0002ff))];
+        assert_eq!(
+            a.symmetric_differenced(&b),
+            v4s(&["10.0.0.0/24", "10.0.2.0/24"])
+        );
+    }
+
+    #[test]
+    fn symmetric_difference_adjacent_merges() {
+        let a = v4s(&["10.0.0.0/24"]);
+        let b = v4s(&["10.0.1.0/24"]);
+        assert_eq!(a.symmetric_differenced(&b), v4s(&["10.0.0.0/23"]));
+    }
+
+    #[test]
+    fn symmetric_difference_with_full_is_complement() {
+        let a = v4s(&["10.0.0.0/8", "255.255.255.255/32"]);
+        assert_eq!(
+            a.clone().symmetric_differenced(&[Ipv4Range::full()]),
+            a.reversed()
+        );
+    }
+
+    #[test]
+    fn symmetric_difference_ipv6() {
+        let a = v6s(&["2001:db8::/32"]);
+        let b = v6s(&["2001:db8:8000::/33", "fc00::/7"]);
+        assert_eq!(
+            a.symmetric_differenced(&b),
+            v6s(&["2001:db8::/33", "fc00::/7"])
+        );
+    }
+
+    #[test]
+    fn symmetric_difference_in_place() {
+        let mut ranges = v4s(&["10.0.0.0/23"]);
+        ranges.symmetric_difference(&v4s(&["10.0.1.0/24", "10.0.2.0/24"]));
+        assert_eq!(ranges, v4s(&["10.0.0.0/24", "10.0.2.0/24"]));
+    }
+
     // ---- count_address ----
 
     #[test]
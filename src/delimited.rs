@@ -0,0 +1,447 @@
+//! Column-aware parsing of CSV, TSV and similar delimited feeds, such as
+//! MaxMind's `network,geoname_id,...` or Spamhaus DROP's `1.10.16.0/20 ; SBL256894`.
+//!
+//! One column holds the CIDR block, address or range and is parsed like a line
+//! of [`parser`](crate::parser) input. The other columns are kept as labels of
+//! the record for downstream grouping.
+//!
+//! Fields may be quoted with `"` as in RFC 4180, with `""` standing for a quote
+//! inside a quoted field. Quoted fields cannot span lines.
+
+use std::io::{self, BufRead};
+
+use crate::parser::{
+    parse_entry, InvalidEntry, LineReader, ParseCidrError, ParseOptions, RecordError,
+};
+use crate::EitherIpRange;
+
+/// The column holding the ranges.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Column {
+    /// 0-based index of the column.
+    Index(usize),
+    /// Name of the column in the header, compared case-insensitively.
+    Name(String),
+}
+
+/// Whether the first record is a header naming the columns.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Header {
+    /// Treat the first record as a header if the range column does not parse,
+    /// or if the column is given by name.
+    #[default]
+    Auto,
+    Present,
+    Absent,
+}
+
+/// Options for [`DelimitedReader`].
+///
+/// Of the nested [`ParseOptions`], comment markers only skip lines that start
+/// with a marker; text after a marker elsewhere stays part of its field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DelimitedOptions {
+    pub delimiter: char,
+    pub column: Column,
+    pub header: Header,
+    pub parse: ParseOptions,
+}
+
+impl Default for DelimitedOptions {
+    fn default() -> Self {
+        DelimitedOptions {
+            delimiter: ',',
+            column: Column::Index(0),
+            header: Header::Auto,
+            parse: ParseOptions::default(),
+        }
+    }
+}
+
+/// A successfully parsed record, as yielded by [`DelimitedReader`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Record {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    pub range: EitherIpRange,
+    /// The other fields of the record in their original order, unquoted and
+    /// with surrounding whitespace trimmed.
+    pub labels: Vec<String>,
+    /// The error tolerated to accept the record, if any.
+    pub warning: Option<ParseCidrError>,
+}
+
+/// Reads delimited records line by line from any [`BufRead`].
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one record. Blank lines, comment lines and records
+/// whose range column is empty are skipped. A record too short to have the
+/// range column is invalid with [`RecordError::MissingColumn`].
+///
+/// ```
+/// use cidr_aggregator::delimited::{Column, DelimitedOptions, DelimitedReader};
+///
+/// let input = "network,geoname_id,country\n1.0.0.0/24,2077456,AU\n1.0.1.0/24,1814991,CN\n";
+/// let options = DelimitedOptions {
+///     column: Column::Name("network".into()),
+///     ..Default::default()
+/// };
+/// let mut reader = DelimitedReader::new(input.as_bytes(), options);
+/// let record = reader.next().unwrap()?.unwrap();
+/// assert_eq!(record.range, "1.0.0.0/24".parse().unwrap());
+/// assert_eq!(record.labels, ["2077456", "AU"]);
+/// assert_eq!(reader.header().unwrap(), ["network", "geoname_id", "country"]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct DelimitedReader<R: BufRead> {
+    lines: LineReader<R>,
+    options: DelimitedOptions,
+    /// `None` until the first record has been read.
+    column: Option<usize>,
+    header: Option<Vec<String>>,
+}
+
+impl<R: BufRead> DelimitedReader<R> {
+    pub fn new(reader: R, options: DelimitedOptions) -> Self {
+        DelimitedReader {
+            lines: LineReader::new(reader),
+            options,
+            column: None,
+            header: None,
+        }
+    }
+
+    /// The column names, once a header has been read.
+    pub fn header(&self) -> Option<&[String]> {
+        self.header.as_deref()
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+
+    /// The line last read, with surrounding whitespace trimmed.
+    pub(crate) fn current_line(&self) -> &str {
+        self.lines.line().trim_start_matches('\u{feff}').trim()
+    }
+}
+
+impl<R: BufRead> Iterator for DelimitedReader<R> {
+    type Item = io::Result<Result<Record, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, line) = match self.lines.next_line()? {
+                Ok((line_number, line)) => {
+                    (line_number, line.trim_start_matches('\u{feff}').trim())
+                }
+                Err(e) => return Some(Err(e)),
+            };
+            if line.is_empty()
+                || self
+                    .options
+                    .parse
+                    .comment_markers
+                    .iter()
+                    .any(|marker| !marker.is_empty() && line.starts_with(marker.as_str()))
+            {
+                continue;
+            }
+            let mut fields = split_fields(line, self.options.delimiter);
+            let column = match self.column {
+                Some(column) => column,
+                None => match find_column(&self.options, &fields) {
+                    Ok((column, is_header)) => {
+                        self.column = Some(column);
+                        if is_header {
+                            self.header = Some(fields);
+                            continue;
+                        }
+                        column
+                    }
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            let invalid = |error| InvalidEntry {
+                line_number,
+                line: line.to_owned(),
+                error,
+            };
+            if column >= fields.len() {
+                return Some(Ok(Err(invalid(RecordError::MissingColumn))));
+            }
+            let field = fields.remove(column);
+            if field.is_empty() {
+                continue;
+            }
+            return Some(Ok(match parse_entry(&field, &self.options.parse) {
+                Ok((range, warning)) => Ok(Record {
+                    line_number,
+                    range,
+                    labels: fields,
+                    warning,
+                }),
+                Err(error) => Err(invalid(error.into())),
+            }));
+        }
+    }
+}
+
+/// Decide whether `fields`, the first record, is a header, and find the
+/// range column.
+fn find_column(options: &DelimitedOptions, fields: &[String]) -> io::Result<(usize, bool)> {
+    let is_header = match (&options.column, options.header) {
+        (_, Header::Present) => true,
+        (_, Header::Absent) => false,
+        (Column::Name(_), Header::Auto) => true,
+        (Column::Index(index), Header::Auto) => fields
+            .get(*index)
+            .is_some_and(|field| parse_entry(field, &options.parse).is_err()),
+    };
+    let column = match &options.column {
+        Column::Index(index) => *index,
+        Column::Name(name) => fields
+            .iter()
+            .position(|field| is_header && field.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("no column named `{}` in the header", name),
+                )
+            })?,
+    };
+    Ok((column, is_header))
+}
+
+/// Split a line into trimmed, unquoted fields.
+pub(crate) fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    // Whether the current field started with a quote, and if we are still
+    // inside that quote. Whitespace after the closing quote is dropped.
+    let mut quoted = false;
+    let mut in_quotes = false;
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c != '"' {
+                field.push(c);
+            } else if chars.peek() == Some(&'"') {
+                field.push(chars.next().unwrap());
+            } else {
+                in_quotes = false;
+            }
+        } else if c == delimiter {
+            fields.push(finish_field(&mut field, quoted));
+            quoted = false;
+        } else if c == '"' && field.trim().is_empty() && !quoted {
+            field.clear();
+            quoted = true;
+            in_quotes = true;
+        } else if !(quoted && c.is_whitespace()) {
+            field.push(c);
+        }
+    }
+    fields.push(finish_field(&mut field, quoted));
+    fields
+}
+
+fn finish_field(field: &mut String, quoted: bool) -> String {
+    let value = if quoted {
+        field.clone()
+    } else {
+        field.trim().to_owned()
+    };
+    field.clear();
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::HostBits;
+
+    fn read(input: &str, options: DelimitedOptions) -> Vec<Result<Record, InvalidEntry>> {
+        DelimitedReader::new(input.as_bytes(), options)
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    fn range(s: &str) -> EitherIpRange {
+        parse_entry(s, &ParseOptions::default()).unwrap().0
+    }
+
+    #[test]
+    fn split_plain_and_quoted() {
+        assert_eq!(split_fields("a, b ,c", ','), ["a", "b", "c"]);
+        assert_eq!(split_fields("a,,", ','), ["a", "", ""]);
+        assert_eq!(
+            split_fields(r#""10.0.0.0/8", "Acme, Inc." ,"say ""hi"" ""#, ','),
+            ["10.0.0.0/8", "Acme, Inc.", "say \"hi\" "]
+        );
+        assert_eq!(split_fields("a\tb c\t", '\t'), ["a", "b c", ""]);
+        // A quote inside an unquoted field is just a character
+        assert_eq!(split_fields("5\" disk,x", ','), ["5\" disk", "x"]);
+    }
+
+    #[test]
+    fn header_detected_from_unparsable_column() {
+        let records = read(
+            "network,geoname_id\n1.0.0.0/24,2077456\n\n1.0.1.0/24,1814991\n",
+            DelimitedOptions::default(),
+        );
+        assert_eq!(
+            records,
+            [
+                Ok(Record {
+                    line_number: 2,
+                    range: range("1.0.0.0/24"),
+                    labels: vec!["2077456".into()],
+                    warning: None,
+                }),
+                Ok(Record {
+                    line_number: 4,
+                    range: range("1.0.1.0/24"),
+                    labels: vec!["1814991".into()],
+                    warning: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_header_detected() {
+        let mut reader = DelimitedReader::new(
+            "AU,1.0.0.0/24\nCN,1.0.1.0/24\n".as_bytes(),
+            DelimitedOptions {
+                column: Column::Index(1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(reader.next().unwrap().unwrap().unwrap().labels, ["AU"]);
+        assert!(reader.header().is_none());
+        assert_eq!(reader.count(), 1);
+    }
+
+    #[test]
+    fn explicit_header_setting() {
+        let input = "10.0.0.0/8,a\n10.1.0.0/16,b\n";
+        let options = |header| DelimitedOptions {
+            header,
+            ..Default::default()
+        };
+        assert_eq!(read(input, options(Header::Present)).len(), 1);
+        assert_eq!(read(input, options(Header::Absent)).len(), 2);
+        // A broken first row is reported rather than taken as a header
+        let records = read("bad,a\n10.0.0.0/8,b", options(Header::Absent));
+        assert_eq!(records[0].as_ref().unwrap_err().line_number, 1);
+    }
+
+    #[test]
+    fn column_by_name() {
+        let input = "\u{feff}Country\t\"Network\"\r\nAU\t1.0.0.0/24\r\n";
+        let options = DelimitedOptions {
+            delimiter: '\t',
+            column: Column::Name("network".into()),
+            ..Default::default()
+        };
+        let records = read(input, options.clone());
+        assert_eq!(records[0].as_ref().unwrap().range, range("1.0.0.0/24"));
+
+        let missing = DelimitedOptions {
+            column: Column::Name("cidr".into()),
+            ..options
+        };
+        let error = DelimitedReader::new(input.as_bytes(), missing)
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn spamhaus_drop() {
+        let input = "; Spamhaus DROP List 2024/01/01\n\
+                     ; Last-Modified: Mon, 01 Jan 2024 00:00:00 GMT\n\
+                     1.10.16.0/20 ; SBL256894\n\
+                     1.19.0.0/16 ; SBL434604\n";
+        let options = DelimitedOptions {
+            delimiter: ';',
+            parse: ParseOptions {
+                comment_markers: vec![";".into()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let records = read(input, options);
+        assert_eq!(records.len(), 2);
+        let record = records[1].as_ref().unwrap();
+        assert_eq!(record.line_number, 4);
+        assert_eq!(record.range, range("1.19.0.0/16"));
+        assert_eq!(record.labels, ["SBL434604"]);
+    }
+
+    #[test]
+    fn invalid_and_short_records() {
+        let records = read(
+            "net,name\n10.0.0.0/8,a\n192.168.1.5/24,b\nfoo\n,empty\n",
+            DelimitedOptions {
+                column: Column::Index(0),
+                ..Default::default()
+            },
+        );
+        assert_eq!(records.len(), 3);
+        let invalid = records[1].as_ref().unwrap_err();
+        assert_eq!(invalid.line, "192.168.1.5/24,b");
+        assert_eq!(
+            invalid.error,
+            RecordError::Cidr(ParseCidrError::HostBitsSet { prefix_len: 24 })
+        );
+        assert_eq!(
+            records[2].as_ref().unwrap_err().error,
+            RecordError::Cidr(ParseCidrError::InvalidAddress)
+        );
+
+        let records = read(
+            "10.0.0.0/8,a\nfoo\n",
+            DelimitedOptions {
+                column: Column::Index(1),
+                header: Header::Absent,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            records[1].as_ref().unwrap_err().error,
+            RecordError::MissingColumn
+        );
+    }
+
+    #[test]
+    fn parse_options_apply_to_the_column() {
+        let options = DelimitedOptions {
+            parse: ParseOptions {
+                host_bits: HostBits::Truncate,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let records = read("192.168.1.5/24,home\n10.0.0.1 - 10.0.0.3,lab", options);
+        let record = records[0].as_ref().unwrap();
+        assert_eq!(record.range, range("192.168.1.0/24"));
+        assert_eq!(
+            record.warning,
+            Some(ParseCidrError::HostBitsSet { prefix_len: 24 })
+        );
+        assert_eq!(
+            records[1].as_ref().unwrap().range,
+            range("10.0.0.1-10.0.0.3")
+        );
+    }
+}
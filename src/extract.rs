@@ -0,0 +1,255 @@
+//! Find IP addresses and CIDR blocks embedded in free text, such as abuse
+//! reports, HTML pages, markdown tables or emails.
+//!
+//! Unlike [`parser`](crate::parser), which expects one entry per line, the
+//! scanner looks at every token of the input and silently skips anything that
+//! is not an address. A token is a run of hex digits, dots and colons,
+//! optionally followed by `/` and a prefix length or dotted mask. It is only
+//! taken when it is not glued to a surrounding word, so `v1.2.3.4` and
+//! `1.2.3.4.5` yield nothing, and version numbers (`1.2.3`) or times of day
+//! (`12:30:45`) never parse as addresses in the first place.
+
+use std::net::Ipv4Addr;
+
+use crate::parser::{parse_entry, ParseOptions};
+use crate::{EitherIpRange, Ipv4Range, Ipv6Range};
+
+/// Iterator over the ranges found in a text, created by [`extract_ranges`].
+#[derive(Debug, Clone)]
+pub struct Extract<'a> {
+    text: &'a str,
+    pos: usize,
+    options: ParseOptions,
+}
+
+/// Scan `text` for IP addresses and CIDR blocks.
+///
+/// `options` decides how blocks with host bits set are treated, as in
+/// [`parse_entry`]. IPv4 shorthand is never applied, since it would turn
+/// version numbers into networks.
+///
+/// Besides the punctuation around a token, a trailing sentence period or
+/// colon and a port number after an IPv4 address (`10.0.0.1:8080`) are
+/// ignored. The two ends of a hyphenated range are found as separate
+/// addresses.
+///
+/// ```
+/// use cidr_aggregator::extract::extract_ranges;
+/// use cidr_aggregator::parser::ParseOptions;
+///
+/// let text = "Blocked 192.0.2.1:443 and [2001:db8::/32]. Upgrade to 1.2.3 today.";
+/// let found: Vec<_> = extract_ranges(text, &ParseOptions::default()).collect();
+/// assert_eq!(found, ["192.0.2.1".parse()?, "2001:db8::/32".parse()?]);
+/// # Ok::<(), cidr_aggregator::ParseCidrError>(())
+/// ```
+pub fn extract_ranges<'a>(text: &'a str, options: &ParseOptions) -> Extract<'a> {
+    Extract {
+        text,
+        pos: 0,
+        options: ParseOptions {
+            ipv4_shorthand: false,
+            ..options.clone()
+        },
+    }
+}
+
+/// Scan `text` for IP addresses and CIDR blocks with the default options,
+/// splitting the results by address family.
+pub fn extract_cidrs(text: &str) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+    for range in extract_ranges(text, &ParseOptions::default()) {
+        match range {
+            EitherIpRange::V4(range) => v4.push(range),
+            EitherIpRange::V6(range) => v6.push(range),
+        }
+    }
+    (v4, v6)
+}
+
+impl Iterator for Extract<'_> {
+    type Item = EitherIpRange;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.text.as_bytes();
+        while self.pos < bytes.len() {
+            let start = self.pos;
+            if !is_address_byte(bytes[start]) {
+                self.pos += 1;
+                continue;
+            }
+            let mut end = start + run_len(&bytes[start..], is_address_byte);
+            let mut address_end = end;
+            if bytes.get(end) == Some(&b'/') {
+                let prefix_len = run_len(&bytes[end + 1..], |b| b.is_ascii_digit() || b == b'.');
+                if prefix_len > 0 && bytes[end + 1].is_ascii_digit() {
+                    end += 1 + prefix_len;
+                }
+            }
+            self.pos = end;
+
+            // Leave out a trailing sentence period or colon, but not the
+            // double colon that ends a compressed IPv6 address
+            if end == address_end {
+                while end > start && bytes[end - 1] == b'.' {
+                    end -= 1;
+                }
+                if bytes[start..end].ends_with(b":") && !bytes[start..end].ends_with(b"::") {
+                    end -= 1;
+                }
+                address_end = end;
+            } else {
+                while bytes[end - 1] == b'.' {
+                    end -= 1;
+                }
+            }
+            let glued = |b: Option<&u8>| b.is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_');
+            if (start > 0 && glued(bytes.get(start - 1))) || glued(bytes.get(self.pos)) {
+                continue;
+            }
+            if let Some(range) = self.parse_token(&self.text[start..end], address_end - start) {
+                return Some(range);
+            }
+        }
+        None
+    }
+}
+
+impl Extract<'_> {
+    fn parse_token(&self, token: &str, address_len: usize) -> Option<EitherIpRange> {
+        let address = &token[..address_len];
+        if !address.bytes().any(|b| b.is_ascii_digit()) {
+            // Words made of hex letters, like `add::` or `cafe:face::`
+            return None;
+        }
+        if let Ok((range, _)) = parse_entry(token, &self.options) {
+            return Some(range);
+        }
+        // An IPv4 address followed by a port number
+        let (host, port) = address.split_once(':')?;
+        if token.len() == address_len
+            && !port.is_empty()
+            && port.bytes().all(|b| b.is_ascii_digit())
+            && host.parse::<Ipv4Addr>().is_ok()
+        {
+            parse_entry(host, &self.options)
+                .ok()
+                .map(|(range, _)| range)
+        } else {
+            None
+        }
+    }
+}
+
+fn is_address_byte(b: u8) -> bool {
+    b.is_ascii_hexdigit() || b == b'.' || b == b':'
+}
+
+fn run_len(bytes: &[u8], pred: impl Fn(u8) -> bool) -> usize {
+    bytes.iter().take_while(|&&b| pred(b)).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::HostBits;
+
+    fn extract(text: &str) -> Vec<EitherIpRange> {
+        extract_ranges(text, &ParseOptions::default()).collect()
+    }
+
+    fn ranges(cidrs: &[&str]) -> Vec<EitherIpRange> {
+        cidrs.iter().map(|cidr| cidr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn plain_addresses_and_blocks() {
+        assert_eq!(
+            extract("10.0.0.1 192.168.0.0/16\n2001:db8::1 fe80::/10"),
+            ranges(&[
+                "10.0.0.1/32",
+                "192.168.0.0/16",
+                "2001:db8::1/128",
+                "fe80::/10"
+            ])
+        );
+    }
+
+    #[test]
+    fn surrounding_punctuation() {
+        assert_eq!(
+            extract("(10.0.0.1), \"10.0.0.2\"; <td>10.0.0.3</td> | 10.0.0.0/8 |"),
+            ranges(&["10.0.0.1/32", "10.0.0.2/32", "10.0.0.3/32", "10.0.0.0/8"])
+        );
+        assert_eq!(
+            extract("Seen from 198.51.100.7. Also 2001:db8::. And 198.51.100.0/24."),
+            ranges(&["198.51.100.7/32", "2001:db8::/128", "198.51.100.0/24"])
+        );
+        assert_eq!(
+            extract("Source: 10.0.0.1: refused"),
+            ranges(&["10.0.0.1/32"])
+        );
+        assert_eq!(extract("fe80::1%eth0"), ranges(&["fe80::1/128"]));
+    }
+
+    #[test]
+    fn urls_and_ports() {
+        assert_eq!(
+            extract("http://192.0.2.10/index.html and https://[2001:db8::5]:8443/"),
+            ranges(&["192.0.2.10/32", "2001:db8::5/128"])
+        );
+        assert_eq!(
+            extract("connect 192.0.2.10:8080"),
+            ranges(&["192.0.2.10/32"])
+        );
+        assert!(extract("connect 192.0.2.10:http").is_empty());
+    }
+
+    #[test]
+    fn dotted_mask() {
+        assert_eq!(
+            extract("net 10.1.0.0/255.255.0.0."),
+            ranges(&["10.1.0.0/16"])
+        );
+    }
+
+    #[test]
+    fn versions_and_timestamps() {
+        assert!(extract("version 1.2.3, v1.2.3.4, 1.2.3.4.5, 10.0.0.256").is_empty());
+        assert!(extract("2024-01-15T12:30:45.123Z at 12:30:45 or 12:30").is_empty());
+        assert!(extract("2024.01.15 and 00:1a:2b:3c:4d:5e").is_empty());
+    }
+
+    #[test]
+    fn words_are_not_addresses() {
+        assert!(extract("add:: cafe:face:: std::vec deadbeef ::").is_empty());
+        assert!(extract("ab10.0.0.1 10.0.0.1x 10.0.0.1_ 10.0.0.0/24abc").is_empty());
+    }
+
+    #[test]
+    fn ranges_yield_both_ends() {
+        assert_eq!(
+            extract("10.0.0.1-10.0.0.9"),
+            ranges(&["10.0.0.1/32", "10.0.0.9/32"])
+        );
+    }
+
+    #[test]
+    fn host_bits_follow_options() {
+        assert!(extract("192.168.1.5/24").is_empty());
+        let options = ParseOptions {
+            host_bits: HostBits::Truncate,
+            ipv4_shorthand: true,
+            ..ParseOptions::default()
+        };
+        let found: Vec<_> = extract_ranges("192.168.1.5/24 and 10.1", &options).collect();
+        assert_eq!(found, ranges(&["192.168.1.0/24"]));
+    }
+
+    #[test]
+    fn split_by_family() {
+        let (v4, v6) = extract_cidrs("a 10.0.0.0/8 b ::1 c 192.0.2.1");
+        assert_eq!(v4.len(), 2);
+        assert_eq!(v6.len(), 1);
+    }
+}
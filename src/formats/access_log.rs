@@ -0,0 +1,397 @@
+//! Client addresses from web server access logs: the Common and Combined Log
+//! Formats of Apache and nginx, Apache's `vhost_combined`, and, with the
+//! `log-json` feature, JSON lines with the address in a configurable field.
+//!
+//! Hits are counted per address, so that a [`HitFilter`] can keep the busy
+//! clients only and widen each to its surrounding network:
+//!
+//! ```
+//! use cidr_aggregator::aggregator::Aggregator;
+//! use cidr_aggregator::formats::access_log::{AccessLogReader, HitCounts, HitFilter, LogFormat};
+//!
+//! let log = r#"192.0.2.7 - - [01/Jan/2024:00:00:00 +0000] "GET /wp-login.php HTTP/1.1" 404 153 "-" "curl/8.0"
+//! 192.0.2.9 - - [01/Jan/2024:00:00:01 +0000] "GET /wp-login.php HTTP/1.1" 404 153 "-" "curl/8.0"
+//! 192.0.2.9 - - [01/Jan/2024:00:00:02 +0000] "GET /xmlrpc.php HTTP/1.1" 404 153 "-" "curl/8.0"
+//! 198.51.100.1 - - [01/Jan/2024:00:00:03 +0000] "GET / HTTP/1.1" 200 612 "-" "Mozilla/5.0"
+//! "#;
+//! let mut hits = HitCounts::default();
+//! for hit in AccessLogReader::new(log.as_bytes(), LogFormat::Combined) {
+//!     hits.insert(hit?.unwrap().client);
+//! }
+//! assert_eq!(hits.get("192.0.2.9".parse()?), 2);
+//!
+//! let filter = HitFilter {
+//!     min_hits: 2,
+//!     ipv4_prefix_len: Some(24),
+//!     ..Default::default()
+//! };
+//! let (v4, v6) = filter.collect(&hits);
+//! assert_eq!(v4.normalized().export(), "192.0.2.0/24");
+//! assert!(v6.is_empty());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use crate::formats::split_by_family;
+use crate::parser::{InvalidEntry, LineReader, ParseCidrError, RecordError};
+use crate::{IpRange, Ipv4Range, Ipv6Range};
+
+/// Where a line of the log has the client address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum LogFormat {
+    /// The first field, as in the Common and Combined Log Formats (`%h` /
+    /// `$remote_addr`).
+    #[default]
+    Combined,
+    /// The second field, after the `host:port` of Apache's `vhost_combined`.
+    VhostCombined,
+    /// A field of a JSON object per line, named by a dot-separated path such
+    /// as `remote_addr` or `http.client.ip`.
+    #[cfg(feature = "log-json")]
+    Json(String),
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    /// Parse `common`, `combined` or `vhost_combined`. JSON formats name a
+    /// field instead, so they have no name here; the error points to the
+    /// `--log-field` option of the command line tool for them.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "common" | "combined" => Ok(LogFormat::Combined),
+            "vhost_combined" => Ok(LogFormat::VhostCombined),
+            _ => Err(
+                "expected common, combined or vhost_combined; for JSON lines, name the client address field with --log-field instead"
+                    .to_owned(),
+            ),
+        }
+    }
+}
+
+/// A request of a client, as found on a line of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hit {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    /// The client address; IPv4-mapped IPv6 addresses are taken as IPv4.
+    pub client: IpAddr,
+}
+
+/// Reads the client address of each request line by line.
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one line. Blank lines are skipped. Lines whose client
+/// is not an IP address, e.g. a host name logged with `HostnameLookups On`, are
+/// invalid with [`ParseCidrError::InvalidAddress`]; JSON lines lacking the
+/// field with [`RecordError::MissingColumn`].
+#[derive(Debug)]
+pub struct AccessLogReader<R: BufRead> {
+    lines: LineReader<R>,
+    format: LogFormat,
+}
+
+impl<R: BufRead> AccessLogReader<R> {
+    pub fn new(reader: R, format: LogFormat) -> Self {
+        AccessLogReader {
+            // Request lines and user agents are whatever clients send, so
+            // need not be UTF-8
+            lines: LineReader::lossy(reader),
+            format,
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl<R: BufRead> Iterator for AccessLogReader<R> {
+    type Item = io::Result<Result<Hit, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, line) = match self.lines.next_line()? {
+                Ok((line_number, line)) => (line_number, line.trim()),
+                Err(e) => return Some(Err(e)),
+            };
+            if line.is_empty() {
+                continue;
+            }
+            return Some(Ok(client_of(line, &self.format)
+                .map(|client| Hit {
+                    line_number,
+                    client,
+                })
+                .map_err(|error| InvalidEntry {
+                    line_number,
+                    line: line.to_owned(),
+                    error,
+                })));
+        }
+    }
+}
+
+fn client_of(line: &str, format: &LogFormat) -> Result<IpAddr, RecordError> {
+    let field = match format {
+        LogFormat::Combined => line.split_whitespace().next(),
+        LogFormat::VhostCombined => line.split_whitespace().nth(1),
+        #[cfg(feature = "log-json")]
+        LogFormat::Json(path) => {
+            let value: serde_json::Value =
+                serde_json::from_str(line).map_err(|_| RecordError::Malformed)?;
+            let value = path
+                .split('.')
+                .try_fold(&value, |value, key| value.get(key))
+                .and_then(serde_json::Value::as_str)
+                .ok_or(RecordError::MissingColumn)?;
+            return Ok(parse_client(value)?);
+        }
+    };
+    Ok(parse_client(field.ok_or(RecordError::MissingColumn)?)?)
+}
+
+/// Parse a client address as servers and proxies log it: possibly with a
+/// port (`192.0.2.1:51234`, `[2001:db8::1]:443`) or as the first of an
+/// `X-Forwarded-For` list.
+pub fn parse_client(s: &str) -> Result<IpAddr, ParseCidrError> {
+    let s = s.split(',').next().unwrap_or_default().trim();
+    let ip = s
+        .parse::<IpAddr>()
+        .or_else(|_| s.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .map_err(|_| ParseCidrError::InvalidAddress)?;
+    Ok(match ip {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+        ip => ip,
+    })
+}
+
+/// The number of hits of each client address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HitCounts {
+    counts: HashMap<IpAddr, u64>,
+}
+
+impl HitCounts {
+    pub fn insert(&mut self, client: IpAddr) {
+        *self.counts.entry(client).or_default() += 1;
+    }
+
+    /// The number of hits of `client`, `0` if it was never seen.
+    pub fn get(&self, client: IpAddr) -> u64 {
+        self.counts.get(&client).copied().unwrap_or_default()
+    }
+
+    /// Number of distinct clients.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (IpAddr, u64)> + '_ {
+        self.counts.iter().map(|(&client, &hits)| (client, hits))
+    }
+}
+
+impl Extend<IpAddr> for HitCounts {
+    fn extend<I: IntoIterator<Item = IpAddr>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|client| self.insert(client));
+    }
+}
+
+impl FromIterator<IpAddr> for HitCounts {
+    fn from_iter<I: IntoIterator<Item = IpAddr>>(iter: I) -> Self {
+        let mut counts = HitCounts::default();
+        counts.extend(iter);
+        counts
+    }
+}
+
+/// Selects the clients with at least `min_hits` hits, and widens each to the
+/// network of the given prefix length around it, if any.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HitFilter {
+    pub min_hits: u64,
+    /// E.g. `24` to take the whole /24 of a busy IPv4 client.
+    pub ipv4_prefix_len: Option<u8>,
+    /// E.g. `64` to take the whole /64 of a busy IPv6 client.
+    pub ipv6_prefix_len: Option<u8>,
+}
+
+impl HitFilter {
+    pub fn matches(&self, hits: u64) -> bool {
+        hits >= self.min_hits
+    }
+
+    /// The selected clients, widened, split by address family and aggregated.
+    pub fn collect(&self, counts: &HitCounts) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+        split_by_family(
+            counts
+                .iter()
+                .filter(|&(_, hits)| self.matches(hits))
+                .map(|(client, _)| match client {
+                    IpAddr::V4(ip) => widen_v4(ip, self.ipv4_prefix_len).into(),
+                    IpAddr::V6(ip) => widen_v6(ip, self.ipv6_prefix_len).into(),
+                }),
+        )
+    }
+}
+
+/// The network of `prefix_len` around `ip`, or just `ip`; prefix lengths
+/// beyond the address width are taken as the full width.
+fn widen_v4(ip: Ipv4Addr, prefix_len: Option<u8>) -> Ipv4Range {
+    let host_bits = 32 - prefix_len.unwrap_or(32).min(32) as u32;
+    let hostmask = u32::MAX.checked_shr(32 - host_bits).unwrap_or(0);
+    let first = u32::from(ip) & !hostmask;
+    Ipv4Range::from_cidr_pair_decimal((first, first | hostmask))
+}
+
+fn widen_v6(ip: Ipv6Addr, prefix_len: Option<u8>) -> Ipv6Range {
+    let host_bits = 128 - prefix_len.unwrap_or(128).min(128) as u32;
+    let hostmask = u128::MAX.checked_shr(128 - host_bits).unwrap_or(0);
+    let first = u128::from(ip) & !hostmask;
+    Ipv6Range::from_cidr_pair_decimal((first, first | hostmask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::Aggregator;
+
+    fn read(input: &[u8], format: LogFormat) -> Vec<Result<Hit, InvalidEntry>> {
+        AccessLogReader::new(input, format)
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn log_formats() {
+        let combined = b"2001:db8::1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /\xff HTTP/1.0\" 200 2326\n\n::ffff:192.0.2.1 - - [10/Oct/2000:13:55:37 -0700] \"GET / HTTP/1.0\" 200 2326\n";
+        assert_eq!(
+            read(combined, LogFormat::Combined),
+            [
+                Ok(Hit {
+                    line_number: 1,
+                    client: ip("2001:db8::1"),
+                }),
+                Ok(Hit {
+                    line_number: 3,
+                    client: ip("192.0.2.1"),
+                }),
+            ]
+        );
+        let vhost = b"example.com:443 192.0.2.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326\n";
+        let format = "vhost-combined".parse().unwrap();
+        assert!("json"
+            .parse::<LogFormat>()
+            .unwrap_err()
+            .contains("--log-field"));
+        assert_eq!(
+            read(vhost, format)[0].as_ref().unwrap().client,
+            ip("192.0.2.1")
+        );
+        assert!(matches!(
+            &read(vhost, LogFormat::Combined)[0],
+            Err(entry) if entry.error == RecordError::Cidr(ParseCidrError::InvalidAddress)
+        ));
+    }
+
+    #[cfg(feature = "log-json")]
+    #[test]
+    fn json_lines() {
+        let input =
+            br#"{"remote_addr": "192.0.2.1:51234", "http": {"client": {"ip": "2001:db8::1"}}}
+{"http": {"client": {"ip": "198.51.100.7, 10.0.0.1"}}}
+{"http": {"client": {}}}
+not json
+"#;
+        let path = LogFormat::Json("http.client.ip".into());
+        let items = read(input, path);
+        assert_eq!(items[0].as_ref().unwrap().client, ip("2001:db8::1"));
+        assert_eq!(items[1].as_ref().unwrap().client, ip("198.51.100.7"));
+        let errors: Vec<_> = items[2..]
+            .iter()
+            .map(|item| item.as_ref().unwrap_err().error)
+            .collect();
+        assert_eq!(errors, [RecordError::MissingColumn, RecordError::Malformed]);
+        let items = read(input, LogFormat::Json("remote_addr".into()));
+        assert_eq!(items[0].as_ref().unwrap().client, ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn client_addresses() {
+        assert_eq!(parse_client("[2001:db8::1]:443"), Ok(ip("2001:db8::1")));
+        assert_eq!(parse_client("192.0.2.1:8080"), Ok(ip("192.0.2.1")));
+        assert_eq!(parse_client("-"), Err(ParseCidrError::InvalidAddress));
+        assert_eq!(
+            parse_client("crawler.example.com"),
+            Err(ParseCidrError::InvalidAddress)
+        );
+    }
+
+    #[test]
+    fn thresholds_and_widening() {
+        let counts: HitCounts = [
+            "192.0.2.1",
+            "192.0.2.1",
+            "192.0.2.200",
+            "192.0.2.200",
+            "198.51.100.1",
+            "2001:db8:0:1::1",
+            "2001:db8:0:1::1",
+            "2001:db8:0:2::1",
+            "2001:db8:0:2::1",
+        ]
+        .into_iter()
+        .map(ip)
+        .collect();
+        assert_eq!(counts.len(), 5);
+        assert_eq!(counts.get(ip("203.0.113.1")), 0);
+
+        let exact = HitFilter {
+            min_hits: 2,
+            ..Default::default()
+        };
+        let (v4, v6) = exact.collect(&counts);
+        assert_eq!(v4.normalized().export(), "192.0.2.1/32\n192.0.2.200/32");
+        assert_eq!(
+            v6.normalized().export(),
+            "2001:db8:0:1::1/128\n2001:db8:0:2::1/128"
+        );
+
+        let widened = HitFilter {
+            min_hits: 2,
+            ipv4_prefix_len: Some(24),
+            ipv6_prefix_len: Some(48),
+        };
+        let (v4, v6) = widened.collect(&counts);
+        assert_eq!(v4.normalized().export(), "192.0.2.0/24");
+        assert_eq!(v6.normalized().export(), "2001:db8::/48");
+
+        let everything = HitFilter {
+            min_hits: 0,
+            ipv4_prefix_len: Some(0),
+            ipv6_prefix_len: Some(200),
+        };
+        let (v4, v6) = everything.collect(&counts);
+        assert_eq!(v4, [Ipv4Range::full()]);
+        assert_eq!(v6.len(), 2);
+    }
+}
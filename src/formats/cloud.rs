@@ -0,0 +1,413 @@
+//! Published IP ranges of cloud providers: AWS `ip-ranges.json`, GCP
+//! `cloud.json` (or `goog.json`), Azure Service Tags and Cloudflare's
+//! `/client/v4/ips` API response.
+//!
+//! Each prefix keeps whatever the provider says about it, so it can be narrowed
+//! down with a [`CloudFilter`] before being collected into ranges:
+//!
+//! ```
+//! use cidr_aggregator::formats::cloud::{parse_cloud_ranges, CloudFilter};
+//!
+//! let json = r#"{
+//!     "syncToken": "1700000000",
+//!     "prefixes": [
+//!         { "ip_prefix": "3.5.140.0/22", "region": "ap-northeast-2", "service": "S3" },
+//!         { "ip_prefix": "13.34.37.64/27", "region": "ap-southeast-4", "service": "EC2" }
+//!     ],
+//!     "ipv6_prefixes": []
+//! }"#;
+//! let prefixes = parse_cloud_ranges(json)?;
+//! let filter = CloudFilter {
+//!     services: vec!["s3".into()],
+//!     ..Default::default()
+//! };
+//! let (v4, v6) = filter.collect(&prefixes);
+//! assert_eq!(v4, ["3.5.140.0/22".parse::<cidr_aggregator::EitherIpRange>()?.into_v4().unwrap()]);
+//! assert!(v6.is_empty());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::formats::{any_of, split_by_family};
+use crate::parser::ParseCidrError;
+use crate::{EitherIpRange, Ipv4Range, Ipv6Range};
+
+/// A prefix from a provider's list, with the metadata attached to it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CloudPrefix {
+    pub range: EitherIpRange,
+    /// AWS `service`, GCP `service` or Azure `systemService`.
+    pub service: Option<String>,
+    /// AWS `region`, GCP `scope` or Azure `region`.
+    pub region: Option<String>,
+    /// Azure service tag name, e.g. `Storage.WestUS`.
+    pub tag: Option<String>,
+}
+
+/// Why a provider's file could not be read.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CloudFormatError {
+    /// The file is not valid JSON, or does not follow the provider's schema.
+    Json(serde_json::Error),
+    /// The JSON does not look like any of the supported providers' files.
+    UnknownFormat,
+    /// A listed prefix is not a valid CIDR block.
+    InvalidPrefix {
+        prefix: String,
+        error: ParseCidrError,
+    },
+}
+
+impl fmt::Display for CloudFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CloudFormatError::Json(error) => write!(f, "malformed JSON: {}", error),
+            CloudFormatError::UnknownFormat => {
+                write!(f, "not an AWS, GCP, Azure or Cloudflare IP range file")
+            }
+            CloudFormatError::InvalidPrefix { prefix, error } => {
+                write!(f, "invalid prefix {}: {}", prefix, error)
+            }
+        }
+    }
+}
+
+impl Error for CloudFormatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CloudFormatError::Json(error) => Some(error),
+            CloudFormatError::InvalidPrefix { error, .. } => Some(error),
+            CloudFormatError::UnknownFormat => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for CloudFormatError {
+    fn from(error: serde_json::Error) -> Self {
+        CloudFormatError::Json(error)
+    }
+}
+
+/// Selects prefixes by their metadata.
+///
+/// Each non-empty list must contain the corresponding field of a prefix,
+/// compared case-insensitively; prefixes without that field never match. Empty
+/// lists match everything.
+///
+/// Note that AWS lists every prefix under the `AMAZON` service in addition to
+/// the more specific service, if any.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CloudFilter {
+    pub services: Vec<String>,
+    pub regions: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl CloudFilter {
+    pub fn matches(&self, prefix: &CloudPrefix) -> bool {
+        any_of(&self.services, prefix.service.as_deref())
+            && any_of(&self.regions, prefix.region.as_deref())
+            && any_of(&self.tags, prefix.tag.as_deref())
+    }
+
+    /// Keep the matching prefixes, split them by address family and
+    /// aggregate.
+    pub fn collect<'a, I: IntoIterator<Item = &'a CloudPrefix>>(
+        &self,
+        prefixes: I,
+    ) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+        split_by_family(
+            prefixes
+                .into_iter()
+                .filter(|prefix| self.matches(prefix))
+                .map(|prefix| prefix.range),
+        )
+    }
+}
+
+/// Parse any of the supported files, telling the provider by the shape of
+/// the JSON.
+pub fn parse_cloud_ranges(json: &str) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    let value: Value = serde_json::from_str(json)?;
+    let is_aws = |prefixes: &Value| {
+        prefixes
+            .get(0)
+            .map_or(value.get("ipv6_prefixes").is_some(), |prefix| {
+                prefix.get("ip_prefix").is_some()
+            })
+    };
+    if value.get("prefixes").is_some_and(is_aws) {
+        from_aws(serde_json::from_value(value)?)
+    } else if value.get("prefixes").is_some() {
+        from_gcp(serde_json::from_value(value)?)
+    } else if value.get("values").is_some() {
+        from_azure(serde_json::from_value(value)?)
+    } else if value.get("result").is_some() {
+        from_cloudflare(serde_json::from_value(value)?)
+    } else {
+        Err(CloudFormatError::UnknownFormat)
+    }
+}
+
+/// Parse AWS `ip-ranges.json`.
+pub fn parse_aws_ip_ranges(json: &str) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    from_aws(serde_json::from_str(json)?)
+}
+
+/// Parse GCP `cloud.json` or `goog.json`.
+pub fn parse_gcp_ip_ranges(json: &str) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    from_gcp(serde_json::from_str(json)?)
+}
+
+/// Parse an Azure Service Tags file, e.g. `ServiceTags_Public_20240101.json`.
+pub fn parse_azure_service_tags(json: &str) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    from_azure(serde_json::from_str(json)?)
+}
+
+/// Parse the response of Cloudflare's `/client/v4/ips` API.
+pub fn parse_cloudflare_ips(json: &str) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    from_cloudflare(serde_json::from_str(json)?)
+}
+
+#[derive(Deserialize)]
+struct AwsIpRanges {
+    #[serde(default)]
+    prefixes: Vec<AwsPrefix>,
+    #[serde(default)]
+    ipv6_prefixes: Vec<AwsPrefix>,
+}
+
+#[derive(Deserialize)]
+struct AwsPrefix {
+    #[serde(alias = "ipv6_prefix")]
+    ip_prefix: String,
+    region: Option<String>,
+    service: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GcpIpRanges {
+    prefixes: Vec<GcpPrefix>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcpPrefix {
+    ipv4_prefix: Option<String>,
+    ipv6_prefix: Option<String>,
+    service: Option<String>,
+    scope: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AzureServiceTags {
+    values: Vec<AzureServiceTag>,
+}
+
+#[derive(Deserialize)]
+struct AzureServiceTag {
+    name: String,
+    properties: AzureProperties,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureProperties {
+    #[serde(default)]
+    region: String,
+    #[serde(default)]
+    system_service: String,
+    address_prefixes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CloudflareResponse {
+    result: CloudflareIps,
+}
+
+#[derive(Deserialize)]
+struct CloudflareIps {
+    #[serde(default)]
+    ipv4_cidrs: Vec<String>,
+    #[serde(default)]
+    ipv6_cidrs: Vec<String>,
+}
+
+fn parse_prefix(prefix: &str) -> Result<EitherIpRange, CloudFormatError> {
+    prefix
+        .parse()
+        .map_err(|error| CloudFormatError::InvalidPrefix {
+            prefix: prefix.to_owned(),
+            error,
+        })
+}
+
+/// Providers leave out fields by giving an empty string as well.
+fn non_empty(s: Option<String>) -> Option<String> {
+    s.filter(|s| !s.is_empty())
+}
+
+fn from_aws(ranges: AwsIpRanges) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    ranges
+        .prefixes
+        .into_iter()
+        .chain(ranges.ipv6_prefixes)
+        .map(|prefix| {
+            Ok(CloudPrefix {
+                range: parse_prefix(&prefix.ip_prefix)?,
+                service: non_empty(prefix.service),
+                region: non_empty(prefix.region),
+                tag: None,
+            })
+        })
+        .collect()
+}
+
+fn from_gcp(ranges: GcpIpRanges) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    ranges
+        .prefixes
+        .into_iter()
+        .map(|prefix| {
+            let cidr = prefix
+                .ipv4_prefix
+                .or(prefix.ipv6_prefix)
+                .unwrap_or_default();
+            Ok(CloudPrefix {
+                range: parse_prefix(&cidr)?,
+                service: non_empty(prefix.service),
+                region: non_empty(prefix.scope),
+                tag: None,
+            })
+        })
+        .collect()
+}
+
+fn from_azure(tags: AzureServiceTags) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    let mut prefixes = Vec::new();
+    for tag in tags.values {
+        let properties = tag.properties;
+        for cidr in &properties.address_prefixes {
+            prefixes.push(CloudPrefix {
+                range: parse_prefix(cidr)?,
+                service: non_empty(Some(properties.system_service.clone())),
+                region: non_empty(Some(properties.region.clone())),
+                tag: Some(tag.name.clone()),
+            });
+        }
+    }
+    Ok(prefixes)
+}
+
+fn from_cloudflare(response: CloudflareResponse) -> Result<Vec<CloudPrefix>, CloudFormatError> {
+    let ips = response.result;
+    ips.ipv4_cidrs
+        .iter()
+        .chain(&ips.ipv6_cidrs)
+        .map(|cidr| {
+            Ok(CloudPrefix {
+                range: parse_prefix(cidr)?,
+                service: None,
+                region: None,
+                tag: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> EitherIpRange {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn aws_both_families() {
+        let json = r#"{
+            "prefixes": [{ "ip_prefix": "3.5.140.0/22", "region": "ap-northeast-2",
+                           "service": "AMAZON", "network_border_group": "ap-northeast-2" }],
+            "ipv6_prefixes": [{ "ipv6_prefix": "2600:1f14::/35", "region": "us-west-2",
+                                "service": "EC2", "network_border_group": "us-west-2" }]
+        }"#;
+        let prefixes = parse_aws_ip_ranges(json).unwrap();
+        assert_eq!(prefixes, parse_cloud_ranges(json).unwrap());
+        assert_eq!(
+            prefixes[1],
+            CloudPrefix {
+                range: range("2600:1f14::/35"),
+                service: Some("EC2".into()),
+                region: Some("us-west-2".into()),
+                tag: None,
+            }
+        );
+    }
+
+    #[test]
+    fn gcp_goog_json_without_metadata() {
+        let json = r#"{ "prefixes": [{ "ipv4Prefix": "8.8.4.0/24" }, { "ipv6Prefix": "2001:4860::/32" }] }"#;
+        let prefixes = parse_cloud_ranges(json).unwrap();
+        assert_eq!(prefixes.len(), 2);
+        assert_eq!(prefixes[1].range, range("2001:4860::/32"));
+        assert_eq!(prefixes[1].service, None);
+    }
+
+    #[test]
+    fn azure_empty_fields() {
+        let json = r#"{ "values": [{ "name": "AzureCloud", "id": "AzureCloud",
+            "properties": { "region": "", "systemService": "", "addressPrefixes": ["13.64.0.0/16"] } }] }"#;
+        let prefixes = parse_cloud_ranges(json).unwrap();
+        assert_eq!(prefixes[0].tag.as_deref(), Some("AzureCloud"));
+        assert_eq!(prefixes[0].region, None);
+        assert_eq!(prefixes[0].service, None);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            parse_cloud_ranges("{ \"foo\": 1 }"),
+            Err(CloudFormatError::UnknownFormat)
+        ));
+        assert!(matches!(
+            parse_cloud_ranges("[1, 2"),
+            Err(CloudFormatError::Json(_))
+        ));
+        assert!(matches!(
+            parse_aws_ip_ranges(r#"{ "prefixes": [{ "ip_prefix": "3.5.140.1/22" }] }"#),
+            Err(CloudFormatError::InvalidPrefix {
+                error: ParseCidrError::HostBitsSet { prefix_len: 22 },
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_gcp_ip_ranges(r#"{ "prefixes": [{ "service": "Google Cloud" }] }"#),
+            Err(CloudFormatError::InvalidPrefix { .. })
+        ));
+    }
+
+    #[test]
+    fn filter_matching() {
+        let prefix = CloudPrefix {
+            range: range("10.0.0.0/8"),
+            service: Some("EC2".into()),
+            region: Some("us-east-1".into()),
+            tag: None,
+        };
+        assert!(CloudFilter::default().matches(&prefix));
+        let filter = |services: &[&str], regions: &[&str], tags: &[&str]| CloudFilter {
+            services: services.iter().map(|&s| s.into()).collect(),
+            regions: regions.iter().map(|&s| s.into()).collect(),
+            tags: tags.iter().map(|&s| s.into()).collect(),
+        };
+        assert!(filter(&["s3", "ec2"], &[], &[]).matches(&prefix));
+        assert!(filter(&["ec2"], &["US-EAST-1"], &[]).matches(&prefix));
+        assert!(!filter(&["ec2"], &["us-west-2"], &[]).matches(&prefix));
+        assert!(!filter(&[], &[], &["AzureCloud"]).matches(&prefix));
+    }
+}
@@ -0,0 +1,992 @@
+//! Firewall state dumps: `iptables-save` / `ip6tables-save` rules, `ipset
+//! save` entries and the set elements of `nft list ruleset`.
+//!
+//! Each reader yields what it finds line by line; a filter then collects the
+//! ranges per chain or set, aggregated, ready for diffing with the
+//! [`Aggregator`] methods:
+//!
+//! ```
+//! use cidr_aggregator::aggregator::Aggregator;
+//! use cidr_aggregator::formats::firewall::{
+//!     Direction, IpsetFilter, IpsetReader, IptablesChain, IptablesFilter, IptablesReader,
+//! };
+//!
+//! let rules = "\
+//! *filter
+//! :INPUT DROP [0:0]
+//! -A INPUT -s 10.0.0.0/9 -j ACCEPT
+//! -A INPUT -s 10.128.0.0/9 -p tcp -m tcp --dport 22 -j ACCEPT
+//! -A INPUT -s 192.0.2.0/24 -j DROP
+//! COMMIT
+//! ";
+//! let rules = IptablesReader::new(rules.as_bytes())
+//!     .map(|rule| rule.map(Result::unwrap))
+//!     .collect::<std::io::Result<Vec<_>>>()?;
+//! let accepted = IptablesFilter {
+//!     targets: vec!["ACCEPT".into()],
+//!     ..Default::default()
+//! }
+//! .collect(rules, Direction::Source);
+//! let input = IptablesChain { table: "filter".into(), chain: "INPUT".into() };
+//! let (allowed, _) = &accepted[&input];
+//! assert_eq!(allowed.clone().normalized().export(), "10.0.0.0/8");
+//!
+//! let sets = "\
+//! create trusted hash:net family inet hashsize 1024 maxelem 65536
+//! add trusted 10.0.0.0/16
+//! add trusted 172.16.0.0/12
+//! ";
+//! let entries = IpsetReader::new(sets.as_bytes())
+//!     .map(|entry| entry.map(Result::unwrap))
+//!     .collect::<std::io::Result<Vec<_>>>()?;
+//! let (trusted, _) = &IpsetFilter::default().collect(entries)["trusted"];
+//! // What the trusted set lets in that the rules don't
+//! let extra = trusted.clone().differenced(allowed);
+//! assert_eq!(extra.normalized().export(), "172.16.0.0/12");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead};
+
+use crate::aggregator::Aggregator;
+use crate::formats::{any_of, split_by_family};
+use crate::parser::{
+    parse_entry, InvalidEntry, LineReader, ParseCidrError, ParseOptions, RecordError,
+};
+use crate::{EitherIpRange, IpRange, Ipv4Range, Ipv6Range};
+
+/// Ranges collected per chain or set, keyed by what names it: a set name for
+/// ipset, an [`IptablesChain`] or an [`NftSet`].
+pub type RangesByName<K = String> = BTreeMap<K, (Vec<Ipv4Range>, Vec<Ipv6Range>)>;
+
+/// A chain of a table, as chain names are only unique within a table.
+///
+/// Displayed as `filter INPUT`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IptablesChain {
+    pub table: String,
+    pub chain: String,
+}
+
+impl fmt::Display for IptablesChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.table, self.chain)
+    }
+}
+
+/// A named set of a table, as set names are only unique within a table and
+/// table names within a family.
+///
+/// Displayed as nft names it, e.g. `inet filter blocklist`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NftSet {
+    pub family: String,
+    pub table: String,
+    pub set: String,
+}
+
+impl fmt::Display for NftSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.family, self.table, self.set)
+    }
+}
+
+/// Which address of a packet a rule matches on.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// `-s` / `--src-range`.
+    #[default]
+    Source,
+    /// `-d` / `--dst-range`.
+    Destination,
+}
+
+/// The addresses given to `-s` or `-d` of a rule, possibly negated with `!`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AddressMatch {
+    pub ranges: Vec<EitherIpRange>,
+    pub negated: bool,
+}
+
+impl AddressMatch {
+    /// The addresses the rule matches, split by address family and
+    /// aggregated. A negated match covers the rest of the address families
+    /// it names.
+    pub fn split(&self) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+        let (v4, v6) = split_by_family(self.ranges.iter().copied());
+        if !self.negated {
+            return (v4, v6);
+        }
+        let v4 = if v4.is_empty() { v4 } else { v4.reversed() };
+        let v6 = if v6.is_empty() { v6 } else { v6.reversed() };
+        (v4, v6)
+    }
+}
+
+/// An `-A` rule of `iptables-save` or `ip6tables-save` output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IptablesRule {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    /// The table from the last `*table` line, e.g. `filter` or `nat`.
+    pub table: String,
+    pub chain: String,
+    pub source: Option<AddressMatch>,
+    pub destination: Option<AddressMatch>,
+    /// What `-j` jumps to or `-g` goes to, e.g. `ACCEPT`, `DROP` or a chain.
+    pub target: Option<String>,
+}
+
+impl IptablesRule {
+    pub fn address(&self, direction: Direction) -> Option<&AddressMatch> {
+        match direction {
+            Direction::Source => self.source.as_ref(),
+            Direction::Destination => self.destination.as_ref(),
+        }
+    }
+}
+
+/// Selects rules by table, chain and target.
+///
+/// Each non-empty list must contain the corresponding field of a rule,
+/// compared case-insensitively. Empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct IptablesFilter {
+    pub tables: Vec<String>,
+    pub chains: Vec<String>,
+    pub targets: Vec<String>,
+}
+
+impl IptablesFilter {
+    pub fn matches(&self, rule: &IptablesRule) -> bool {
+        any_of(&self.tables, [rule.table.as_str()])
+            && any_of(&self.chains, [rule.chain.as_str()])
+            && any_of(&self.targets, rule.target.as_deref())
+    }
+
+    /// The addresses the matching rules match on in `direction`, per table
+    /// and chain.
+    ///
+    /// Rules that do not match on addresses in that direction apply to every
+    /// address, and are left out rather than swallowing everything else.
+    pub fn collect<I: IntoIterator<Item = IptablesRule>>(
+        &self,
+        rules: I,
+        direction: Direction,
+    ) -> RangesByName<IptablesChain> {
+        let mut chains = RangesByName::new();
+        for rule in rules.into_iter().filter(|rule| self.matches(rule)) {
+            if let Some(address) = rule.address(direction) {
+                let (v4, v6) = address.split();
+                let key = IptablesChain {
+                    table: rule.table,
+                    chain: rule.chain,
+                };
+                let (chain_v4, chain_v6) = chains.entry(key).or_default();
+                chain_v4.extend(v4);
+                chain_v6.extend(v6);
+            }
+        }
+        aggregate_all(chains)
+    }
+}
+
+/// Reads rules line by line from `iptables-save` or `ip6tables-save` output.
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one rule. Table headers, chain policies, `COMMIT` and
+/// comments are skipped. Rules with addresses that are not IP addresses or
+/// ranges, such as host names, are invalid.
+#[derive(Debug)]
+pub struct IptablesReader<R: BufRead> {
+    lines: LineReader<R>,
+    table: String,
+}
+
+impl<R: BufRead> IptablesReader<R> {
+    pub fn new(reader: R) -> Self {
+        IptablesReader {
+            lines: LineReader::new(reader),
+            table: "filter".to_owned(),
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl<R: BufRead> Iterator for IptablesReader<R> {
+    type Item = io::Result<Result<IptablesRule, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, line) = match self.lines.next_line()? {
+                Ok((line_number, line)) => (line_number, line.trim()),
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(table) = line.strip_prefix('*') {
+                self.table = table.to_owned();
+                continue;
+            }
+            if !line.starts_with("-A ") && !line.starts_with("-I ") {
+                continue;
+            }
+            return Some(Ok(parse_rule(line_number, &self.table, line).map_err(
+                |error| InvalidEntry {
+                    line_number,
+                    line: line.to_owned(),
+                    error,
+                },
+            )));
+        }
+    }
+}
+
+fn parse_rule(line_number: usize, table: &str, line: &str) -> Result<IptablesRule, RecordError> {
+    let tokens = split_words(line);
+    let mut tokens = tokens.iter().map(String::as_str).skip(1);
+    let chain = tokens.next().ok_or(RecordError::Malformed)?;
+    let mut rule = IptablesRule {
+        line_number,
+        table: table.to_owned(),
+        chain: chain.to_owned(),
+        source: None,
+        destination: None,
+        target: None,
+    };
+    let mut negated = false;
+    while let Some(token) = tokens.next() {
+        if token == "!" {
+            negated = true;
+            continue;
+        }
+        let (slot, is_range) = match token {
+            "-s" | "--source" | "--src" => (Some(&mut rule.source), false),
+            "--src-range" => (Some(&mut rule.source), true),
+            "-d" | "--destination" | "--dst" => (Some(&mut rule.destination), false),
+            "--dst-range" => (Some(&mut rule.destination), true),
+            "-j" | "--jump" | "-g" | "--goto" => {
+                rule.target = tokens.next().map(str::to_owned);
+                (None, false)
+            }
+            _ => (None, false),
+        };
+        if let Some(slot) = slot {
+            let value = tokens.next().ok_or(RecordError::Malformed)?;
+            let ranges = if is_range {
+                // `-m iprange` takes a single `first-last` range
+                vec![parse_range(value)?]
+            } else {
+                value.split(',').map(str::parse).collect::<Result<_, _>>()?
+            };
+            *slot = Some(AddressMatch { ranges, negated });
+        }
+        negated = false;
+    }
+    Ok(rule)
+}
+
+/// An `add` entry of `ipset save` output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IpsetEntry {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    pub set: String,
+    /// The first dimension of the entry, e.g. the network of `hash:net,port`.
+    pub range: EitherIpRange,
+    /// The entry carries the `nomatch` option, so it is an exception to the
+    /// set's wider entries rather than part of it.
+    pub nomatch: bool,
+}
+
+/// Selects ipset entries by set name.
+///
+/// A non-empty list must contain the set name, compared case-insensitively.
+/// An empty list matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct IpsetFilter {
+    pub sets: Vec<String>,
+}
+
+impl IpsetFilter {
+    pub fn matches(&self, entry: &IpsetEntry) -> bool {
+        any_of(&self.sets, [entry.set.as_str()])
+    }
+
+    /// The addresses in each matching set.
+    ///
+    /// As in the kernel, the most specific entry covering an address decides
+    /// whether it is in the set, so `nomatch` entries carve exceptions out of
+    /// wider entries, and narrower entries may take them back.
+    pub fn collect<I: IntoIterator<Item = IpsetEntry>>(&self, entries: I) -> RangesByName {
+        let mut sets = HashMap::<_, (Vec<_>, Vec<_>)>::new();
+        for entry in entries.into_iter().filter(|entry| self.matches(entry)) {
+            let (v4, v6) = sets.entry(entry.set).or_default();
+            match entry.range {
+                EitherIpRange::V4(range) => v4.push((range, entry.nomatch)),
+                EitherIpRange::V6(range) => v6.push((range, entry.nomatch)),
+            }
+        }
+        sets.into_iter()
+            .map(|(set, (v4, v6))| (set, (most_specific_wins(v4), most_specific_wins(v6))))
+            .collect()
+    }
+}
+
+/// Reads entries line by line from `ipset save` output.
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one `add` line. Other commands are skipped, and so
+/// are the entries of sets whose `create` line says they do not hold addresses
+/// first, such as `hash:mac` or `list:set`.
+#[derive(Debug)]
+pub struct IpsetReader<R: BufRead> {
+    lines: LineReader<R>,
+    /// Sets created with a type that does not start with an address.
+    skipped_sets: Vec<String>,
+}
+
+impl<R: BufRead> IpsetReader<R> {
+    pub fn new(reader: R) -> Self {
+        IpsetReader {
+            lines: LineReader::new(reader),
+            skipped_sets: Vec::new(),
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl<R: BufRead> Iterator for IpsetReader<R> {
+    type Item = io::Result<Result<IpsetEntry, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, line) = match self.lines.next_line()? {
+                Ok((line_number, line)) => (line_number, line.trim()),
+                Err(e) => return Some(Err(e)),
+            };
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("create" | "-N"), Some(set)) => {
+                    let kind = words.next().unwrap_or_default();
+                    let (_, dimensions) = kind.split_once(':').unwrap_or_default();
+                    if !matches!(dimensions.split(',').next(), Some("ip" | "net")) {
+                        self.skipped_sets.push(set.to_owned());
+                    }
+                }
+                (Some("add" | "-A"), Some(set)) if !self.skipped_sets.iter().any(|s| s == set) => {
+                    let entry = match words.next() {
+                        Some(element) => {
+                            let first = element.split(',').next().unwrap_or_default();
+                            parse_range(first)
+                                .map(|range| IpsetEntry {
+                                    line_number,
+                                    set: set.to_owned(),
+                                    range,
+                                    nomatch: words.any(|word| word == "nomatch"),
+                                })
+                                .map_err(RecordError::from)
+                        }
+                        None => Err(RecordError::Malformed),
+                    };
+                    return Some(Ok(entry.map_err(|error| InvalidEntry {
+                        line_number,
+                        line: line.to_owned(),
+                        error,
+                    })));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// An element of a named set or map in `nft list ruleset` output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NftElement {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    /// The family of the table, e.g. `inet`, `ip` or `ip6`.
+    pub family: String,
+    pub table: String,
+    pub set: String,
+    /// The key of the element, or its first component for concatenations.
+    pub range: EitherIpRange,
+}
+
+/// Selects nftables set elements by table and set name.
+///
+/// Each non-empty list must contain the corresponding field of an element,
+/// compared case-insensitively. Empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NftFilter {
+    pub tables: Vec<String>,
+    pub sets: Vec<String>,
+}
+
+impl NftFilter {
+    pub fn matches(&self, element: &NftElement) -> bool {
+        any_of(&self.tables, [element.table.as_str()]) && any_of(&self.sets, [element.set.as_str()])
+    }
+
+    /// The addresses in each matching set, per family, table and set name.
+    pub fn collect<I: IntoIterator<Item = NftElement>>(&self, elements: I) -> RangesByName<NftSet> {
+        let mut sets = BTreeMap::<_, Vec<_>>::new();
+        for element in elements.into_iter().filter(|element| self.matches(element)) {
+            let key = NftSet {
+                family: element.family,
+                table: element.table,
+                set: element.set,
+            };
+            sets.entry(key).or_default().push(element.range);
+        }
+        sets.into_iter()
+            .map(|(set, ranges)| (set, split_by_family(ranges)))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+enum Block {
+    Table { family: String, name: String },
+    Set { name: String, holds_addresses: bool },
+    Other,
+}
+
+/// Reads the elements of named sets and maps from `nft list ruleset` (or
+/// `nft list set`) output.
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one element. Elements may be addresses, prefixes or
+/// intervals like `192.0.2.4-192.0.2.9`; for concatenations and maps only the
+/// key's first component is read. Sets whose `type` or `typeof` does not start
+/// with an address are skipped, as are anonymous sets in rules.
+#[derive(Debug)]
+pub struct NftReader<R: BufRead> {
+    lines: LineReader<R>,
+    state: NftState,
+}
+
+/// Where an [`NftReader`] is in the ruleset.
+#[derive(Debug, Default)]
+struct NftState {
+    blocks: Vec<Block>,
+    in_elements: bool,
+    pending: VecDeque<Result<NftElement, InvalidEntry>>,
+}
+
+impl<R: BufRead> NftReader<R> {
+    pub fn new(reader: R) -> Self {
+        NftReader {
+            lines: LineReader::new(reader),
+            state: NftState::default(),
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl NftState {
+    fn read_line(&mut self, line_number: usize, line: &str) {
+        if self.in_elements {
+            self.read_elements(line_number, line);
+            return;
+        }
+        if line.starts_with('#') {
+            return;
+        }
+        if let Some(elements) = line.strip_prefix("elements = {") {
+            self.in_elements = true;
+            self.read_elements(line_number, elements);
+            return;
+        }
+        if let Some(Block::Set {
+            holds_addresses, ..
+        }) = self.blocks.last_mut()
+        {
+            if let Some(kind) = line
+                .strip_prefix("type ")
+                .or_else(|| line.strip_prefix("typeof "))
+            {
+                let keys = [
+                    "ipv4_addr",
+                    "ipv6_addr",
+                    "ip saddr",
+                    "ip daddr",
+                    "ip6 saddr",
+                    "ip6 daddr",
+                ];
+                *holds_addresses = keys.iter().any(|key| kind.starts_with(key));
+            }
+        }
+        let (opened, closed) = count_braces(line);
+        for _ in opened..closed {
+            self.blocks.pop();
+        }
+        if opened > closed {
+            let words: Vec<_> = line.split_whitespace().collect();
+            let block = match words[..] {
+                ["table", family, name, "{"] => Block::Table {
+                    family: family.to_owned(),
+                    name: name.to_owned(),
+                },
+                ["table", name, "{"] => Block::Table {
+                    family: "ip".to_owned(),
+                    name: name.to_owned(),
+                },
+                ["set" | "map", name, "{"] => Block::Set {
+                    name: name.to_owned(),
+                    holds_addresses: false,
+                },
+                _ => Block::Other,
+            };
+            self.blocks.push(block);
+        }
+    }
+
+    /// Queue the elements on a line of an `elements = { ... }` list.
+    fn read_elements(&mut self, line_number: usize, line: &str) {
+        let (elements, end) = match find_unquoted(line, '}') {
+            Some(end) => (&line[..end], true),
+            None => (line, false),
+        };
+        self.in_elements = !end;
+        let (family, table) = self
+            .blocks
+            .iter()
+            .find_map(|block| match block {
+                Block::Table { family, name } => Some((family.clone(), name.clone())),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let Some(Block::Set {
+            name,
+            holds_addresses: true,
+        }) = self.blocks.last()
+        else {
+            return;
+        };
+        for element in split_unquoted(elements, ',') {
+            let Some(key) = element.split_whitespace().next() else {
+                continue;
+            };
+            self.pending.push_back(
+                parse_range(key)
+                    .map(|range| NftElement {
+                        line_number,
+                        family: family.clone(),
+                        table: table.clone(),
+                        set: name.clone(),
+                        range,
+                    })
+                    .map_err(|error| InvalidEntry {
+                        line_number,
+                        line: element.trim().to_owned(),
+                        error: error.into(),
+                    }),
+            );
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for NftReader<R> {
+    type Item = io::Result<Result<NftElement, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(element) = self.state.pending.pop_front() {
+                return Some(Ok(element));
+            }
+            match self.lines.next_line()? {
+                Ok((line_number, line)) => self.state.read_line(line_number, line.trim()),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Parse an address, CIDR block or `first-last` range, where the syntax
+/// allows ranges that are not CIDR blocks.
+fn parse_range(s: &str) -> Result<EitherIpRange, ParseCidrError> {
+    parse_entry(s, &ParseOptions::default()).map(|(range, _)| range)
+}
+
+/// Apply entries from the widest to the narrowest, adding the matching ones
+/// and taking out the `nomatch` ones.
+fn most_specific_wins<R: IpRange>(mut entries: Vec<(R, bool)>) -> Vec<R> {
+    let width = |range: &R| range.last_address_as_decimal() - range.first_address_as_decimal();
+    entries.sort_by_key(|(range, _)| Reverse(width(range)));
+    let mut ranges = Vec::new();
+    for group in entries.chunk_by(|(a, _), (b, _)| width(a) == width(b)) {
+        let (nomatch, matching): (Vec<_>, Vec<_>) = group.iter().partition(|(_, nomatch)| *nomatch);
+        let matching: Vec<_> = matching.into_iter().map(|(range, _)| range).collect();
+        let nomatch: Vec<_> = nomatch.into_iter().map(|(range, _)| range).collect();
+        ranges = ranges
+            .unioned(&matching.aggregated())
+            .differenced(&nomatch.aggregated());
+    }
+    ranges
+}
+
+fn aggregate_all<K: Ord>(groups: RangesByName<K>) -> RangesByName<K> {
+    groups
+        .into_iter()
+        .map(|(name, (v4, v6))| (name, (v4.aggregated(), v6.aggregated())))
+        .collect()
+}
+
+/// Split a shell-like line into words, undoing double quotes and backslash
+/// escapes as `iptables-save` writes them.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+/// Count the braces outside double quotes.
+fn count_braces(line: &str) -> (usize, usize) {
+    let mut quoted = false;
+    let (mut opened, mut closed) = (0, 0);
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '{' if !quoted => opened += 1,
+            '}' if !quoted => closed += 1,
+            _ => {}
+        }
+    }
+    (opened, closed)
+}
+
+fn find_unquoted(line: &str, needle: char) -> Option<usize> {
+    let mut quoted = false;
+    line.char_indices().find_map(|(i, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        (c == needle && !quoted).then_some(i)
+    })
+}
+
+fn split_unquoted(line: &str, delimiter: char) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    while let Some(i) = find_unquoted(rest, delimiter) {
+        fields.push(&rest[..i]);
+        rest = &rest[i + delimiter.len_utf8()..];
+    }
+    fields.push(rest);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> EitherIpRange {
+        s.parse().unwrap()
+    }
+
+    fn read<T, I: Iterator<Item = io::Result<Result<T, InvalidEntry>>>>(
+        items: I,
+    ) -> Vec<Result<T, InvalidEntry>> {
+        items.collect::<io::Result<_>>().unwrap()
+    }
+
+    fn export<K: fmt::Display>(ranges: &RangesByName<K>, name: &str) -> (String, String) {
+        let (_, (v4, v6)) = ranges
+            .iter()
+            .find(|(key, _)| key.to_string() == name)
+            .unwrap();
+        (
+            v4.clone().normalized().export(),
+            v6.clone().normalized().export(),
+        )
+    }
+
+    fn names<K: fmt::Display>(ranges: &RangesByName<K>) -> Vec<String> {
+        ranges.keys().map(K::to_string).collect()
+    }
+
+    #[test]
+    fn iptables_rules() {
+        let input = r#"# Generated by iptables-save v1.8.7 on Mon Jan  1 00:00:00 2024
+*nat
+:POSTROUTING ACCEPT [0:0]
+-A POSTROUTING -s 10.8.0.0/24 ! -d 10.8.0.0/24 -j MASQUERADE
+COMMIT
+*nat
+:INPUT ACCEPT [0:0]
+-A INPUT -s 203.0.113.0/24 -j ACCEPT
+COMMIT
+*filter
+:INPUT DROP [0:0]
+:TRUSTED - [0:0]
+-A INPUT -i lo -j ACCEPT
+-A INPUT ! -s 192.168.0.0/16 -m comment --comment "not \"-s\" ours" -j DROP
+-A INPUT -s 192.168.1.0/24,192.168.2.0/24 -g TRUSTED
+-A TRUSTED -m iprange --src-range 192.168.1.10-192.168.1.20 -j ACCEPT
+COMMIT
+"#;
+        let rules: Vec<_> = read(IptablesReader::new(input.as_bytes()))
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rules.len(), 6);
+        assert_eq!(
+            rules[0],
+            IptablesRule {
+                line_number: 4,
+                table: "nat".into(),
+                chain: "POSTROUTING".into(),
+                source: Some(AddressMatch {
+                    ranges: vec![range("10.8.0.0/24")],
+                    negated: false,
+                }),
+                destination: Some(AddressMatch {
+                    ranges: vec![range("10.8.0.0/24")],
+                    negated: true,
+                }),
+                target: Some("MASQUERADE".into()),
+            }
+        );
+        assert_eq!(rules[2].source, None);
+        assert_eq!(rules[3].target.as_deref(), Some("DROP"));
+        assert_eq!(rules[4].target.as_deref(), Some("TRUSTED"));
+
+        let chains = IptablesFilter::default().collect(rules.clone(), Direction::Source);
+        assert_eq!(
+            names(&chains),
+            [
+                "filter INPUT",
+                "filter TRUSTED",
+                "nat INPUT",
+                "nat POSTROUTING"
+            ]
+        );
+        assert_eq!(export(&chains, "nat INPUT").0, "203.0.113.0/24");
+        // Everything but 192.168.0.0/16, then parts of it again
+        let input = IptablesChain {
+            table: "filter".into(),
+            chain: "INPUT".into(),
+        };
+        let (input_v4, input_v6) = chains[&input].clone();
+        assert_eq!(input_v4.count_address(), u32::MAX - (1 << 16) + 1 + 512);
+        assert!(input_v6.is_empty());
+        assert_eq!(
+            export(&chains, "filter TRUSTED").0,
+            "192.168.1.10/31\n192.168.1.12/30\n192.168.1.16/30\n192.168.1.20/32"
+        );
+
+        let masqueraded = IptablesFilter {
+            targets: vec!["masquerade".into()],
+            ..Default::default()
+        }
+        .collect(rules, Direction::Destination);
+        assert_eq!(
+            masqueraded.values().next().unwrap().0,
+            vec![range("10.8.0.0/24").into_v4().unwrap()].reversed()
+        );
+    }
+
+    #[test]
+    fn ip6tables_and_invalid_rules() {
+        let input = "*filter\n-A INPUT -s 2001:db8::/32 -j ACCEPT\n-A INPUT -s example.com/32 -j ACCEPT\n-A INPUT -s 10.0.0.1-10.0.0.9 -j ACCEPT\n-A INPUT -s\n";
+        let items = read(IptablesReader::new(input.as_bytes()));
+        let (v4, v6) = items[0].as_ref().unwrap().source.as_ref().unwrap().split();
+        assert!(v4.is_empty());
+        assert_eq!(v6.normalized().export(), "2001:db8::/32");
+        let errors: Vec<_> = items[1..]
+            .iter()
+            .map(|item| item.as_ref().unwrap_err().error)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                RecordError::Cidr(ParseCidrError::InvalidAddress),
+                RecordError::Cidr(ParseCidrError::InvalidAddress),
+                RecordError::Malformed
+            ]
+        );
+    }
+
+    #[test]
+    fn ipset_entries() {
+        let input = "\
+create allow hash:net family inet hashsize 1024 maxelem 65536
+add allow 10.0.0.0/8
+add allow 10.1.0.0/16 nomatch
+add allow 10.1.2.0/24
+create web hash:ip,port family inet6 hashsize 1024 maxelem 65536 timeout 300
+add web 2001:db8::1,tcp:443 timeout 120
+create macs hash:mac hashsize 1024 maxelem 65536
+add macs 00:11:22:33:44:55
+create all list:set size 8
+add all allow
+add allow
+";
+        let items = read(IpsetReader::new(input.as_bytes()));
+        assert_eq!(items.len(), 5);
+        assert_eq!(
+            items[3],
+            Ok(IpsetEntry {
+                line_number: 6,
+                set: "web".into(),
+                range: range("2001:db8::1/128"),
+                nomatch: false,
+            })
+        );
+        assert!(matches!(&items[4], Err(entry) if entry.line_number == 11));
+
+        let sets = IpsetFilter::default().collect(items.into_iter().flatten());
+        assert_eq!(names(&sets), ["allow", "web"]);
+        let (allow, _) = &sets["allow"];
+        assert_eq!(allow.count_address(), (1 << 24) - (1 << 16) + (1 << 8));
+        assert_eq!(export(&sets, "web").1, "2001:db8::1/128");
+    }
+
+    #[test]
+    fn nft_set_elements() {
+        let input = r#"table inet filter {
+	set blocklist {
+		type ipv4_addr
+		flags interval
+		elements = { 192.0.2.0/24, 198.51.100.4-198.51.100.9,
+			     203.0.113.7 comment "scanner, {again}" }
+	}
+
+	set blocklist6 {
+		typeof ip6 saddr
+		flags interval
+		elements = { 2001:db8::/32 timeout 1h expires 59m }
+	}
+
+	set ports {
+		type inet_service
+		elements = { 22, 443 }
+	}
+
+	map redirects {
+		type ipv4_addr . inet_service : verdict
+		elements = { 10.0.0.1 . 80 : accept }
+	}
+
+	chain input {
+		type filter hook input priority filter; policy drop;
+		ip saddr { 10.9.9.9, 10.8.8.8 } accept
+		ip saddr @blocklist drop
+	}
+}
+table ip nat {
+	set blocklist {
+		type ipv4_addr
+		elements = { 192.0.2.1, bogus }
+	}
+}
+"#;
+        let items = read(NftReader::new(input.as_bytes()));
+        let elements: Vec<_> = items.iter().flatten().collect();
+        assert_eq!(elements.len(), 6);
+        assert_eq!(
+            *elements[2],
+            NftElement {
+                line_number: 6,
+                family: "inet".into(),
+                table: "filter".into(),
+                set: "blocklist".into(),
+                range: range("203.0.113.7"),
+            }
+        );
+        assert_eq!(elements[4].set, "redirects");
+        assert_eq!(elements[5].table, "nat");
+        let errors: Vec<_> = items
+            .iter()
+            .filter_map(|item| item.as_ref().err())
+            .collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            (errors[0].line_number, errors[0].line.as_str()),
+            (34, "bogus")
+        );
+
+        let sets = NftFilter::default().collect(items.into_iter().flatten());
+        assert_eq!(
+            names(&sets),
+            [
+                "inet filter blocklist",
+                "inet filter blocklist6",
+                "inet filter redirects",
+                "ip nat blocklist"
+            ]
+        );
+        assert_eq!(
+            export(&sets, "inet filter blocklist").0,
+            "192.0.2.0/24\n198.51.100.4/30\n198.51.100.8/31\n203.0.113.7/32"
+        );
+        assert_eq!(export(&sets, "ip nat blocklist").0, "192.0.2.1/32");
+        assert_eq!(export(&sets, "inet filter blocklist6").1, "2001:db8::/32");
+    }
+
+    #[test]
+    fn word_splitting() {
+        assert_eq!(
+            split_words(r#"-A INPUT -m comment --comment "a \"b\" c" -j  ACCEPT"#),
+            [
+                "-A",
+                "INPUT",
+                "-m",
+                "comment",
+                "--comment",
+                r#"a "b" c"#,
+                "-j",
+                "ACCEPT"
+            ]
+        );
+        assert_eq!(split_words(r#"--comment """#), ["--comment", ""]);
+        assert_eq!(
+            split_unquoted(r#"a, "b, c", d"#, ','),
+            ["a", r#" "b, c""#, " d"]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! GeoIP networks from MaxMind GeoLite2 / GeoIP2 databases, in either the CSV
+//! layout or the binary [`mmdb`](super::mmdb) format, selected by country,
+//! continent or autonomous system.
+//!
+//! The CSV layout spreads a database over a blocks file, with one network per
+//! row, and a locations file the blocks refer to by `geoname_id`:
+//!
+//! ```
+//! use cidr_aggregator::aggregator::Aggregator;
+//! use cidr_aggregator::formats::geoip::{read_geolite2_locations, GeoFilter, GeoLite2BlocksReader};
+//!
+//! let locations = "\
+//! geoname_id,locale_code,continent_code,continent_name,country_iso_code,country_name,is_in_european_union
+//! 2077456,en,OC,Oceania,AU,Australia,0
+//! 1814991,en,AS,Asia,CN,China,0
+//! ";
+//! let blocks = "\
+//! network,geoname_id,registered_country_geoname_id,represented_country_geoname_id,is_anonymous_proxy,is_satellite_provider,is_anycast
+//! 1.0.0.0/24,2077456,2077456,,0,0,
+//! 1.0.1.0/24,1814991,1814991,,0,0,
+//! 1.0.2.0/23,1814991,1814991,,0,0,
+//! ";
+//! let locations = read_geolite2_locations(locations.as_bytes())?;
+//! let networks = GeoLite2BlocksReader::new(blocks.as_bytes(), locations)
+//!     .map(|item| item.map(|network| network.unwrap()))
+//!     .collect::<std::io::Result<Vec<_>>>()?;
+//! let filter = GeoFilter {
+//!     countries: vec!["CN".into()],
+//!     ..Default::default()
+//! };
+//! let (v4, v6) = filter.collect(networks);
+//! assert_eq!(v4.normalized().export(), "1.0.1.0/24\n1.0.2.0/23");
+//! assert!(v6.is_empty());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use crate::aggregator::Aggregator;
+use crate::delimited::{split_fields, Column, DelimitedOptions, DelimitedReader, Header};
+use crate::formats::mmdb::MmdbValue;
+use crate::formats::{any_of, split_by_family};
+use crate::parser::{InvalidEntry, ParseOptions, RecordError};
+use crate::{EitherIpRange, IpRange, Ipv4Range, Ipv6Range};
+
+/// A network with what the database says about it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GeoNetwork {
+    pub range: EitherIpRange,
+    /// ISO 3166 country code of where the network is located, falling back to
+    /// the country it is registered to.
+    pub country: Option<String>,
+    /// Two-letter continent code, e.g. `EU`.
+    pub continent: Option<String>,
+    /// Autonomous system number, from ASN databases.
+    pub asn: Option<u32>,
+    /// Autonomous system organization, from ASN databases.
+    pub organization: Option<String>,
+}
+
+impl GeoNetwork {
+    /// Pick the fields out of a record of a GeoIP2 / GeoLite2 Country, City
+    /// or ASN database.
+    pub fn from_mmdb(range: EitherIpRange, data: &MmdbValue) -> Self {
+        let string = |path: &[&str]| data.get_path(path)?.as_str().map(str::to_owned);
+        GeoNetwork {
+            range,
+            country: string(&["country", "iso_code"])
+                .or_else(|| string(&["registered_country", "iso_code"])),
+            continent: string(&["continent", "code"]),
+            asn: data
+                .get("autonomous_system_number")
+                .and_then(MmdbValue::as_u64)
+                .and_then(|asn| asn.try_into().ok()),
+            organization: string(&["autonomous_system_organization"]),
+        }
+    }
+}
+
+/// Selects networks by country, continent and ASN.
+///
+/// Each non-empty list must contain the corresponding field of a network,
+/// with codes compared case-insensitively; networks without that field never
+/// match. Empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct GeoFilter {
+    pub countries: Vec<String>,
+    pub continents: Vec<String>,
+    pub asns: Vec<u32>,
+}
+
+impl GeoFilter {
+    pub fn matches(&self, network: &GeoNetwork) -> bool {
+        any_of(&self.countries, network.country.as_deref())
+            && any_of(&self.continents, network.continent.as_deref())
+            && (self.asns.is_empty() || network.asn.is_some_and(|asn| self.asns.contains(&asn)))
+    }
+
+    /// Keep the matching networks, split them by address family with
+    /// [`fold_embedded_ipv4`], and aggregate.
+    pub fn collect<I: IntoIterator<Item = GeoNetwork>>(
+        &self,
+        networks: I,
+    ) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+        let (v4, v6) = split_by_family(
+            networks
+                .into_iter()
+                .filter(|network| self.matches(network))
+                .map(|network| network.range),
+        );
+        fold_embedded_ipv4(v4, v6)
+    }
+}
+
+/// Move the parts of `v6` in the IPv4-compatible `::/96` and IPv4-mapped
+/// `::ffff:0:0/96` blocks over to `v4`, returning both aggregated.
+pub fn fold_embedded_ipv4(
+    v4: Vec<Ipv4Range>,
+    v6: Vec<Ipv6Range>,
+) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+    const MAPPED: u128 = 0xffff << 32;
+    let embedded = [
+        Ipv6Range::from_cidr_pair_decimal((0, u32::MAX as u128)),
+        Ipv6Range::from_cidr_pair_decimal((MAPPED, MAPPED | u32::MAX as u128)),
+    ];
+    let v6 = v6.aggregated();
+    let mut v4 = v4;
+    v4.extend(v6.clone().intersected(&embedded).into_iter().map(|range| {
+        let (first, last) = range.into_cidr_pair_decimal();
+        Ipv4Range::from_cidr_pair_decimal((first as u32, last as u32))
+    }));
+    (v4.aggregated(), v6.differenced(&embedded))
+}
+
+/// Where a `geoname_id` of a GeoLite2 locations file is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct GeoLocation {
+    pub country: Option<String>,
+    pub continent: Option<String>,
+}
+
+/// Read a GeoLite2 / GeoIP2 locations CSV file, such as
+/// `GeoLite2-Country-Locations-en.csv`, into a map by `geoname_id`.
+pub fn read_geolite2_locations<R: BufRead>(reader: R) -> io::Result<HashMap<u32, GeoLocation>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut lines = reader.lines();
+    let header = split_fields(
+        lines
+            .next()
+            .transpose()?
+            .unwrap_or_default()
+            .trim_start_matches('\u{feff}'),
+        ',',
+    );
+    let column = |name: &str| header.iter().position(|field| field == name);
+    let id_column = column("geoname_id")
+        .ok_or_else(|| invalid("no geoname_id column in the locations header".to_owned()))?;
+    let country_column = column("country_iso_code");
+    let continent_column = column("continent_code");
+
+    let mut locations = HashMap::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_fields(&line, ',');
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|column| fields.get(column))
+                .filter(|field| !field.is_empty())
+                .cloned()
+        };
+        let id = field(Some(id_column))
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| invalid(format!("line {}: invalid geoname_id", i + 2)))?;
+        locations.insert(
+            id,
+            GeoLocation {
+                country: field(country_column),
+                continent: field(continent_column),
+            },
+        );
+    }
+    Ok(locations)
+}
+
+/// Reads a GeoLite2 / GeoIP2 blocks CSV file, such as
+/// `GeoLite2-Country-Blocks-IPv4.csv` or `GeoLite2-ASN-Blocks-IPv6.csv`, line
+/// by line, joining each network with its location.
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one record. Rows are invalid if their network does
+/// not parse, or with [`RecordError::Malformed`] if a `geoname_id` or ASN is
+/// not a number.
+#[derive(Debug)]
+pub struct GeoLite2BlocksReader<R: BufRead> {
+    records: DelimitedReader<R>,
+    locations: HashMap<u32, GeoLocation>,
+    /// Label indices of the columns of interest, once the header is read.
+    columns: Option<BlocksColumns>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BlocksColumns {
+    geoname_id: Option<usize>,
+    registered_country_geoname_id: Option<usize>,
+    asn: Option<usize>,
+    organization: Option<usize>,
+}
+
+impl<R: BufRead> GeoLite2BlocksReader<R> {
+    /// Read from `reader`, looking up `geoname_id`s in `locations`, which may
+    /// be empty for ASN databases.
+    pub fn new(reader: R, locations: HashMap<u32, GeoLocation>) -> Self {
+        let options = DelimitedOptions {
+            delimiter: ',',
+            column: Column::Name("network".to_owned()),
+            header: Header::Present,
+            parse: ParseOptions {
+                comment_markers: Vec::new(),
+                ..Default::default()
+            },
+        };
+        GeoLite2BlocksReader {
+            records: DelimitedReader::new(reader, options),
+            locations,
+            columns: None,
+        }
+    }
+
+    fn columns(&mut self) -> BlocksColumns {
+        *self.columns.get_or_insert_with(|| {
+            // Labels are the header fields except `network`
+            let labels: Vec<_> = self
+                .records
+                .header()
+                .unwrap_or_default()
+                .iter()
+                .filter(|name| !name.eq_ignore_ascii_case("network"))
+                .collect();
+            let label = |name: &str| labels.iter().position(|label| *label == name);
+            BlocksColumns {
+                geoname_id: label("geoname_id"),
+                registered_country_geoname_id: label("registered_country_geoname_id"),
+                asn: label("autonomous_system_number"),
+                organization: label("autonomous_system_organization"),
+            }
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for GeoLite2BlocksReader<R> {
+    type Item = io::Result<Result<GeoNetwork, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(Ok(record)) => record,
+            Ok(Err(invalid)) => return Some(Ok(Err(invalid))),
+            Err(e) => return Some(Err(e)),
+        };
+        let columns = self.columns();
+        let label = |column: Option<usize>| {
+            column
+                .and_then(|column| record.labels.get(column))
+                .filter(|label| !label.is_empty())
+        };
+        let malformed = || InvalidEntry {
+            line_number: record.line_number,
+            line: self.records.current_line().to_owned(),
+            error: RecordError::Malformed,
+        };
+        let id = |column| label(column).map(|id| id.parse::<u32>()).transpose();
+        let (geoname_id, registered_id, asn) = match (
+            id(columns.geoname_id),
+            id(columns.registered_country_geoname_id),
+            id(columns.asn),
+        ) {
+            (Ok(geoname_id), Ok(registered_id), Ok(asn)) => (geoname_id, registered_id, asn),
+            _ => return Some(Ok(Err(malformed()))),
+        };
+        let location = |id: Option<u32>| id.and_then(|id| self.locations.get(&id));
+        let here = location(geoname_id);
+        let registered = location(registered_id);
+        Some(Ok(Ok(GeoNetwork {
+            range: record.range,
+            country: here
+                .and_then(|location| location.country.clone())
+                .or_else(|| registered.and_then(|location| location.country.clone())),
+            continent: here
+                .or(registered)
+                .and_then(|location| location.continent.clone()),
+            asn,
+            organization: label(columns.organization).cloned(),
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseCidrError;
+
+    const LOCATIONS: &str = "\
+geoname_id,locale_code,continent_code,continent_name,country_iso_code,country_name,is_in_european_union
+2077456,en,OC,Oceania,AU,Australia,0
+2921044,en,EU,Europe,DE,Germany,1
+6255148,en,EU,Europe,,,0
+";
+
+    fn read_blocks(blocks: &str) -> Vec<Result<GeoNetwork, InvalidEntry>> {
+        let locations = read_geolite2_locations(LOCATIONS.as_bytes()).unwrap();
+        GeoLite2BlocksReader::new(blocks.as_bytes(), locations)
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn locations() {
+        let locations = read_geolite2_locations(LOCATIONS.as_bytes()).unwrap();
+        assert_eq!(locations.len(), 3);
+        assert_eq!(
+            locations[&6255148],
+            GeoLocation {
+                country: None,
+                continent: Some("EU".into()),
+            }
+        );
+        assert!(read_geolite2_locations("locale_code\nen\n".as_bytes()).is_err());
+        assert!(read_geolite2_locations("geoname_id\nabc\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn country_blocks() {
+        let networks = read_blocks(
+            "\u{feff}network,geoname_id,registered_country_geoname_id,represented_country_geoname_id,is_anonymous_proxy,is_satellite_provider\n\
+             2a00:1450::/29,2921044,2921044,,0,0\n\
+             5.1.0.0/17,,2921044,,0,0\n\
+             5.2.0.0/17,6255148,,,0,0\n\
+             5.3.0.0/17,x,,,0,0\n\
+             5.4.0.0/33,,,,0,0\n",
+        );
+        let network = networks[0].as_ref().unwrap();
+        assert_eq!(network.range, "2a00:1450::/29".parse().unwrap());
+        assert_eq!(network.country.as_deref(), Some("DE"));
+        assert_eq!(network.continent.as_deref(), Some("EU"));
+        assert_eq!(network.asn, None);
+
+        // Registered country fills in for an unknown location
+        let registered = networks[1].as_ref().unwrap();
+        assert_eq!(registered.country.as_deref(), Some("DE"));
+        let continent_only = networks[2].as_ref().unwrap();
+        assert_eq!(continent_only.country, None);
+        assert_eq!(continent_only.continent.as_deref(), Some("EU"));
+
+        let malformed = networks[3].as_ref().unwrap_err();
+        assert_eq!(malformed.line_number, 5);
+        assert_eq!(malformed.error, RecordError::Malformed);
+        assert_eq!(malformed.line, "5.3.0.0/17,x,,,0,0");
+        assert!(matches!(
+            networks[4].as_ref().unwrap_err().error,
+            RecordError::Cidr(ParseCidrError::PrefixTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn asn_blocks() {
+        let networks = read_blocks(
+            "network,autonomous_system_number,autonomous_system_organization\n\
+             1.0.0.0/24,13335,CLOUDFLARENET\n\
+             1.0.4.0/22,38803,\"Gtelecom Pty Ltd, AU\"\n",
+        );
+        let network = networks[1].as_ref().unwrap();
+        assert_eq!(network.asn, Some(38803));
+        assert_eq!(
+            network.organization.as_deref(),
+            Some("Gtelecom Pty Ltd, AU")
+        );
+        assert_eq!(network.country, None);
+    }
+
+    #[test]
+    fn filter() {
+        let network = |range: &str, country: Option<&str>, asn: Option<u32>| GeoNetwork {
+            range: range.parse().unwrap(),
+            country: country.map(str::to_owned),
+            continent: Some("EU".into()),
+            asn,
+            organization: None,
+        };
+        let networks = [
+            network("10.0.0.0/9", Some("DE"), Some(1)),
+            network("10.128.0.0/9", Some("de"), Some(2)),
+            network("::ffff:10.0.0.0/104", Some("FR"), Some(1)),
+            network("2001:db8::/32", None, Some(1)),
+        ];
+        let collect = |filter: GeoFilter| {
+            let (v4, v6) = filter.collect(networks.iter().cloned());
+            (v4.normalized().export(), v6.normalized().export())
+        };
+        assert_eq!(
+            collect(GeoFilter {
+                countries: vec!["DE".into()],
+                ..Default::default()
+            }),
+            ("10.0.0.0/8".into(), "".into())
+        );
+        assert_eq!(
+            collect(GeoFilter {
+                continents: vec!["eu".into()],
+                asns: vec![1],
+                ..Default::default()
+            }),
+            ("10.0.0.0/8".into(), "2001:db8::/32".into())
+        );
+        assert_eq!(
+            collect(GeoFilter {
+                continents: vec!["AS".into()],
+                ..Default::default()
+            }),
+            ("".into(), "".into())
+        );
+    }
+
+    #[test]
+    fn embedded_ipv4() {
+        let (v4, v6, _) = crate::parser::parse_cidrs(
+            "1.2.5.0/24\n::1.2.3.0/120\n::ffff:1.2.4.0/120\n::1:0:0:0/96",
+        );
+        let (v4, v6) = fold_embedded_ipv4(v4, v6);
+        assert_eq!(v4.normalized().export(), "1.2.3.0/24\n1.2.4.0/23");
+        assert_eq!(v6.normalized().export(), "::1:0:0:0/96");
+    }
+}
@@ -0,0 +1,404 @@
+//! v2ray / Xray `geoip.dat` files, the protobuf `GeoIPList` used by proxy
+//! routing rules such as `geoip:cn`.
+//!
+//! A file is a list of entries, each holding a tag, conventionally an
+//! uppercase country code like `CN` or a name like `PRIVATE`, and its CIDRs:
+//!
+//! ```text
+//! message CIDR { bytes ip = 1; uint32 prefix = 2; }
+//! message GeoIP { string country_code = 1; repeated CIDR cidr = 2; bool reverse_match = 3; }
+//! message GeoIPList { repeated GeoIP entry = 1; }
+//! ```
+//!
+//! ```
+//! use cidr_aggregator::formats::geoip_dat::{read_geoip_dat, write_geoip_dat, GeoIpTag};
+//! use cidr_aggregator::parser::parse_cidrs;
+//!
+//! let (v4, v6, _) = parse_cidrs("1.0.1.0/24\n1.0.2.0/23\n1.0.1.128/25\n2001:250::/35");
+//! let mut tag = GeoIpTag {
+//!     code: "CN".into(),
+//!     v4,
+//!     v6,
+//!     reverse_match: false,
+//! };
+//! tag.process(false, false);
+//!
+//! let tags = read_geoip_dat(&write_geoip_dat(&[tag.clone()]))?;
+//! assert_eq!(tags, [tag]);
+//! # Ok::<(), cidr_aggregator::formats::geoip_dat::GeoIpDatError>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use crate::aggregator::{self, Aggregator};
+use crate::{IpRange, Ipv4Range, Ipv6Range};
+
+/// An entry of a `geoip.dat` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct GeoIpTag {
+    /// The tag as written, e.g. `CN`; matched case-insensitively by v2ray.
+    pub code: String,
+    pub v4: Vec<Ipv4Range>,
+    pub v6: Vec<Ipv6Range>,
+    /// Whether rules using this entry match addresses *not* in it.
+    pub reverse_match: bool,
+}
+
+impl GeoIpTag {
+    /// Run both families through [`aggregator::process`], leaving them
+    /// aggregated and normalized.
+    pub fn process(&mut self, reverse: bool, exclude_reserved: bool) {
+        self.v4 = aggregator::process(std::mem::take(&mut self.v4), reverse, exclude_reserved);
+        self.v6 = aggregator::process(std::mem::take(&mut self.v6), reverse, exclude_reserved);
+    }
+}
+
+/// Merge the entries sharing a tag, compared case-insensitively, keeping the
+/// order in which tags first appear.
+///
+/// Ranges are concatenated, not aggregated; `reverse_match` is kept if any
+/// of the merged entries has it.
+pub fn merge_geoip_tags<I: IntoIterator<Item = GeoIpTag>>(tags: I) -> Vec<GeoIpTag> {
+    let mut merged: Vec<GeoIpTag> = Vec::new();
+    for tag in tags {
+        match merged
+            .iter_mut()
+            .find(|existing| existing.code.eq_ignore_ascii_case(&tag.code))
+        {
+            Some(existing) => {
+                existing.v4.extend(tag.v4);
+                existing.v6.extend(tag.v6);
+                existing.reverse_match |= tag.reverse_match;
+            }
+            None => merged.push(tag),
+        }
+    }
+    merged
+}
+
+/// Why a `geoip.dat` file could not be read.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum GeoIpDatError {
+    /// The protobuf encoding is broken.
+    Malformed(&'static str),
+    /// A CIDR of the entry with the given tag has an address that is neither
+    /// 4 nor 16 bytes long, or a prefix length beyond the address length.
+    InvalidCidr { code: String },
+}
+
+impl fmt::Display for GeoIpDatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GeoIpDatError::Malformed(reason) => write!(f, "malformed geoip.dat: {}", reason),
+            GeoIpDatError::InvalidCidr { code } => {
+                write!(f, "invalid CIDR in geoip.dat entry {}", code)
+            }
+        }
+    }
+}
+
+impl Error for GeoIpDatError {}
+
+/// Read the entries of a `geoip.dat` file.
+///
+/// Host bits set in a CIDR are masked off. Unknown fields are skipped.
+pub fn read_geoip_dat(buf: &[u8]) -> Result<Vec<GeoIpTag>, GeoIpDatError> {
+    let mut tags = Vec::new();
+    for field in Fields(buf) {
+        if let (1, Value::Bytes(entry)) = field? {
+            tags.push(read_entry(entry)?);
+        }
+    }
+    Ok(tags)
+}
+
+fn read_entry(buf: &[u8]) -> Result<GeoIpTag, GeoIpDatError> {
+    let mut tag = GeoIpTag::default();
+    let mut cidrs = Vec::new();
+    for field in Fields(buf) {
+        match field? {
+            (1, Value::Bytes(code)) => {
+                tag.code = String::from_utf8(code.to_vec())
+                    .map_err(|_| GeoIpDatError::Malformed("tag is not UTF-8"))?;
+            }
+            (2, Value::Bytes(cidr)) => cidrs.push(cidr),
+            (3, Value::Varint(reverse_match)) => tag.reverse_match = reverse_match != 0,
+            _ => {}
+        }
+    }
+    // The tag may come after the CIDRs, so parse them once it is known
+    for cidr in cidrs {
+        let mut ip: &[u8] = &[];
+        let mut prefix_len = 0;
+        for field in Fields(cidr) {
+            match field? {
+                (1, Value::Bytes(bytes)) => ip = bytes,
+                (2, Value::Varint(n)) => prefix_len = n,
+                _ => {}
+            }
+        }
+        let invalid = || GeoIpDatError::InvalidCidr {
+            code: tag.code.clone(),
+        };
+        let be = |bytes: &[u8]| bytes.iter().fold(0, |n, &b| n << 8 | b as u128);
+        let bit_len = ip.len() as u64 * 8;
+        if prefix_len > bit_len {
+            return Err(invalid());
+        }
+        let host_mask = |bit_len: u64| {
+            (u128::MAX >> (128 - bit_len))
+                .checked_shr(prefix_len as u32)
+                .unwrap_or(0)
+        };
+        match ip.len() {
+            4 => {
+                let first = be(ip) as u32 & !(host_mask(32) as u32);
+                let last = first | host_mask(32) as u32;
+                tag.v4
+                    .push(Ipv4Range::from_cidr_pair_decimal((first, last)));
+            }
+            16 => {
+                let first = be(ip) & !host_mask(128);
+                let last = first | host_mask(128);
+                tag.v6
+                    .push(Ipv6Range::from_cidr_pair_decimal((first, last)));
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(tag)
+}
+
+/// Write entries as a `geoip.dat` file.
+///
+/// Ranges are aggregated and normalized into CIDRs on the way; run
+/// [`GeoIpTag::process`] first for anything more.
+pub fn write_geoip_dat(tags: &[GeoIpTag]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for tag in tags {
+        let mut entry = Vec::new();
+        write_bytes(&mut entry, 1, tag.code.as_bytes());
+        let v4 = tag.v4.clone().aggregated().normalized();
+        let v6 = tag.v6.clone().aggregated().normalized();
+        let cidrs = v4
+            .into_iter()
+            .map(|range| {
+                let (first, last) = range.into_cidr_pair_decimal();
+                (
+                    first.to_be_bytes().to_vec(),
+                    32 - (last - first).count_ones(),
+                )
+            })
+            .chain(v6.into_iter().map(|range| {
+                let (first, last) = range.into_cidr_pair_decimal();
+                (
+                    first.to_be_bytes().to_vec(),
+                    128 - (last - first).count_ones(),
+                )
+            }));
+        for (ip, prefix_len) in cidrs {
+            let mut cidr = Vec::new();
+            write_bytes(&mut cidr, 1, &ip);
+            // Like protobuf encoders, leave out fields with default values
+            if prefix_len != 0 {
+                write_varint(&mut cidr, 2 << 3);
+                write_varint(&mut cidr, prefix_len as u64);
+            }
+            write_bytes(&mut entry, 2, &cidr);
+        }
+        if tag.reverse_match {
+            write_varint(&mut entry, 3 << 3);
+            write_varint(&mut entry, 1);
+        }
+        write_bytes(&mut buf, 1, &entry);
+    }
+    buf
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Write a length-delimited field.
+fn write_bytes(buf: &mut Vec<u8>, field_number: u64, bytes: &[u8]) {
+    write_varint(buf, field_number << 3 | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// The value of a protobuf field; fixed-width values are skipped.
+#[derive(Debug)]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterator over the fields of a protobuf message, as field numbers and values.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64, GeoIpDatError> {
+        let mut n = 0;
+        for (i, &byte) in self.0.iter().enumerate().take(10) {
+            n |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[i + 1..];
+                return Ok(n);
+            }
+        }
+        Err(GeoIpDatError::Malformed("truncated or overlong varint"))
+    }
+
+    fn take(&mut self, len: u64) -> Result<&'a [u8], GeoIpDatError> {
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= self.0.len())
+            .ok_or(GeoIpDatError::Malformed("field runs past the end"))?;
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn field(&mut self) -> Result<(u64, Value<'a>), GeoIpDatError> {
+        let key = self.varint()?;
+        let value = match key & 0x07 {
+            0 => Value::Varint(self.varint()?),
+            1 => self.take(8).map(|_| Value::Fixed)?,
+            2 => {
+                let len = self.varint()?;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => self.take(4).map(|_| Value::Fixed)?,
+            _ => return Err(GeoIpDatError::Malformed("unsupported wire type")),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Value<'a>), GeoIpDatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            // Stop after the first error
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_cidrs;
+
+    fn tag(code: &str, cidrs: &str) -> GeoIpTag {
+        let (v4, v6, invalid) = parse_cidrs(cidrs);
+        assert!(invalid.is_empty());
+        GeoIpTag {
+            code: code.to_owned(),
+            v4,
+            v6,
+            reverse_match: false,
+        }
+    }
+
+    fn export(tag: &GeoIpTag) -> (String, String) {
+        (tag.v4.export(), tag.v6.export())
+    }
+
+    #[test]
+    fn known_encoding() {
+        // GeoIPList { entry: [GeoIP { country_code: "PRIVATE", cidr: [10.0.0.0/8, ::1/128] }] }
+        let bytes = b"\x0a\x2a\x0a\x07PRIVATE\
+            \x12\x08\x0a\x04\x0a\x00\x00\x00\x10\x08\
+            \x12\x15\x0a\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x10\x80\x01";
+        let tags = read_geoip_dat(bytes).unwrap();
+        assert_eq!(tags, [tag("PRIVATE", "10.0.0.0/8\n::1/128")]);
+        assert_eq!(write_geoip_dat(&tags), bytes);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut cn = tag("CN", "1.0.1.0/24\n1.0.2.0/23\n240e::/18");
+        cn.reverse_match = true;
+        let tags = [cn, tag("ALL", "0.0.0.0/0\n::/0"), tag("EMPTY", "")];
+        assert_eq!(read_geoip_dat(&write_geoip_dat(&tags)).unwrap(), tags);
+    }
+
+    #[test]
+    fn writing_normalizes() {
+        let tags = [tag("US", "10.0.0.1-10.0.0.6\n10.0.0.4/30")];
+        let read = read_geoip_dat(&write_geoip_dat(&tags)).unwrap();
+        assert_eq!(export(&read[0]).0, "10.0.0.1/32\n10.0.0.2/31\n10.0.0.4/30");
+    }
+
+    #[test]
+    fn host_bits_and_unknown_fields() {
+        // 10.1.2.3/8, with an unknown fixed32 field 9 in the CIDR and an
+        // unknown varint field 7 in the entry
+        let bytes = b"\x0a\x15\x0a\x02XX\x38\x2a\x12\x0d\x0a\x04\x0a\x01\x02\x03\x10\x08\x4d\x00\x00\x00\x00";
+        let tags = read_geoip_dat(bytes).unwrap();
+        assert_eq!(export(&tags[0]), ("10.0.0.0/8".into(), "".into()));
+    }
+
+    #[test]
+    fn errors() {
+        let cidr_error = |ip: &[u8], prefix_len: u8| {
+            let mut cidr = Vec::new();
+            write_bytes(&mut cidr, 1, ip);
+            cidr.extend_from_slice(&[2 << 3, prefix_len]);
+            let mut entry = Vec::new();
+            write_bytes(&mut entry, 1, b"XX");
+            write_bytes(&mut entry, 2, &cidr);
+            let mut buf = Vec::new();
+            write_bytes(&mut buf, 1, &entry);
+            read_geoip_dat(&buf).unwrap_err()
+        };
+        let invalid = GeoIpDatError::InvalidCidr { code: "XX".into() };
+        assert_eq!(cidr_error(&[10, 0, 0, 0], 33), invalid);
+        assert_eq!(cidr_error(&[10, 0, 0], 8), invalid);
+        // 128 takes two bytes as a varint, so the one written is cut short
+        assert_eq!(
+            cidr_error(&[0; 16], 128),
+            GeoIpDatError::Malformed("truncated or overlong varint")
+        );
+
+        assert_eq!(
+            read_geoip_dat(b"\x0a\x05XX"),
+            Err(GeoIpDatError::Malformed("field runs past the end"))
+        );
+        assert_eq!(
+            read_geoip_dat(b"\x0b"),
+            Err(GeoIpDatError::Malformed("unsupported wire type"))
+        );
+    }
+
+    #[test]
+    fn process_and_merge() {
+        let tags = merge_geoip_tags([
+            tag("cn", "1.0.1.0/24\n2001:250::/35"),
+            tag("JP", "1.0.16.0/20"),
+            tag("CN", "1.0.2.0/23\n2001:250:2000::/35\n192.168.0.0/16"),
+        ]);
+        assert_eq!(tags.len(), 2);
+        let mut cn = tags[0].clone();
+        assert_eq!(cn.code, "cn");
+        cn.process(false, true);
+        assert_eq!(
+            export(&cn),
+            ("1.0.1.0/24\n1.0.2.0/23".into(), "2001:250::/34".into())
+        );
+    }
+}
@@ -0,0 +1,1183 @@
+//! MaxMind DB (`.mmdb`) files, the binary format of GeoIP2 and GeoLite2
+//! databases.
+//!
+//! A database is a binary search tree over address bits whose leaves point
+//! into a data section of typed values, followed by a metadata map. See the
+//! [format specification](https://maxmind.github.io/MaxMind-DB/). Read
+//! databases with [`MmdbReader`] and write them with [`MmdbWriter`].
+//!
+//! In IPv6 databases, IPv4 addresses live under `::/96`, and the subtrees of
+//! IPv4-mapped `::ffff:0:0/96` and 6to4 `2002::/16` addresses usually alias
+//! that same subtree. [`MmdbReader::networks`] visits each aliased subtree only
+//! once, under `::/96`; fold it back into IPv4 with
+//! [`fold_embedded_ipv4`](crate::formats::geoip::fold_embedded_ipv4).
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::aggregator::Aggregator;
+use crate::{EitherIpRange, IpRange, Ipv4Range, Ipv6Range};
+
+/// Marks the start of the metadata section.
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// Zero bytes between the search tree and the data section.
+const DATA_SECTION_SEPARATOR: usize = 16;
+/// Deepest nesting of maps and arrays accepted in the data section.
+const MAX_DATA_DEPTH: usize = 64;
+/// Most values decoded for one record. Pointers let a small data section
+/// expand exponentially, so bounding the depth alone is not enough.
+const MAX_DATA_VALUES: usize = 1 << 16;
+
+/// A value of the data section.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MmdbValue {
+    String(String),
+    Double(f64),
+    Bytes(Vec<u8>),
+    Uint16(u16),
+    Uint32(u32),
+    Map(BTreeMap<String, MmdbValue>),
+    Int32(i32),
+    Uint64(u64),
+    Uint128(u128),
+    Array(Vec<MmdbValue>),
+    Boolean(bool),
+    Float(f32),
+}
+
+impl MmdbValue {
+    /// Look up `key` if this is a map.
+    pub fn get(&self, key: &str) -> Option<&MmdbValue> {
+        match self {
+            MmdbValue::Map(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Look up a path of keys through nested maps, e.g. `["country", "iso_code"]`.
+    pub fn get_path(&self, path: &[&str]) -> Option<&MmdbValue> {
+        path.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MmdbValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value of any of the unsigned integer types, if it fits in a `u64`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            MmdbValue::Uint16(n) => Some(n.into()),
+            MmdbValue::Uint32(n) => Some(n.into()),
+            MmdbValue::Uint64(n) => Some(n),
+            MmdbValue::Uint128(n) => n.try_into().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Why a database could not be read or written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MmdbError {
+    /// The metadata marker was not found, so this is not a MaxMind DB file.
+    MissingMetadata,
+    /// A required metadata field is missing or has the wrong type.
+    InvalidMetadata(&'static str),
+    /// The search tree or data section is corrupt, or too large to write.
+    InvalidDatabase(&'static str),
+    /// An IPv6 range was inserted into an IPv4 database.
+    UnsupportedRange,
+}
+
+impl fmt::Display for MmdbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MmdbError::MissingMetadata => write!(f, "not a MaxMind DB file"),
+            MmdbError::InvalidMetadata(field) => {
+                write!(f, "invalid MaxMind DB metadata: {}", field)
+            }
+            MmdbError::InvalidDatabase(reason) => write!(f, "corrupt MaxMind DB: {}", reason),
+            MmdbError::UnsupportedRange => write!(f, "IPv6 range in an IPv4 MaxMind DB"),
+        }
+    }
+}
+
+impl Error for MmdbError {}
+
+/// The metadata of a database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub node_count: u32,
+    /// Bits per record, i.e. per branch of a node: 24, 28 or 32.
+    pub record_size: u16,
+    /// 4 for IPv4-only databases, 6 for databases holding both families.
+    pub ip_version: u16,
+    /// E.g. `GeoLite2-Country`.
+    pub database_type: String,
+    pub languages: Vec<String>,
+    /// Descriptions keyed by language.
+    pub description: BTreeMap<String, String>,
+    pub binary_format_major_version: u16,
+    pub binary_format_minor_version: u16,
+    /// Seconds since the Unix epoch.
+    pub build_epoch: u64,
+}
+
+impl Metadata {
+    fn from_value(value: &MmdbValue) -> Result<Self, MmdbError> {
+        let uint = |key: &'static str| {
+            value
+                .get(key)
+                .and_then(MmdbValue::as_u64)
+                .ok_or(MmdbError::InvalidMetadata(key))
+        };
+        let small = |key: &'static str| -> Result<u16, MmdbError> {
+            uint(key)?
+                .try_into()
+                .map_err(|_| MmdbError::InvalidMetadata(key))
+        };
+        let strings = |key| match value.get(key) {
+            Some(MmdbValue::Array(items)) => items
+                .iter()
+                .filter_map(|item| item.as_str().map(str::to_owned))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let description = match value.get("description") {
+            Some(MmdbValue::Map(map)) => map
+                .iter()
+                .filter_map(|(lang, text)| Some((lang.clone(), text.as_str()?.to_owned())))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        let metadata = Metadata {
+            node_count: uint("node_count")?
+                .try_into()
+                .map_err(|_| MmdbError::InvalidMetadata("node_count"))?,
+            record_size: small("record_size")?,
+            ip_version: small("ip_version")?,
+            database_type: value
+                .get("database_type")
+                .and_then(MmdbValue::as_str)
+                .unwrap_or_default()
+                .to_owned(),
+            languages: strings("languages"),
+            description,
+            binary_format_major_version: small("binary_format_major_version")?,
+            binary_format_minor_version: small("binary_format_minor_version").unwrap_or(0),
+            build_epoch: uint("build_epoch").unwrap_or(0),
+        };
+        if ![24, 28, 32].contains(&metadata.record_size) {
+            return Err(MmdbError::InvalidMetadata("record_size"));
+        }
+        if ![4, 6].contains(&metadata.ip_version) {
+            return Err(MmdbError::InvalidMetadata("ip_version"));
+        }
+        if metadata.binary_format_major_version != 2 {
+            return Err(MmdbError::InvalidMetadata("binary_format_major_version"));
+        }
+        Ok(metadata)
+    }
+
+    fn to_value(&self) -> MmdbValue {
+        let string = |s: &str| MmdbValue::String(s.to_owned());
+        MmdbValue::Map(BTreeMap::from([
+            ("node_count".to_owned(), MmdbValue::Uint32(self.node_count)),
+            (
+                "record_size".to_owned(),
+                MmdbValue::Uint16(self.record_size),
+            ),
+            ("ip_version".to_owned(), MmdbValue::Uint16(self.ip_version)),
+            ("database_type".to_owned(), string(&self.database_type)),
+            (
+                "languages".to_owned(),
+                MmdbValue::Array(self.languages.iter().map(|lang| string(lang)).collect()),
+            ),
+            (
+                "description".to_owned(),
+                MmdbValue::Map(
+                    self.description
+                        .iter()
+                        .map(|(lang, text)| (lang.clone(), string(text)))
+                        .collect(),
+                ),
+            ),
+            (
+                "binary_format_major_version".to_owned(),
+                MmdbValue::Uint16(self.binary_format_major_version),
+            ),
+            (
+                "binary_format_minor_version".to_owned(),
+                MmdbValue::Uint16(self.binary_format_minor_version),
+            ),
+            (
+                "build_epoch".to_owned(),
+                MmdbValue::Uint64(self.build_epoch),
+            ),
+        ]))
+    }
+}
+
+/// A database loaded into memory.
+#[derive(Debug, Clone)]
+pub struct MmdbReader {
+    buf: Vec<u8>,
+    metadata: Metadata,
+    /// Where the data section starts and ends in `buf`.
+    data_section: (usize, usize),
+    /// The node of `::/96` in IPv6 databases, the root in IPv4 ones.
+    ipv4_start: u32,
+}
+
+impl MmdbReader {
+    /// Load a database from the contents of a `.mmdb` file.
+    pub fn from_bytes(buf: Vec<u8>) -> Result<Self, MmdbError> {
+        let marker = buf
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or(MmdbError::MissingMetadata)?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let (value, _) = Decoder::new(&buf[metadata_start..]).decode(0, 0)?;
+        let metadata = Metadata::from_value(&value)?;
+
+        let tree_size = metadata.node_count as usize * metadata.record_size as usize / 4;
+        let data_start = tree_size + DATA_SECTION_SEPARATOR;
+        if data_start > marker {
+            return Err(MmdbError::InvalidDatabase("search tree overlaps metadata"));
+        }
+        let mut reader = MmdbReader {
+            buf,
+            metadata,
+            data_section: (data_start, marker),
+            ipv4_start: 0,
+        };
+        if reader.metadata.ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= reader.metadata.node_count {
+                    break;
+                }
+                node = reader.record(node, 0);
+            }
+            reader.ipv4_start = node;
+        }
+        Ok(reader)
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// The data of the network containing `addr`, if any.
+    ///
+    /// IPv4 addresses are looked up under `::/96` in IPv6 databases. IPv6
+    /// addresses are not found in IPv4 databases.
+    pub fn lookup(&self, addr: IpAddr) -> Result<Option<MmdbValue>, MmdbError> {
+        let (mut node, bits, len) = match addr {
+            IpAddr::V4(addr) => (self.ipv4_start, u32::from(addr) as u128, 32),
+            IpAddr::V6(_) if self.metadata.ip_version == 4 => return Ok(None),
+            IpAddr::V6(addr) => (0, u128::from(addr), 128),
+        };
+        let node_count = self.metadata.node_count;
+        for i in (0..len).rev() {
+            if node >= node_count {
+                break;
+            }
+            node = self.record(node, (bits >> i) as usize & 1);
+        }
+        match node {
+            n if n < node_count => Err(MmdbError::InvalidDatabase("search tree too deep")),
+            n if n == node_count => Ok(None),
+            n => self.resolve(n).map(Some),
+        }
+    }
+
+    /// Iterate over all networks with data, in address order.
+    pub fn networks(&self) -> Networks<'_> {
+        let bit_len = if self.metadata.ip_version == 4 {
+            32
+        } else {
+            128
+        };
+        Networks {
+            reader: self,
+            stack: vec![(0, 0, 0)],
+            bit_len,
+            visited: 0,
+        }
+    }
+
+    /// The given branch of `node`: 0 for left, 1 for right.
+    fn record(&self, node: u32, branch: usize) -> u32 {
+        let record_size = self.metadata.record_size as usize;
+        let offset = node as usize * record_size / 4;
+        let b = &self.buf[offset..offset + record_size / 4];
+        let be = |bytes: &[u8]| bytes.iter().fold(0, |n, &b| n << 8 | b as u32);
+        match (record_size, branch) {
+            (24, 0) => be(&b[0..3]),
+            (24, _) => be(&b[3..6]),
+            (28, 0) => (b[3] as u32 & 0xf0) << 20 | be(&b[0..3]),
+            (28, _) => (b[3] as u32 & 0x0f) << 24 | be(&b[4..7]),
+            (_, 0) => be(&b[0..4]),
+            (_, _) => be(&b[4..8]),
+        }
+    }
+
+    /// Decode the data a record beyond the node count points to.
+    fn resolve(&self, record: u32) -> Result<MmdbValue, MmdbError> {
+        let offset = ((record - self.metadata.node_count) as usize)
+            .checked_sub(DATA_SECTION_SEPARATOR)
+            .ok_or(MmdbError::InvalidDatabase(
+                "record points into the separator",
+            ))?;
+        let (start, end) = self.data_section;
+        Decoder::new(&self.buf[start..end])
+            .decode(offset, 0)
+            .map(|(value, _)| value)
+    }
+}
+
+/// Iterator over the networks of a database, created by [`MmdbReader::networks`].
+#[derive(Debug, Clone)]
+pub struct Networks<'a> {
+    reader: &'a MmdbReader,
+    /// Nodes left to visit, with the address bits and depth leading there.
+    stack: Vec<(u32, u128, u32)>,
+    bit_len: u32,
+    /// Nodes visited so far. A tree has no more than `node_count`; a file
+    /// whose nodes share children would have the walk visit exponentially
+    /// many.
+    visited: u32,
+}
+
+impl Iterator for Networks<'_> {
+    type Item = Result<(EitherIpRange, MmdbValue), MmdbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node_count = self.reader.metadata.node_count;
+        while let Some((node, bits, depth)) = self.stack.pop() {
+            if node < node_count {
+                let aliases_ipv4 = self.bit_len == 128
+                    && node == self.reader.ipv4_start
+                    && (bits, depth) != (0, 96)
+                    && depth > 0;
+                if aliases_ipv4 {
+                    continue;
+                }
+                self.visited += 1;
+                if self.visited > node_count {
+                    self.stack.clear();
+                    return Some(Err(MmdbError::InvalidDatabase(
+                        "search tree nodes are shared",
+                    )));
+                }
+                if depth == self.bit_len {
+                    self.stack.clear();
+                    return Some(Err(MmdbError::InvalidDatabase("search tree too deep")));
+                }
+                let right = bits | 1 << (self.bit_len - depth - 1);
+                self.stack
+                    .push((self.reader.record(node, 1), right, depth + 1));
+                self.stack
+                    .push((self.reader.record(node, 0), bits, depth + 1));
+            } else if node > node_count {
+                let range = if self.bit_len == 32 {
+                    EitherIpRange::V4(Ipv4Range::from_cidr_pair((
+                        Ipv4Addr::from(bits as u32),
+                        depth as u8,
+                    )))
+                } else {
+                    EitherIpRange::V6(Ipv6Range::from_cidr_pair((
+                        Ipv6Addr::from(bits),
+                        depth as u8,
+                    )))
+                };
+                return Some(self.reader.resolve(node).map(|value| (range, value)));
+            }
+        }
+        None
+    }
+}
+
+/// Reads values from the data or metadata section.
+struct Decoder<'a> {
+    data: &'a [u8],
+    /// How many more values may be decoded, counting down from
+    /// [`MAX_DATA_VALUES`].
+    values_left: Cell<usize>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Decoder {
+            data,
+            values_left: Cell::new(MAX_DATA_VALUES),
+        }
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8], MmdbError> {
+        self.data
+            .get(offset..offset + len)
+            .ok_or(MmdbError::InvalidDatabase("data runs past the end"))
+    }
+
+    fn uint(&self, offset: usize, len: usize) -> Result<u128, MmdbError> {
+        Ok(self
+            .bytes(offset, len)?
+            .iter()
+            .fold(0, |n, &b| n << 8 | b as u128))
+    }
+
+    /// Decode the value at `offset`, returning it and the offset following it.
+    fn decode(&self, offset: usize, depth: usize) -> Result<(MmdbValue, usize), MmdbError> {
+        if depth > MAX_DATA_DEPTH {
+            return Err(MmdbError::InvalidDatabase("data nested too deeply"));
+        }
+        let values_left =
+            self.values_left
+                .get()
+                .checked_sub(1)
+                .ok_or(MmdbError::InvalidDatabase(
+                    "data expands to too many values",
+                ))?;
+        self.values_left.set(values_left);
+        let ctrl = self.bytes(offset, 1)?[0];
+        let mut offset = offset + 1;
+        let mut type_num = u16::from(ctrl >> 5);
+        if type_num == 1 {
+            let high = (ctrl & 0x07) as usize;
+            let (pointer, len) = match (ctrl >> 3) & 0x03 {
+                0 => (high << 8 | self.uint(offset, 1)? as usize, 1),
+                1 => ((high << 16 | self.uint(offset, 2)? as usize) + 2048, 2),
+                2 => ((high << 24 | self.uint(offset, 3)? as usize) + 526336, 3),
+                _ => (self.uint(offset, 4)? as usize, 4),
+            };
+            if self.bytes(pointer, 1)?[0] >> 5 == 1 {
+                return Err(MmdbError::InvalidDatabase("pointer to a pointer"));
+            }
+            let (value, _) = self.decode(pointer, depth + 1)?;
+            return Ok((value, offset + len));
+        }
+        if type_num == 0 {
+            // Widened, as extended types past 255 are not defined
+            type_num = 7 + u16::from(self.bytes(offset, 1)?[0]);
+            offset += 1;
+        }
+        let size = match ctrl & 0x1f {
+            29 => {
+                offset += 1;
+                29 + self.uint(offset - 1, 1)? as usize
+            }
+            30 => {
+                offset += 2;
+                285 + self.uint(offset - 2, 2)? as usize
+            }
+            31 => {
+                offset += 3;
+                65821 + self.uint(offset - 3, 3)? as usize
+            }
+            size => size as usize,
+        };
+        let fixed = |max_len: usize| {
+            if size > max_len {
+                Err(MmdbError::InvalidDatabase("integer too wide"))
+            } else {
+                self.uint(offset, size)
+            }
+        };
+        let value = match type_num {
+            2 => MmdbValue::String(
+                String::from_utf8(self.bytes(offset, size)?.to_vec())
+                    .map_err(|_| MmdbError::InvalidDatabase("string is not UTF-8"))?,
+            ),
+            3 if size == 8 => MmdbValue::Double(f64::from_bits(fixed(8)? as u64)),
+            4 => MmdbValue::Bytes(self.bytes(offset, size)?.to_vec()),
+            5 => MmdbValue::Uint16(fixed(2)? as u16),
+            6 => MmdbValue::Uint32(fixed(4)? as u32),
+            7 => {
+                let mut map = BTreeMap::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(offset, depth + 1)?;
+                    let MmdbValue::String(key) = key else {
+                        return Err(MmdbError::InvalidDatabase("map key is not a string"));
+                    };
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    map.insert(key, value);
+                    offset = next;
+                }
+                return Ok((MmdbValue::Map(map), offset));
+            }
+            8 => MmdbValue::Int32(fixed(4)? as u32 as i32),
+            9 => MmdbValue::Uint64(fixed(8)? as u64),
+            10 => MmdbValue::Uint128(fixed(16)?),
+            11 => {
+                let mut items = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    let (item, next) = self.decode(offset, depth + 1)?;
+                    items.push(item);
+                    offset = next;
+                }
+                return Ok((MmdbValue::Array(items), offset));
+            }
+            14 if size <= 1 => return Ok((MmdbValue::Boolean(size == 1), offset)),
+            15 if size == 4 => MmdbValue::Float(f32::from_bits(fixed(4)? as u32)),
+            _ => return Err(MmdbError::InvalidDatabase("unknown data type")),
+        };
+        Ok((value, offset + size))
+    }
+}
+
+/// Builds a database from ranges and the data to store for them.
+///
+/// Ranges are split into CIDR blocks, and a block inserted inside or over
+/// earlier ones takes precedence where they overlap. Identical data is stored
+/// once however many ranges share it.
+///
+/// ```
+/// use std::collections::{BTreeMap, HashMap};
+/// use cidr_aggregator::formats::mmdb::{MmdbReader, MmdbValue, MmdbWriter};
+///
+/// let mut writer = MmdbWriter::ipv6();
+/// writer.database_type = "Blocklist".into();
+/// let data = MmdbValue::Map(BTreeMap::from([(
+///     "list".to_owned(),
+///     MmdbValue::String("spamhaus-drop".into()),
+/// )]));
+/// writer.insert("192.0.2.0/24".parse()?, &data)?;
+/// writer.insert("2001:db8::/32".parse()?, &data)?;
+///
+/// let reader = MmdbReader::from_bytes(writer.to_bytes()?)?;
+/// assert_eq!(reader.lookup("192.0.2.1".parse()?)?, Some(data.clone()));
+/// assert_eq!(reader.lookup("::ffff:192.0.2.1".parse()?)?, Some(data));
+/// assert_eq!(reader.lookup("198.51.100.1".parse()?)?, None);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MmdbWriter {
+    ip_version: u16,
+    /// E.g. `GeoLite2-Country`; empty by default.
+    pub database_type: String,
+    pub languages: Vec<String>,
+    /// Descriptions keyed by language.
+    pub description: BTreeMap<String, String>,
+    /// Seconds since the Unix epoch; 0 by default.
+    pub build_epoch: u64,
+    /// Bits per record: 24, 28 or 32. The smallest that fits is used if `None`.
+    pub record_size: Option<u16>,
+    /// Whether, in IPv6 databases, the IPv4-mapped `::ffff:0:0/96` and 6to4
+    /// `2002::/16` subtrees alias `::/96`, so that IPv4 addresses are found in
+    /// any of those forms. Either is left alone if ranges were inserted in it.
+    /// On by default.
+    pub alias_ipv4: bool,
+    nodes: Vec<[Record; 2]>,
+    data: Vec<u8>,
+    /// Offsets of the values already in `data`, by their encoding.
+    offsets: HashMap<Vec<u8>, u32>,
+}
+
+/// A branch of a node of a tree being built.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Record {
+    Empty,
+    Node(u32),
+    /// An offset into the data section.
+    Data(u32),
+}
+
+impl MmdbWriter {
+    /// A database of IPv4 ranges only.
+    pub fn ipv4() -> Self {
+        Self::new(4)
+    }
+
+    /// A database of IPv6 ranges, with IPv4 ranges under `::/96`.
+    pub fn ipv6() -> Self {
+        Self::new(6)
+    }
+
+    fn new(ip_version: u16) -> Self {
+        MmdbWriter {
+            ip_version,
+            database_type: String::new(),
+            languages: Vec::new(),
+            description: BTreeMap::new(),
+            build_epoch: 0,
+            record_size: None,
+            alias_ipv4: true,
+            nodes: vec![[Record::Empty; 2]],
+            data: Vec::new(),
+            offsets: HashMap::new(),
+        }
+    }
+
+    fn bit_len(&self) -> u32 {
+        if self.ip_version == 4 {
+            32
+        } else {
+            128
+        }
+    }
+
+    /// Store `data` for every address of `range`.
+    ///
+    /// Fails with [`MmdbError::UnsupportedRange`] for IPv6 ranges in IPv4
+    /// databases.
+    pub fn insert(&mut self, range: EitherIpRange, data: &MmdbValue) -> Result<(), MmdbError> {
+        let blocks: Vec<(u128, u32)> = match range {
+            EitherIpRange::V4(range) => {
+                let offset = if self.ip_version == 4 { 0 } else { 96 };
+                vec![range]
+                    .normalized()
+                    .into_iter()
+                    .map(|block| {
+                        let (first, last) = block.into_cidr_pair_decimal();
+                        (first as u128, 32 - (last - first).count_ones() + offset)
+                    })
+                    .collect()
+            }
+            EitherIpRange::V6(_) if self.ip_version == 4 => {
+                return Err(MmdbError::UnsupportedRange)
+            }
+            EitherIpRange::V6(range) => vec![range]
+                .normalized()
+                .into_iter()
+                .map(|block| {
+                    let (first, last) = block.into_cidr_pair_decimal();
+                    (first, 128 - (last - first).count_ones())
+                })
+                .collect(),
+        };
+        let mut encoded = Vec::new();
+        encode(data, &mut encoded)?;
+        let offset = match self.offsets.get(&encoded) {
+            Some(&offset) => offset,
+            None => {
+                let offset = self.data.len() as u32;
+                self.data.extend_from_slice(&encoded);
+                self.offsets.insert(encoded, offset);
+                offset
+            }
+        };
+        for (bits, prefix_len) in blocks {
+            self.insert_block(bits, prefix_len, Record::Data(offset));
+        }
+        Ok(())
+    }
+
+    /// Set the records of the block `bits/prefix_len`, with `bits` aligned to
+    /// the top of an address.
+    fn insert_block(&mut self, bits: u128, prefix_len: u32, record: Record) {
+        if prefix_len == 0 {
+            // The root is a node, so cover both its halves
+            self.insert_block(0, 1, record);
+            self.insert_block(1 << (self.bit_len() - 1), 1, record);
+            return;
+        }
+        let bit_len = self.bit_len();
+        let mut node = 0;
+        for depth in 0..prefix_len - 1 {
+            let branch = (bits >> (bit_len - depth - 1)) as usize & 1;
+            node = match self.nodes[node][branch] {
+                Record::Node(next) => next as usize,
+                // Split an empty or data record into a node with the same on both sides
+                other => {
+                    self.nodes.push([other; 2]);
+                    let next = self.nodes.len() - 1;
+                    self.nodes[node][branch] = Record::Node(next as u32);
+                    next
+                }
+            };
+        }
+        let branch = (bits >> (bit_len - prefix_len)) as usize & 1;
+        self.nodes[node][branch] = record;
+    }
+
+    /// Point the record of the block `bits/prefix_len` to the node of
+    /// `::/96`, unless ranges were inserted there.
+    fn alias(nodes: &mut Vec<[Record; 2]>, ipv4_start: u32, bits: u128, prefix_len: u32) {
+        let branch = |depth: u32| (bits >> (127 - depth)) as usize & 1;
+        let mut node = 0;
+        let mut depth = 0;
+        while depth < prefix_len - 1 {
+            match nodes[node][branch(depth)] {
+                Record::Node(next) => node = next as usize,
+                Record::Empty => break,
+                Record::Data(_) => return,
+            }
+            depth += 1;
+        }
+        if depth == prefix_len - 1 && nodes[node][branch(depth)] != Record::Empty {
+            return;
+        }
+        while depth < prefix_len - 1 {
+            nodes.push([Record::Empty; 2]);
+            let next = nodes.len() - 1;
+            nodes[node][branch(depth)] = Record::Node(next as u32);
+            node = next;
+            depth += 1;
+        }
+        nodes[node][branch(depth)] = Record::Node(ipv4_start);
+    }
+
+    /// Serialize the database into the contents of a `.mmdb` file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MmdbError> {
+        let mut nodes = self.nodes.clone();
+        if self.ip_version == 6 && self.alias_ipv4 {
+            let mut ipv4_start = Record::Node(0);
+            for _ in 0..96 {
+                ipv4_start = match ipv4_start {
+                    Record::Node(node) => nodes[node as usize][0],
+                    _ => break,
+                };
+            }
+            if let Record::Node(ipv4_start) = ipv4_start {
+                Self::alias(&mut nodes, ipv4_start, 0xffff << 32, 96);
+                Self::alias(&mut nodes, ipv4_start, 0x2002 << 112, 16);
+            }
+        }
+
+        // Number the nodes reachable from the root breadth first, dropping
+        // those orphaned by inserting blocks over them
+        let mut ids = vec![None; nodes.len()];
+        let mut order = vec![0];
+        ids[0] = Some(0);
+        let mut i = 0;
+        while i < order.len() {
+            for record in nodes[order[i]] {
+                if let Record::Node(node) = record {
+                    let node = node as usize;
+                    if ids[node].is_none() {
+                        ids[node] = Some(order.len() as u32);
+                        order.push(node);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let node_count = order.len() as u32;
+        let too_large = MmdbError::InvalidDatabase("too large for 32-bit records");
+        let max_record = (node_count as u64 + DATA_SECTION_SEPARATOR as u64)
+            .checked_add(self.data.len() as u64)
+            .ok_or(too_large)?;
+        let fits = |record_size: u16| max_record < 1 << record_size;
+        let record_size = match self.record_size {
+            Some(record_size @ (24 | 28 | 32)) if fits(record_size) => record_size,
+            Some(24 | 28 | 32) => return Err(too_large),
+            Some(_) => return Err(MmdbError::InvalidMetadata("record_size")),
+            None => *[24, 28, 32]
+                .iter()
+                .find(|&&record_size| fits(record_size))
+                .ok_or(too_large)?,
+        };
+
+        let value = |record: Record| match record {
+            Record::Empty => node_count,
+            Record::Node(node) => ids[node as usize].unwrap_or(node_count),
+            Record::Data(offset) => node_count + DATA_SECTION_SEPARATOR as u32 + offset,
+        };
+        let mut buf = Vec::with_capacity(
+            order.len() * record_size as usize / 4 + DATA_SECTION_SEPARATOR + self.data.len(),
+        );
+        for &node in &order {
+            let [left, right] = nodes[node].map(value);
+            let (left_bytes, right_bytes) = (left.to_be_bytes(), right.to_be_bytes());
+            match record_size {
+                24 => {
+                    buf.extend_from_slice(&left_bytes[1..]);
+                    buf.extend_from_slice(&right_bytes[1..]);
+                }
+                28 => {
+                    buf.extend_from_slice(&left_bytes[1..]);
+                    buf.push(left_bytes[0] << 4 | right_bytes[0]);
+                    buf.extend_from_slice(&right_bytes[1..]);
+                }
+                _ => {
+                    buf.extend_from_slice(&left_bytes);
+                    buf.extend_from_slice(&right_bytes);
+                }
+            }
+        }
+        buf.extend_from_slice(&[0; DATA_SECTION_SEPARATOR]);
+        buf.extend_from_slice(&self.data);
+        buf.extend_from_slice(METADATA_MARKER);
+        let metadata = Metadata {
+            node_count,
+            record_size,
+            ip_version: self.ip_version,
+            database_type: self.database_type.clone(),
+            languages: self.languages.clone(),
+            description: self.description.clone(),
+            binary_format_major_version: 2,
+            binary_format_minor_version: 0,
+            build_epoch: self.build_epoch,
+        };
+        encode(&metadata.to_value(), &mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Append the encoding of `value` to `out`, without pointers.
+fn encode(value: &MmdbValue, out: &mut Vec<u8>) -> Result<(), MmdbError> {
+    fn header(out: &mut Vec<u8>, type_num: u8, size: usize) -> Result<(), MmdbError> {
+        let (size_bits, extra): (u8, &[u8]) = match size {
+            0..=28 => (size as u8, &[]),
+            29..=284 => (29, &[(size - 29) as u8]),
+            285..=65820 => (30, &((size - 285) as u16).to_be_bytes()),
+            65821..=16843036 => (31, &((size - 65821) as u32).to_be_bytes()[1..]),
+            _ => return Err(MmdbError::InvalidDatabase("value too large")),
+        };
+        if type_num < 8 {
+            out.push(type_num << 5 | size_bits);
+        } else {
+            out.extend_from_slice(&[size_bits, type_num - 7]);
+        }
+        out.extend_from_slice(extra);
+        Ok(())
+    }
+    fn uint(out: &mut Vec<u8>, type_num: u8, n: u128) -> Result<(), MmdbError> {
+        let bytes = n.to_be_bytes();
+        let skip = (n.leading_zeros() / 8) as usize;
+        header(out, type_num, 16 - skip)?;
+        out.extend_from_slice(&bytes[skip..]);
+        Ok(())
+    }
+
+    match value {
+        MmdbValue::String(s) => {
+            header(out, 2, s.len())?;
+            out.extend_from_slice(s.as_bytes());
+        }
+        MmdbValue::Double(n) => {
+            header(out, 3, 8)?;
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+        MmdbValue::Bytes(bytes) => {
+            header(out, 4, bytes.len())?;
+            out.extend_from_slice(bytes);
+        }
+        MmdbValue::Uint16(n) => uint(out, 5, *n as u128)?,
+        MmdbValue::Uint32(n) => uint(out, 6, *n as u128)?,
+        MmdbValue::Map(map) => {
+            header(out, 7, map.len())?;
+            for (key, value) in map {
+                header(out, 2, key.len())?;
+                out.extend_from_slice(key.as_bytes());
+                encode(value, out)?;
+            }
+        }
+        MmdbValue::Int32(n) if *n < 0 => {
+            header(out, 8, 4)?;
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+        MmdbValue::Int32(n) => uint(out, 8, *n as u128)?,
+        MmdbValue::Uint64(n) => uint(out, 9, *n as u128)?,
+        MmdbValue::Uint128(n) => uint(out, 10, *n)?,
+        MmdbValue::Array(items) => {
+            header(out, 11, items.len())?;
+            for item in items {
+                encode(item, out)?;
+            }
+        }
+        MmdbValue::Boolean(b) => header(out, 14, *b as usize)?,
+        MmdbValue::Float(n) => {
+            header(out, 15, 4)?;
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_entry, ParseOptions};
+
+    fn string(s: &str) -> MmdbValue {
+        MmdbValue::String(s.to_owned())
+    }
+
+    fn country(code: &str) -> MmdbValue {
+        MmdbValue::Map(BTreeMap::from([(
+            "country".to_owned(),
+            MmdbValue::Map(BTreeMap::from([("iso_code".to_owned(), string(code))])),
+        )]))
+    }
+
+    fn range(s: &str) -> EitherIpRange {
+        parse_entry(s, &ParseOptions::default()).unwrap().0
+    }
+
+    fn networks(reader: &MmdbReader) -> Vec<(EitherIpRange, MmdbValue)> {
+        reader.networks().collect::<Result<_, _>>().unwrap()
+    }
+
+    fn ipv4_database(record_size: u16) -> MmdbReader {
+        let mut writer = MmdbWriter::ipv4();
+        writer.database_type = "Test-DB".into();
+        writer.languages = vec!["en".into()];
+        writer.description = BTreeMap::from([("en".into(), "test database".into())]);
+        writer.build_epoch = 1_700_000_000;
+        writer.record_size = Some(record_size);
+        writer.insert(range("10.0.0.0/8"), &country("AU")).unwrap();
+        writer
+            .insert(range("192.168.1.0/24"), &country("CN"))
+            .unwrap();
+        MmdbReader::from_bytes(writer.to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn metadata() {
+        let reader = ipv4_database(24);
+        let metadata = reader.metadata();
+        assert_eq!(metadata.ip_version, 4);
+        assert_eq!(metadata.database_type, "Test-DB");
+        assert_eq!(metadata.languages, ["en"]);
+        assert_eq!(metadata.description["en"], "test database");
+        assert_eq!(metadata.build_epoch, 1_700_000_000);
+        assert_eq!(metadata.binary_format_major_version, 2);
+    }
+
+    #[test]
+    fn ipv4_round_trip_for_every_record_size() {
+        for record_size in [24, 28, 32] {
+            let reader = ipv4_database(record_size);
+            assert_eq!(reader.metadata().record_size, record_size);
+            assert_eq!(
+                networks(&reader),
+                [
+                    (range("10.0.0.0/8"), country("AU")),
+                    (range("192.168.1.0/24"), country("CN")),
+                ]
+            );
+            let lookup = |addr: &str| reader.lookup(addr.parse().unwrap()).unwrap();
+            assert_eq!(lookup("10.1.2.3"), Some(country("AU")));
+            assert_eq!(lookup("192.168.2.1"), None);
+            assert_eq!(lookup("::1"), None);
+        }
+    }
+
+    #[test]
+    fn ranges_are_split_and_overwritten() {
+        let mut writer = MmdbWriter::ipv4();
+        writer
+            .insert(range("10.0.0.1-10.0.0.6"), &string("a"))
+            .unwrap();
+        writer.insert(range("10.0.0.4/31"), &string("b")).unwrap();
+        // Inserting over a subtree orphans its nodes
+        writer.insert(range("172.16.1.0/24"), &string("c")).unwrap();
+        writer.insert(range("172.16.0.0/12"), &string("d")).unwrap();
+        let reader = MmdbReader::from_bytes(writer.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            networks(&reader),
+            [
+                (range("10.0.0.1/32"), string("a")),
+                (range("10.0.0.2/31"), string("a")),
+                (range("10.0.0.4/31"), string("b")),
+                (range("10.0.0.6/32"), string("a")),
+                (range("172.16.0.0/12"), string("d")),
+            ]
+        );
+        // 33 nodes down to 10.0.0.x and 11 more to 172.16.0.0/12, none for
+        // 172.16.1.0/24
+        assert_eq!(reader.metadata().node_count, 45);
+    }
+
+    #[test]
+    fn whole_address_space() {
+        let mut writer = MmdbWriter::ipv4();
+        writer.insert(range("0.0.0.0/0"), &string("all")).unwrap();
+        let reader = MmdbReader::from_bytes(writer.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            networks(&reader),
+            [
+                (range("0.0.0.0/1"), string("all")),
+                (range("128.0.0.0/1"), string("all")),
+            ]
+        );
+        assert_eq!(
+            writer.insert(range("::/0"), &string("all")),
+            Err(MmdbError::UnsupportedRange)
+        );
+    }
+
+    #[test]
+    fn identical_data_is_stored_once() {
+        let mut writer = MmdbWriter::ipv4();
+        let data = country("DE");
+        writer.insert(range("10.0.0.0/8"), &data).unwrap();
+        let size = writer.to_bytes().unwrap().len();
+        // 11.0.0.0/8 differs from 10.0.0.0/8 only in the last bit, so takes
+        // neither nodes nor data
+        writer.insert(range("11.0.0.0/8"), &data).unwrap();
+        assert_eq!(writer.to_bytes().unwrap().len(), size);
+    }
+
+    #[test]
+    fn ipv6_aliases() {
+        let mut writer = MmdbWriter::ipv6();
+        writer.insert(range("10.0.0.0/8"), &country("AU")).unwrap();
+        writer
+            .insert(range("2001:db8::/32"), &country("CN"))
+            .unwrap();
+        let reader = MmdbReader::from_bytes(writer.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            networks(&reader),
+            [
+                (range("::a00:0/104"), country("AU")),
+                (range("2001:db8::/32"), country("CN")),
+            ],
+            "aliases of ::/96 must be visited only once"
+        );
+        let lookup = |addr: &str| reader.lookup(addr.parse().unwrap()).unwrap();
+        assert_eq!(lookup("10.0.0.1"), Some(country("AU")));
+        assert_eq!(lookup("::ffff:10.0.0.1"), Some(country("AU")));
+        assert_eq!(lookup("2002:a00:1::"), Some(country("AU")));
+        assert_eq!(lookup("2002:b00:1::"), None);
+
+        // Ranges of their own keep blocks from aliasing
+        writer.insert(range("2002::/16"), &country("NL")).unwrap();
+        let reader = MmdbReader::from_bytes(writer.to_bytes().unwrap()).unwrap();
+        let lookup = |addr: &str| reader.lookup(addr.parse().unwrap()).unwrap();
+        assert_eq!(lookup("::ffff:10.0.0.1"), Some(country("AU")));
+        assert_eq!(lookup("2002:a00:1::"), Some(country("NL")));
+
+        writer.alias_ipv4 = false;
+        let reader = MmdbReader::from_bytes(writer.to_bytes().unwrap()).unwrap();
+        assert_eq!(reader.lookup("::ffff:10.0.0.1".parse().unwrap()), Ok(None));
+    }
+
+    #[test]
+    fn value_round_trip() {
+        let values = [
+            string(""),
+            string(&"x".repeat(28)),
+            string(&"x".repeat(29)),
+            string(&"x".repeat(300)),
+            string(&"x".repeat(70000)),
+            MmdbValue::Double(1.5),
+            MmdbValue::Float(-2.5),
+            MmdbValue::Bytes(vec![1, 2]),
+            MmdbValue::Uint16(0),
+            MmdbValue::Uint16(u16::MAX),
+            MmdbValue::Uint32(256),
+            MmdbValue::Int32(-7),
+            MmdbValue::Int32(7),
+            MmdbValue::Uint64(u64::MAX),
+            MmdbValue::Uint128(u128::MAX),
+            MmdbValue::Boolean(true),
+            MmdbValue::Boolean(false),
+            MmdbValue::Array(vec![MmdbValue::Uint32(1), string("two")]),
+            country("FR"),
+        ];
+        for value in values {
+            let mut bytes = Vec::new();
+            encode(&value, &mut bytes).unwrap();
+            let (decoded, next) = Decoder::new(&bytes).decode(0, 0).unwrap();
+            assert_eq!(decoded, value);
+            assert_eq!(next, bytes.len());
+        }
+    }
+
+    #[test]
+    fn shared_nodes() {
+        // Chain the nodes with both records of each pointing to the next, so
+        // that every path leads through all of them
+        let mut reader = ipv4_database(24);
+        let node_count = reader.metadata.node_count;
+        assert!(node_count < 32);
+        for node in 0..node_count {
+            let next = (node + 1).to_be_bytes();
+            let offset = node as usize * 6;
+            reader.buf[offset..offset + 3].copy_from_slice(&next[1..]);
+            reader.buf[offset + 3..offset + 6].copy_from_slice(&next[1..]);
+        }
+        assert_eq!(
+            reader.networks().collect::<Vec<_>>(),
+            [Err(MmdbError::InvalidDatabase(
+                "search tree nodes are shared"
+            ))]
+        );
+    }
+
+    #[test]
+    fn pointers() {
+        // A map whose second key is a pointer to the first key
+        let mut bytes = Vec::new();
+        encode(
+            &MmdbValue::Map(BTreeMap::from([("a".into(), string("x"))])),
+            &mut bytes,
+        )
+        .unwrap();
+        bytes[0] = 7 << 5 | 2;
+        bytes.extend_from_slice(&[1 << 5, 1]);
+        encode(&string("y"), &mut bytes).unwrap();
+        let (value, next) = Decoder::new(&bytes).decode(0, 0).unwrap();
+        assert_eq!(next, bytes.len());
+        assert_eq!(value.get("a"), Some(&string("y")));
+
+        let loop_bytes = [1 << 5, 0];
+        assert_eq!(
+            Decoder::new(&loop_bytes).decode(0, 0),
+            Err(MmdbError::InvalidDatabase("pointer to a pointer"))
+        );
+
+        // 20 levels of arrays holding two pointers to the next level: shallow,
+        // but 2^20 values
+        let mut bomb = Vec::new();
+        for level in 1..=20 {
+            let next = level * 6;
+            let pointer = [1 << 5 | (next >> 8) as u8, next as u8];
+            bomb.extend_from_slice(&[2, 11 - 7]);
+            bomb.extend_from_slice(&pointer);
+            bomb.extend_from_slice(&pointer);
+        }
+        bomb.push(5 << 5);
+        assert_eq!(
+            Decoder::new(&bomb).decode(0, 0),
+            Err(MmdbError::InvalidDatabase(
+                "data expands to too many values"
+            ))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            MmdbReader::from_bytes(b"not a database".to_vec()).unwrap_err(),
+            MmdbError::MissingMetadata
+        );
+        let mut bytes = ipv4_database(24).buf;
+        let position = bytes
+            .windows(b"record_size".len())
+            .position(|window| window == b"record_size")
+            .unwrap();
+        // The value of record_size, a 1-byte uint16, follows its key
+        bytes[position + b"record_size".len() + 1] = 20;
+        assert_eq!(
+            MmdbReader::from_bytes(bytes).unwrap_err(),
+            MmdbError::InvalidMetadata("record_size")
+        );
+        let truncated = [6 << 5 | 4, 0, 0];
+        assert!(Decoder::new(&truncated).decode(0, 0).is_err());
+        for extended in [5, 0xff] {
+            assert_eq!(
+                Decoder::new(&[0, extended]).decode(0, 0),
+                Err(MmdbError::InvalidDatabase("unknown data type"))
+            );
+        }
+
+        let mut writer = MmdbWriter::ipv4();
+        writer.record_size = Some(20);
+        assert_eq!(
+            writer.to_bytes(),
+            Err(MmdbError::InvalidMetadata("record_size"))
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Readers for third-party IP range publications. Those needing extra
+//! dependencies are behind features.
+
+use std::io::{self, BufRead, Read};
+
+use crate::aggregator::Aggregator;
+use crate::parser::RecordError;
+use crate::{EitherIpRange, Ipv4Range, Ipv6Range};
+
+pub mod access_log;
+#[cfg(feature = "cloud-formats")]
+pub mod cloud;
+pub mod firewall;
+pub mod geoip;
+pub mod geoip_dat;
+pub mod mmdb;
+pub mod mrt;
+pub mod rir;
+pub mod rpki;
+pub mod rpsl;
+
+/// Undo gzip or bzip2 compression, detected by magic bytes, or pass the
+/// input through as is.
+///
+/// Decompression needs the `gzip` or `bzip2` feature respectively; without it
+/// compressed input is an [`io::ErrorKind::Unsupported`] error.
+pub fn decompress<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(b"\x1f\x8b") {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)));
+        #[cfg(not(feature = "gzip"))]
+        return Err(unsupported("gzip"));
+    }
+    if magic.starts_with(b"BZh") {
+        #[cfg(feature = "bzip2")]
+        return Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(reader)));
+        #[cfg(not(feature = "bzip2"))]
+        return Err(unsupported("bzip2"));
+    }
+    Ok(Box::new(reader))
+}
+
+/// Parse an AS number like `64496` or `AS64496`, with the `AS` prefix in any
+/// case.
+///
+/// Only decimal digits may follow the prefix, so unlike `u32::from_str` this
+/// rejects a sign.
+pub fn parse_asn(s: &str) -> Result<u32, RecordError> {
+    let digits = s
+        .get(..2)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("AS"))
+        .map_or(s, |_| &s[2..]);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(RecordError::InvalidAsn);
+    }
+    digits.parse().map_err(|_| RecordError::InvalidAsn)
+}
+
+/// Whether a filter list lets a field through: the list is empty, or holds
+/// one of the field's values, compared case-insensitively.
+pub(crate) fn any_of<'a, I: IntoIterator<Item = &'a str>>(wanted: &[String], values: I) -> bool {
+    wanted.is_empty()
+        || values
+            .into_iter()
+            .any(|value| wanted.iter().any(|w| w.eq_ignore_ascii_case(value)))
+}
+
+/// Split ranges by address family and aggregate each.
+pub(crate) fn split_by_family<I: IntoIterator<Item = EitherIpRange>>(
+    ranges: I,
+) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+    for range in ranges {
+        match range {
+            EitherIpRange::V4(range) => v4.push(range),
+            EitherIpRange::V6(range) => v6.push(range),
+        }
+    }
+    (v4.aggregated(), v6.aggregated())
+}
+
+#[cfg_attr(all(feature = "gzip", feature = "bzip2"), allow(dead_code))]
+fn unsupported(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("compressed input needs the `{}` feature", feature),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asn_prefix() {
+        assert_eq!(parse_asn("AS64496"), Ok(64496));
+        assert_eq!(parse_asn("as64496"), Ok(64496));
+        assert_eq!(parse_asn("4200000000"), Ok(4200000000));
+        assert_eq!(parse_asn("AS"), Err(RecordError::InvalidAsn));
+        assert_eq!(parse_asn("AS+1"), Err(RecordError::InvalidAsn));
+        assert_eq!(parse_asn("+1"), Err(RecordError::InvalidAsn));
+        assert_eq!(parse_asn("AS4294967296"), Err(RecordError::InvalidAsn));
+    }
+}
@@ -0,0 +1,549 @@
+//! BGP routing table dumps in the MRT format (RFC 6396), such as the `bview`
+//! and `rib` files of RIPE RIS and RouteViews.
+//!
+//! Only `TABLE_DUMP_V2` records are read: the peer index table, and the IPv4
+//! and IPv6 unicast RIB records, with or without ADD-PATH. Other records,
+//! such as `BGP4MP` updates, are skipped. Dumps are usually compressed; see
+//! [`decompress`](super::decompress).
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use cidr_aggregator::aggregator::Aggregator;
+//! use cidr_aggregator::formats::decompress;
+//! use cidr_aggregator::formats::mrt::{MrtReader, RouteFilter};
+//!
+//! let file = BufReader::new(File::open("bview.20240101.0000.gz")?);
+//! let filter = RouteFilter { origins: vec![13335] };
+//! let mut routes = Vec::new();
+//! for route in MrtReader::new(decompress(file)?) {
+//!     routes.extend(route?.ok());
+//! }
+//! let (v4, v6) = filter.collect(routes);
+//! println!("{}\n{}", v4.normalized().export(), v6.normalized().export());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::formats::split_by_family;
+use crate::{EitherIpRange, IpRange, Ipv4Range, Ipv6Range};
+
+const TABLE_DUMP_V2: u16 = 13;
+const PEER_INDEX_TABLE: u16 = 1;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+const RIB_IPV4_UNICAST_ADDPATH: u16 = 8;
+const RIB_IPV6_UNICAST_ADDPATH: u16 = 10;
+/// The BGP path attribute type of `AS_PATH`.
+const AS_PATH: u8 = 2;
+
+/// A segment of an AS path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AsPathSegment {
+    Sequence(Vec<u32>),
+    /// Unordered, as left by aggregation.
+    Set(Vec<u32>),
+    ConfedSequence(Vec<u32>),
+    ConfedSet(Vec<u32>),
+}
+
+/// The `AS_PATH` attribute of a route, nearest AS first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct AsPath(pub Vec<AsPathSegment>);
+
+impl AsPath {
+    /// The ASes the route may originate from: the last AS of the path, or
+    /// every member of a trailing `AS_SET`. Confederation segments are
+    /// ignored, and an empty path, from a locally originated route, has none.
+    pub fn origins(&self) -> &[u32] {
+        let last = self.0.iter().rev().find_map(|segment| match segment {
+            AsPathSegment::Sequence(asns) if !asns.is_empty() => Some(&asns[asns.len() - 1..]),
+            AsPathSegment::Set(asns) if !asns.is_empty() => Some(&asns[..]),
+            _ => None,
+        });
+        last.unwrap_or_default()
+    }
+}
+
+/// Formats like `bgpdump`, e.g. `64496 64497 {64498,64499}`.
+impl fmt::Display for AsPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |asns: &[u32], separator| {
+            asns.iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        let segments: Vec<String> = self
+            .0
+            .iter()
+            .map(|segment| match segment {
+                AsPathSegment::Sequence(asns) => join(asns, " "),
+                AsPathSegment::Set(asns) => format!("{{{}}}", join(asns, ",")),
+                AsPathSegment::ConfedSequence(asns) => format!("({})", join(asns, " ")),
+                AsPathSegment::ConfedSet(asns) => format!("[{}]", join(asns, ",")),
+            })
+            .collect();
+        f.write_str(&segments.join(" "))
+    }
+}
+
+/// A peer of the collector, from the peer index table.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Peer {
+    pub bgp_id: Ipv4Addr,
+    pub address: IpAddr,
+    pub asn: u32,
+}
+
+/// A route to a prefix as heard from one peer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Route {
+    pub prefix: EitherIpRange,
+    /// Index into [`MrtReader::peers`].
+    pub peer_index: u16,
+    /// When the route was received, in seconds since the Unix epoch.
+    pub originated_time: u32,
+    pub as_path: AsPath,
+}
+
+/// Selects routes by origin AS.
+///
+/// A route matches if any of its [`origins`](AsPath::origins) is listed. An
+/// empty list matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RouteFilter {
+    pub origins: Vec<u32>,
+}
+
+impl RouteFilter {
+    pub fn matches(&self, route: &Route) -> bool {
+        self.origins.is_empty()
+            || route
+                .as_path
+                .origins()
+                .iter()
+                .any(|asn| self.origins.contains(asn))
+    }
+
+    /// Keep the prefixes of matching routes, split them by address family
+    /// and aggregate.
+    pub fn collect<I: IntoIterator<Item = Route>>(
+        &self,
+        routes: I,
+    ) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+        split_by_family(
+            routes
+                .into_iter()
+                .filter(|route| self.matches(route))
+                .map(|route| route.prefix),
+        )
+    }
+}
+
+/// A malformed MRT record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MrtError {
+    /// 1-based number of the record in the input.
+    pub record: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for MrtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MRT record {}: {}", self.record, self.reason)
+    }
+}
+
+impl Error for MrtError {}
+
+/// Reads routes record by record from any [`Read`].
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of reading one route. A malformed record yields one [`MrtError`]
+/// and is skipped as a whole, as records are length-prefixed.
+#[derive(Debug)]
+pub struct MrtReader<R: Read> {
+    reader: R,
+    record: usize,
+    peers: Vec<Peer>,
+    /// Routes of the current RIB record not yet yielded, last first.
+    pending: Vec<Route>,
+    /// Set at the end of the input or after an I/O error.
+    done: bool,
+}
+
+impl<R: Read> MrtReader<R> {
+    pub fn new(reader: R) -> Self {
+        MrtReader {
+            reader,
+            record: 0,
+            peers: Vec::new(),
+            pending: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// The peers of the last peer index table read.
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers
+    }
+
+    /// Number of records read so far.
+    pub fn record_number(&self) -> usize {
+        self.record
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the next record, or `None` at the end of the input.
+    fn read_record(&mut self) -> io::Result<Option<(u16, u16, Vec<u8>)>> {
+        let mut header = [0; 12];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let kind = u16::from_be_bytes([header[4], header[5]]);
+        let subtype = u16::from_be_bytes([header[6], header[7]]);
+        let len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let mut body = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut body)?;
+        if body.len() < len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.record += 1;
+        Ok(Some((kind, subtype, body)))
+    }
+}
+
+impl<R: Read> Iterator for MrtReader<R> {
+    type Item = io::Result<Result<Route, MrtError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(route) = self.pending.pop() {
+                return Some(Ok(Ok(route)));
+            }
+            if self.done {
+                return None;
+            }
+            let (kind, subtype, body) = match self.read_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    // The reader is no longer at a record boundary
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            if kind != TABLE_DUMP_V2 {
+                continue;
+            }
+            let mut body = Bytes(&body);
+            let result = match subtype {
+                PEER_INDEX_TABLE => read_peer_index_table(&mut body).map(|peers| {
+                    self.peers = peers;
+                }),
+                RIB_IPV4_UNICAST | RIB_IPV6_UNICAST => {
+                    read_rib(&mut body, subtype == RIB_IPV6_UNICAST, false)
+                        .map(|routes| self.pending = routes)
+                }
+                RIB_IPV4_UNICAST_ADDPATH | RIB_IPV6_UNICAST_ADDPATH => {
+                    read_rib(&mut body, subtype == RIB_IPV6_UNICAST_ADDPATH, true)
+                        .map(|routes| self.pending = routes)
+                }
+                _ => Ok(()),
+            };
+            if let Err(reason) = result {
+                return Some(Ok(Err(MrtError {
+                    record: self.record,
+                    reason,
+                })));
+            }
+        }
+    }
+}
+
+/// A cursor over the body of a record.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        if len > self.0.len() {
+            return Err("record is truncated");
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u128(&mut self) -> Result<u128, &'static str> {
+        Ok(u128::from_be_bytes(self.take(16)?.try_into().unwrap()))
+    }
+}
+
+fn read_peer_index_table(body: &mut Bytes) -> Result<Vec<Peer>, &'static str> {
+    body.u32()?;
+    let view_name_len = body.u16()?;
+    body.take(view_name_len as usize)?;
+    let count = body.u16()?;
+    let mut peers = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let peer_type = body.u8()?;
+        let bgp_id = Ipv4Addr::from(body.u32()?);
+        let address = if peer_type & 0x01 != 0 {
+            IpAddr::V6(Ipv6Addr::from(body.u128()?))
+        } else {
+            IpAddr::V4(Ipv4Addr::from(body.u32()?))
+        };
+        let asn = if peer_type & 0x02 != 0 {
+            body.u32()?
+        } else {
+            body.u16()? as u32
+        };
+        peers.push(Peer {
+            bgp_id,
+            address,
+            asn,
+        });
+    }
+    Ok(peers)
+}
+
+/// Read the routes of a RIB record, returning them last first.
+fn read_rib(body: &mut Bytes, v6: bool, add_path: bool) -> Result<Vec<Route>, &'static str> {
+    body.u32()?;
+    let prefix_len = body.u8()?;
+    let bit_len = if v6 { 128 } else { 32 };
+    if prefix_len > bit_len {
+        return Err("prefix is too long");
+    }
+    let mut bits = [0; 16];
+    bits[..(prefix_len as usize).div_ceil(8)]
+        .copy_from_slice(body.take((prefix_len as usize).div_ceil(8))?);
+    let bits = u128::from_be_bytes(bits);
+    // Mask off anything past the prefix
+    let host_mask = u128::MAX.checked_shr(prefix_len as u32).unwrap_or(0);
+    let first = bits & !host_mask;
+    let prefix = if v6 {
+        EitherIpRange::V6(Ipv6Range::from_cidr_pair_decimal((
+            first,
+            first | host_mask,
+        )))
+    } else {
+        let first = (first >> 96) as u32;
+        EitherIpRange::V4(Ipv4Range::from_cidr_pair_decimal((
+            first,
+            first | (host_mask >> 96) as u32,
+        )))
+    };
+
+    let count = body.u16()?;
+    let mut routes = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let peer_index = body.u16()?;
+        let originated_time = body.u32()?;
+        if add_path {
+            body.u32()?;
+        }
+        let attributes_len = body.u16()?;
+        let mut attributes = Bytes(body.take(attributes_len as usize)?);
+        routes.push(Route {
+            prefix,
+            peer_index,
+            originated_time,
+            as_path: read_as_path(&mut attributes)?,
+        });
+    }
+    routes.reverse();
+    Ok(routes)
+}
+
+/// Find the `AS_PATH` among BGP path attributes; ASNs are always 4 bytes in
+/// `TABLE_DUMP_V2`.
+fn read_as_path(attributes: &mut Bytes) -> Result<AsPath, &'static str> {
+    while !attributes.0.is_empty() {
+        let flags = attributes.u8()?;
+        let kind = attributes.u8()?;
+        let len = if flags & 0x10 != 0 {
+            attributes.u16()? as usize
+        } else {
+            attributes.u8()? as usize
+        };
+        let mut value = Bytes(attributes.take(len)?);
+        if kind != AS_PATH {
+            continue;
+        }
+        let mut segments = Vec::new();
+        while !value.0.is_empty() {
+            let segment_type = value.u8()?;
+            let count = value.u8()?;
+            let asns = (0..count)
+                .map(|_| value.u32())
+                .collect::<Result<Vec<_>, _>>()?;
+            segments.push(match segment_type {
+                1 => AsPathSegment::Set(asns),
+                2 => AsPathSegment::Sequence(asns),
+                3 => AsPathSegment::ConfedSequence(asns),
+                4 => AsPathSegment::ConfedSet(asns),
+                _ => return Err("unknown AS path segment type"),
+            });
+        }
+        return Ok(AsPath(segments));
+    }
+    Ok(AsPath::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::Aggregator;
+
+    fn record(subtype: u16, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&0u32.to_be_bytes());
+        out.extend_from_slice(&TABLE_DUMP_V2.to_be_bytes());
+        out.extend_from_slice(&subtype.to_be_bytes());
+        out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    /// A RIB record for `prefix` with one entry of the given `AS_PATH` value.
+    fn rib(prefix: &[u8], as_path: &[u8]) -> Vec<u8> {
+        let mut body = vec![0, 0, 0, 0];
+        body.extend_from_slice(prefix);
+        body.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(&(as_path.len() as u16 + 4).to_be_bytes());
+        // AS_PATH with the extended length flag
+        body.extend_from_slice(&[0x50, AS_PATH]);
+        body.extend_from_slice(&(as_path.len() as u16).to_be_bytes());
+        body.extend_from_slice(as_path);
+        record(RIB_IPV4_UNICAST, &body)
+    }
+
+    fn read(input: &[u8]) -> Vec<Result<Route, MrtError>> {
+        MrtReader::new(input).collect::<io::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn as_path_origins() {
+        let path = AsPath(vec![
+            AsPathSegment::ConfedSequence(vec![65001]),
+            AsPathSegment::Sequence(vec![64496, 64497]),
+            AsPathSegment::Set(vec![64498, 64499]),
+            AsPathSegment::ConfedSet(vec![65002]),
+        ]);
+        assert_eq!(path.origins(), [64498, 64499]);
+        assert_eq!(
+            path.to_string(),
+            "(65001) 64496 64497 {64498,64499} [65002]"
+        );
+        let path = AsPath(vec![AsPathSegment::Sequence(vec![64496, 64497])]);
+        assert_eq!(path.origins(), [64497]);
+        assert!(AsPath::default().origins().is_empty());
+    }
+
+    #[test]
+    fn host_bits_are_masked() {
+        // 10.255.0.0/9 with a two-AS sequence
+        let input = rib(&[9, 10, 255], &[2, 2, 0, 0, 0xfb, 0xf0, 0, 0, 0x34, 0x17]);
+        let routes = read(&input);
+        let route = routes[0].as_ref().unwrap();
+        assert_eq!(route.prefix, "10.128.0.0/9".parse().unwrap());
+        assert_eq!(route.as_path.to_string(), "64496 13335");
+    }
+
+    #[test]
+    fn malformed_records_are_skipped() {
+        let mut input = rib(&[33, 10, 0, 0, 0, 0], &[]);
+        input.extend(rib(&[8, 10], &[5, 0]));
+        input.extend(rib(&[8, 10], &[2, 2, 0, 0]));
+        input.extend(rib(&[0], &[]));
+        let routes = read(&input);
+        let errors: Vec<_> = routes[..3]
+            .iter()
+            .map(|route| route.as_ref().unwrap_err().clone())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                MrtError {
+                    record: 1,
+                    reason: "prefix is too long"
+                },
+                MrtError {
+                    record: 2,
+                    reason: "unknown AS path segment type"
+                },
+                MrtError {
+                    record: 3,
+                    reason: "record is truncated"
+                },
+            ]
+        );
+        let route = routes[3].as_ref().unwrap();
+        assert_eq!(route.prefix, "0.0.0.0/0".parse().unwrap());
+        assert_eq!(route.as_path, AsPath::default());
+    }
+
+    #[test]
+    fn ipv6_routes_stay_ipv6() {
+        let route = |prefix: &str, origin| Route {
+            prefix: prefix.parse().unwrap(),
+            peer_index: 0,
+            originated_time: 0,
+            as_path: AsPath(vec![AsPathSegment::Sequence(vec![origin])]),
+        };
+        let routes = [
+            route("::/0", 64496),
+            route("::ffff:0:0/96", 64496),
+            route("192.0.2.0/24", 64496),
+            route("2001:db8::/32", 64497),
+        ];
+        let filter = RouteFilter {
+            origins: vec![64496],
+        };
+        let (v4, v6) = filter.collect(routes);
+        assert_eq!(v4.normalized().export(), "192.0.2.0/24");
+        assert_eq!(v6.normalized().export(), "::/0");
+    }
+
+    #[test]
+    fn truncated_input() {
+        let input = rib(&[8, 10], &[]);
+        let mut reader = MrtReader::new(&input[..input.len() - 1]);
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let mut reader = MrtReader::new(&input[..5]);
+        assert!(reader.next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,328 @@
+//! RIR statistics exchange files, i.e. `delegated-<registry>-latest` and
+//! `delegated-<registry>-extended-latest` as published by AFRINIC, APNIC,
+//! ARIN, LACNIC and RIPE NCC.
+//!
+//! Records look like `apnic|CN|ipv4|1.0.1.0|256|20110414|allocated`. IPv4
+//! records give a start address and an address count, which need not be a
+//! power of two; IPv6 records give a prefix length. Both become a single
+//! range, so normalize before display. The version line, summary lines,
+//! comments and ASN records are skipped.
+//!
+//! ```
+//! use cidr_aggregator::aggregator::Aggregator;
+//! use cidr_aggregator::formats::rir::{RirFilter, RirReader};
+//!
+//! let input = "\
+//! 2|apnic|20240101|3|19830613|20231231|+1000
+//! apnic|*|ipv4|*|3|summary
+//! apnic|CN|ipv4|1.0.1.0|256|20110414|allocated
+//! apnic|CN|ipv4|1.0.2.0|768|20110414|allocated
+//! apnic|AU|ipv4|1.0.0.0|256|20110811|assigned
+//! ";
+//! let filter = RirFilter {
+//!     countries: vec!["CN".into()],
+//!     ..Default::default()
+//! };
+//! let mut v4 = Vec::new();
+//! for record in RirReader::new(input.as_bytes()) {
+//!     let delegation = record?.unwrap();
+//!     if filter.matches(&delegation) {
+//!         v4.extend(delegation.range.into_v4());
+//!     }
+//! }
+//! assert_eq!(v4.aggregated().normalized().export(), "1.0.1.0/24\n1.0.2.0/23\n1.0.4.0/24");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use crate::formats::any_of;
+use crate::parser::{InvalidEntry, LineReader, ParseCidrError, RecordError};
+use crate::{EitherIpRange, IpRange, Ipv4Range};
+
+/// The status of a delegation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Status {
+    Allocated,
+    Assigned,
+    /// Not yet delegated; only in extended files.
+    Available,
+    /// Held back by the registry; only in extended files.
+    Reserved,
+}
+
+impl FromStr for Status {
+    type Err = RecordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "allocated" => Ok(Status::Allocated),
+            "assigned" => Ok(Status::Assigned),
+            "available" => Ok(Status::Available),
+            "reserved" => Ok(Status::Reserved),
+            _ => Err(RecordError::Malformed),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Allocated => "allocated",
+            Status::Assigned => "assigned",
+            Status::Available => "available",
+            Status::Reserved => "reserved",
+        })
+    }
+}
+
+/// An IPv4 or IPv6 record of a delegated statistics file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Delegation {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    /// Lowercase registry name, e.g. `apnic` or `ripencc`.
+    pub registry: String,
+    /// Uppercase ISO 3166 country code; `ZZ` or empty for undelegated space.
+    pub country: String,
+    pub range: EitherIpRange,
+    pub status: Status,
+    /// The date of the delegation as `YYYYMMDD`, if given.
+    pub date: Option<String>,
+    /// The opaque ID of the holder; only in extended files.
+    pub opaque_id: Option<String>,
+}
+
+/// Selects delegations by country, registry and status.
+///
+/// Each non-empty list must contain the corresponding field of a delegation,
+/// compared case-insensitively. Empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RirFilter {
+    pub countries: Vec<String>,
+    pub registries: Vec<String>,
+    pub statuses: Vec<Status>,
+}
+
+impl RirFilter {
+    pub fn matches(&self, delegation: &Delegation) -> bool {
+        any_of(&self.countries, [delegation.country.as_str()])
+            && any_of(&self.registries, [delegation.registry.as_str()])
+            && (self.statuses.is_empty() || self.statuses.contains(&delegation.status))
+    }
+}
+
+/// Reads delegations line by line from any [`BufRead`].
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one record. Records that are not well-formed are
+/// invalid with [`RecordError::Malformed`], or with the error of their address.
+#[derive(Debug)]
+pub struct RirReader<R: BufRead> {
+    lines: LineReader<R>,
+    seen_version: bool,
+}
+
+impl<R: BufRead> RirReader<R> {
+    pub fn new(reader: R) -> Self {
+        RirReader {
+            lines: LineReader::new(reader),
+            seen_version: false,
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl<R: BufRead> Iterator for RirReader<R> {
+    type Item = io::Result<Result<Delegation, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, line) = match self.lines.next_line()? {
+                Ok((line_number, line)) => (line_number, line.trim()),
+                Err(e) => return Some(Err(e)),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('|').collect();
+            // The version line comes first and starts with the format version
+            let is_version =
+                !self.seen_version && fields[0].starts_with(|c: char| c.is_ascii_digit());
+            self.seen_version = true;
+            if is_version || fields.get(5) == Some(&"summary") || fields.get(2) == Some(&"asn") {
+                continue;
+            }
+            return Some(Ok(parse_record(line_number, &fields).map_err(|error| {
+                InvalidEntry {
+                    line_number,
+                    line: line.to_owned(),
+                    error,
+                }
+            })));
+        }
+    }
+}
+
+fn parse_record(line_number: usize, fields: &[&str]) -> Result<Delegation, RecordError> {
+    let &[registry, country, kind, start, value, date, status, ..] = fields else {
+        return Err(RecordError::Malformed);
+    };
+    let range = match kind {
+        "ipv4" => {
+            let first = u32::from(
+                start
+                    .parse::<Ipv4Addr>()
+                    .map_err(|_| ParseCidrError::InvalidAddress)?,
+            );
+            let last = value
+                .parse::<u32>()
+                .ok()
+                .and_then(|count| count.checked_sub(1))
+                .and_then(|offset| first.checked_add(offset))
+                .ok_or(RecordError::Malformed)?;
+            EitherIpRange::V4(Ipv4Range::from_cidr_pair_decimal((first, last)))
+        }
+        "ipv6" => format!("{}/{}", start, value).parse()?,
+        _ => return Err(RecordError::Malformed),
+    };
+    Ok(Delegation {
+        line_number,
+        registry: registry.to_ascii_lowercase(),
+        country: country.to_ascii_uppercase(),
+        range,
+        status: status.parse()?,
+        date: Some(date.to_owned()).filter(|date| !date.is_empty() && date != "00000000"),
+        opaque_id: fields.get(7).map(|id| id.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str) -> Vec<Result<Delegation, InvalidEntry>> {
+        RirReader::new(input.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn extended_records() {
+        let records = read(
+            "# comment\n\
+             2.3|arin|1704085199|3|19700101|20240101|-0500\n\
+             arin|*|ipv4|*|2|summary\n\
+             arin|US|asn|1|1|20010906|assigned|e5e3b9c13678dfc483fb1f819d70883c\n\
+             arin|US|ipv4|23.128.0.0|1536|20190129|allocated|c9e8ba4c5da9c3e7b0e2d4a5d96cc2c8\n\
+             arin||ipv6|2001:400::|32|00000000|available|\n",
+        );
+        assert_eq!(records.len(), 2);
+        let delegation = records[0].as_ref().unwrap();
+        assert_eq!(delegation.line_number, 5);
+        assert_eq!(delegation.country, "US");
+        assert_eq!(delegation.status, Status::Allocated);
+        assert_eq!(delegation.date.as_deref(), Some("20190129"));
+        assert_eq!(
+            delegation.range,
+            EitherIpRange::V4(Ipv4Range::from_cidr_pair_decimal((
+                u32::from(Ipv4Addr::new(23, 128, 0, 0)),
+                u32::from(Ipv4Addr::new(23, 128, 5, 255))
+            )))
+        );
+        let available = records[1].as_ref().unwrap();
+        assert_eq!(available.range, "2001:400::/32".parse().unwrap());
+        assert_eq!(available.status, Status::Available);
+        assert_eq!(available.date, None);
+        assert_eq!(available.opaque_id.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn plain_records() {
+        let records = read("ripencc|FR|ipv4|2.0.0.0|1048576|20100712|allocated");
+        let delegation = records[0].as_ref().unwrap();
+        assert_eq!(delegation.range, "2.0.0.0/12".parse().unwrap());
+        assert_eq!(delegation.opaque_id, None);
+    }
+
+    #[test]
+    fn malformed_records() {
+        let records = read(
+            "apnic|CN|ipv4|1.0.1.0|0|20110414|allocated\n\
+             apnic|CN|ipv4|255.255.255.0|512|20110414|allocated\n\
+             apnic|CN|ipv4|1.0.1.0|256|20110414|stolen\n\
+             apnic|CN|ipv4|1.0.1.0|256\n\
+             apnic|CN|ipv5|1.0.1.0|256|20110414|allocated\n\
+             apnic|CN|ipv4|1.0.1|256|20110414|allocated\n\
+             apnic|CN|ipv6|2001:250::|129|20000426|allocated\n",
+        );
+        let errors: Vec<_> = records.into_iter().map(|r| r.unwrap_err().error).collect();
+        assert_eq!(
+            errors,
+            [
+                RecordError::Malformed,
+                RecordError::Malformed,
+                RecordError::Malformed,
+                RecordError::Malformed,
+                RecordError::Malformed,
+                RecordError::Cidr(ParseCidrError::InvalidAddress),
+                RecordError::Cidr(ParseCidrError::PrefixTooLong {
+                    prefix_len: 129,
+                    max_len: 128
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn filter() {
+        let records = read(
+            "apnic|CN|ipv4|1.0.1.0|256|20110414|allocated\n\
+             apnic|cn|ipv4|1.0.8.0|2048|20110412|assigned\n\
+             apnic|JP|ipv4|1.0.16.0|4096|20110412|allocated\n\
+             ripencc|CN|ipv4|2.58.92.0|1024|20190102|assigned\n",
+        );
+        let delegations: Vec<_> = records.into_iter().map(Result::unwrap).collect();
+        let count = |filter: RirFilter| delegations.iter().filter(|d| filter.matches(d)).count();
+        assert_eq!(count(RirFilter::default()), 4);
+        assert_eq!(
+            count(RirFilter {
+                countries: vec!["cn".into()],
+                ..Default::default()
+            }),
+            3
+        );
+        assert_eq!(
+            count(RirFilter {
+                countries: vec!["CN".into()],
+                registries: vec!["APNIC".into()],
+                statuses: vec![Status::Assigned],
+            }),
+            1
+        );
+    }
+
+    #[test]
+    fn status_round_trip() {
+        for status in [
+            Status::Allocated,
+            Status::Assigned,
+            Status::Available,
+            Status::Reserved,
+        ] {
+            assert_eq!(status.to_string().parse::<Status>(), Ok(status));
+        }
+    }
+}
@@ -0,0 +1,786 @@
+//! RPKI Validated ROA Payloads (VRPs) as exported by relying party software
+//! such as Routinator and rpki-client, and Route Origin Validation (RFC 6811)
+//! of BGP announcements against them.
+//!
+//! VRPs are read from CSV, e.g. `routinator vrps --format csv` or
+//! rpki-client's `-c` output, with the columns `ASN,IP Prefix,Max Length,Trust
+//! Anchor`, or, with the `rpki-json` feature, from the JSON both tools export.
+//!
+//! ```
+//! use cidr_aggregator::aggregator::Aggregator;
+//! use cidr_aggregator::formats::rpki::{ValidationState, VrpReader, VrpSet};
+//!
+//! let csv = "\
+//! ASN,IP Prefix,Max Length,Trust Anchor
+//! AS64496,192.0.2.0/24,25,apnic
+//! AS64497,198.51.100.0/24,24,ripe
+//! ";
+//! let vrps: VrpSet = VrpReader::new(csv.as_bytes())
+//!     .map(|vrp| vrp.map(Result::unwrap))
+//!     .collect::<std::io::Result<_>>()?;
+//!
+//! let announcement = |prefix: &str| prefix.parse().unwrap();
+//! assert_eq!(vrps.validate(announcement("192.0.2.128/25"), 64496), ValidationState::Valid);
+//! assert_eq!(vrps.validate(announcement("192.0.2.0/26"), 64496), ValidationState::Invalid);
+//! assert_eq!(vrps.validate(announcement("198.51.100.0/24"), 64496), ValidationState::Invalid);
+//! assert_eq!(vrps.validate(announcement("203.0.113.0/24"), 64496), ValidationState::NotFound);
+//!
+//! let (v4, v6) = &vrps.authorized_space()[&64496];
+//! assert_eq!(v4.clone().normalized().export(), "192.0.2.0/24");
+//! assert!(v6.is_empty());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use crate::delimited::split_fields;
+use crate::formats::{any_of, parse_asn, split_by_family};
+use crate::parser::{InvalidEntry, LineReader, ParseCidrError, RecordError};
+use crate::{EitherIpRange, IpRange, Ipv4Range, Ipv6Range};
+
+/// A Validated ROA Payload: `asn` may originate `prefix` and any more
+/// specific prefix of it up to `max_length`.
+///
+/// A VRP for AS 0 (RFC 6483 §4) authorizes no one, but still makes
+/// announcements it covers invalid unless another VRP matches them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Vrp {
+    pub prefix: EitherIpRange,
+    pub max_length: u8,
+    pub asn: u32,
+    /// The trust anchor the VRP was validated under, e.g. `apnic` or `ripe`.
+    pub trust_anchor: Option<String>,
+}
+
+impl Vrp {
+    /// Check that `prefix` is a CIDR block and that `max_length` lies between
+    /// its prefix length and the address width.
+    pub fn new(
+        prefix: EitherIpRange,
+        max_length: u8,
+        asn: u32,
+        trust_anchor: Option<String>,
+    ) -> Result<Vrp, RecordError> {
+        let prefix_len = prefix_len(prefix).ok_or(RecordError::Malformed)?;
+        let width = if prefix.is_v4() { 32 } else { 128 };
+        if max_length > width {
+            return Err(ParseCidrError::PrefixTooLong {
+                prefix_len: max_length.into(),
+                max_len: width.into(),
+            }
+            .into());
+        }
+        if max_length < prefix_len {
+            return Err(RecordError::Malformed);
+        }
+        Ok(Vrp {
+            prefix,
+            max_length,
+            asn,
+            trust_anchor,
+        })
+    }
+}
+
+/// The outcome of Route Origin Validation for an announcement.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ValidationState {
+    /// A VRP covering the prefix authorizes the origin at that length.
+    Valid,
+    /// VRPs cover the prefix, but none of them matches the origin and length.
+    Invalid,
+    /// No VRP covers the prefix.
+    NotFound,
+}
+
+impl fmt::Display for ValidationState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ValidationState::Valid => "Valid",
+            ValidationState::Invalid => "Invalid",
+            ValidationState::NotFound => "NotFound",
+        })
+    }
+}
+
+/// VRPs indexed by prefix for validation.
+#[derive(Debug, Clone, Default)]
+pub struct VrpSet {
+    vrps: HashMap<EitherIpRange, Vec<Vrp>>,
+    len: usize,
+}
+
+impl VrpSet {
+    pub fn insert(&mut self, vrp: Vrp) {
+        let vrps = self.vrps.entry(vrp.prefix).or_default();
+        if !vrps.contains(&vrp) {
+            vrps.push(vrp);
+            self.len += 1;
+        }
+    }
+
+    /// Number of distinct VRPs.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vrp> {
+        self.vrps.values().flatten()
+    }
+
+    /// The VRPs whose prefix equals or covers `prefix`.
+    pub fn covering(&self, prefix: EitherIpRange) -> impl Iterator<Item = &Vrp> {
+        let prefix_len = prefix_len(prefix).unwrap_or(0);
+        (0..=prefix_len)
+            .filter_map(move |len| self.vrps.get(&supernet(prefix, len)))
+            .flatten()
+    }
+
+    /// Validate the announcement of `prefix` by `origin` as per RFC 6811.
+    ///
+    /// An announcement whose AS path ends in an AS_SET has no origin; pass
+    /// `0` for it, which no VRP matches.
+    pub fn validate(&self, prefix: EitherIpRange, origin: u32) -> ValidationState {
+        let prefix_len = prefix_len(prefix).unwrap_or(0);
+        let mut state = ValidationState::NotFound;
+        for vrp in self.covering(prefix) {
+            if vrp.asn != 0 && vrp.asn == origin && prefix_len <= vrp.max_length {
+                return ValidationState::Valid;
+            }
+            state = ValidationState::Invalid;
+        }
+        state
+    }
+
+    /// The address space each AS number may originate routes for, aggregated.
+    ///
+    /// AS0 VRPs (RFC 6483 §4) authorize no one and are left out.
+    pub fn authorized_space(&self) -> BTreeMap<u32, (Vec<Ipv4Range>, Vec<Ipv6Range>)> {
+        let mut space = BTreeMap::<_, Vec<_>>::new();
+        for vrp in self.iter().filter(|vrp| vrp.asn != 0) {
+            space.entry(vrp.asn).or_default().push(vrp.prefix);
+        }
+        space
+            .into_iter()
+            .map(|(asn, prefixes)| (asn, split_by_family(prefixes)))
+            .collect()
+    }
+}
+
+impl Extend<Vrp> for VrpSet {
+    fn extend<I: IntoIterator<Item = Vrp>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|vrp| self.insert(vrp));
+    }
+}
+
+impl FromIterator<Vrp> for VrpSet {
+    fn from_iter<I: IntoIterator<Item = Vrp>>(iter: I) -> Self {
+        let mut set = VrpSet::default();
+        set.extend(iter);
+        set
+    }
+}
+
+/// Selects VRPs by AS number and trust anchor.
+///
+/// Each non-empty list must contain the corresponding field of a VRP, with
+/// trust anchors compared case-insensitively. Empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct VrpFilter {
+    pub asns: Vec<u32>,
+    pub trust_anchors: Vec<String>,
+}
+
+impl VrpFilter {
+    pub fn matches(&self, vrp: &Vrp) -> bool {
+        (self.asns.is_empty() || self.asns.contains(&vrp.asn))
+            && any_of(&self.trust_anchors, vrp.trust_anchor.as_deref())
+    }
+
+    /// The address space the matching VRPs authorize, split by address family
+    /// and aggregated. AS0 VRPs authorize nothing, even if `asns` holds 0.
+    pub fn collect<'a, I: IntoIterator<Item = &'a Vrp>>(
+        &self,
+        vrps: I,
+    ) -> (Vec<Ipv4Range>, Vec<Ipv6Range>) {
+        split_by_family(
+            vrps.into_iter()
+                .filter(|vrp| vrp.asn != 0 && self.matches(vrp))
+                .map(|vrp| vrp.prefix),
+        )
+    }
+}
+
+/// Where the fields of a VRP are in a CSV record.
+#[derive(Debug, Clone, Copy)]
+struct Columns {
+    asn: usize,
+    prefix: usize,
+    max_length: usize,
+    trust_anchor: Option<usize>,
+}
+
+impl Columns {
+    /// Routinator's `csv` and `csvcompat` and rpki-client's CSV output agree.
+    const DEFAULT: Columns = Columns {
+        asn: 0,
+        prefix: 1,
+        max_length: 2,
+        trust_anchor: Some(3),
+    };
+
+    /// Find the columns by name in a header, as Routinator's `csvext` output
+    /// has them in another order.
+    fn from_header(fields: &[String]) -> Option<Columns> {
+        let find = |names: &[&str]| {
+            fields.iter().position(|field| {
+                let field = field.replace(['_', '-'], " ");
+                names.iter().any(|name| field.eq_ignore_ascii_case(name))
+            })
+        };
+        Some(Columns {
+            asn: find(&["asn"])?,
+            prefix: find(&["ip prefix", "prefix"])?,
+            max_length: find(&["max length", "maxlength"])?,
+            trust_anchor: find(&["trust anchor", "ta"]),
+        })
+    }
+}
+
+/// Reads VRPs line by line from CSV.
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one record. A header line is skipped, and picks the
+/// columns if they are not in the usual order. Records that are not well-formed
+/// are invalid with [`RecordError::Malformed`], those with a bad AS number with
+/// [`RecordError::InvalidAsn`], or with the error of their prefix.
+#[derive(Debug)]
+pub struct VrpReader<R: BufRead> {
+    lines: LineReader<R>,
+    columns: Option<Columns>,
+}
+
+impl<R: BufRead> VrpReader<R> {
+    pub fn new(reader: R) -> Self {
+        VrpReader {
+            lines: LineReader::new(reader),
+            columns: None,
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl<R: BufRead> Iterator for VrpReader<R> {
+    type Item = io::Result<Result<Vrp, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, line) = match self.lines.next_line()? {
+                Ok((line_number, line)) => (line_number, line.trim()),
+                Err(e) => return Some(Err(e)),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = split_fields(line, ',');
+            let columns = match self.columns {
+                Some(columns) => columns,
+                None => {
+                    let header = Columns::from_header(&fields);
+                    self.columns = Some(header.unwrap_or(Columns::DEFAULT));
+                    if header.is_some() {
+                        continue;
+                    }
+                    Columns::DEFAULT
+                }
+            };
+            return Some(Ok(parse_record(&fields, columns).map_err(|error| {
+                InvalidEntry {
+                    line_number,
+                    line: line.to_owned(),
+                    error,
+                }
+            })));
+        }
+    }
+}
+
+fn parse_record(fields: &[String], columns: Columns) -> Result<Vrp, RecordError> {
+    let field = |index: usize| fields.get(index).ok_or(RecordError::MissingColumn);
+    let prefix = field(columns.prefix)?.parse()?;
+    let max_length = field(columns.max_length)?
+        .parse()
+        .map_err(|_| RecordError::Malformed)?;
+    let asn = parse_asn(field(columns.asn)?)?;
+    let trust_anchor = columns
+        .trust_anchor
+        .and_then(|index| fields.get(index))
+        .filter(|ta| !ta.is_empty())
+        .cloned();
+    Vrp::new(prefix, max_length, asn, trust_anchor)
+}
+
+/// An announcement of a prefix by an origin AS, to be validated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Announcement {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    pub prefix: EitherIpRange,
+    pub origin: u32,
+}
+
+impl FromStr for Announcement {
+    type Err = RecordError;
+
+    /// Parse a prefix and an origin AS separated by whitespace or a comma,
+    /// e.g. `192.0.2.0/24 AS64496`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|field| !field.is_empty());
+        let (Some(prefix), Some(origin), None) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(RecordError::Malformed);
+        };
+        let prefix = prefix.parse()?;
+        prefix_len(prefix).ok_or(RecordError::Malformed)?;
+        Ok(Announcement {
+            line_number: 0,
+            prefix,
+            origin: parse_asn(origin)?,
+        })
+    }
+}
+
+/// Reads announcements line by line, one [`Announcement`] per line.
+///
+/// Blank lines and lines starting with `#` are skipped.
+#[derive(Debug)]
+pub struct AnnouncementReader<R: BufRead> {
+    lines: LineReader<R>,
+}
+
+impl<R: BufRead> AnnouncementReader<R> {
+    pub fn new(reader: R) -> Self {
+        AnnouncementReader {
+            lines: LineReader::new(reader),
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl<R: BufRead> Iterator for AnnouncementReader<R> {
+    type Item = io::Result<Result<Announcement, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, line) = match self.lines.next_line()? {
+                Ok((line_number, line)) => (line_number, line.trim()),
+                Err(e) => return Some(Err(e)),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            return Some(Ok(line
+                .parse()
+                .map(|announcement| Announcement {
+                    line_number,
+                    ..announcement
+                })
+                .map_err(|error| InvalidEntry {
+                    line_number,
+                    line: line.to_owned(),
+                    error,
+                })));
+        }
+    }
+}
+
+/// Why a JSON VRP export could not be read.
+#[cfg(feature = "rpki-json")]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum VrpJsonError {
+    /// The file is not valid JSON, or has no `roas` array of VRPs.
+    Json(serde_json::Error),
+}
+
+#[cfg(feature = "rpki-json")]
+impl fmt::Display for VrpJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VrpJsonError::Json(error) => write!(f, "malformed JSON: {}", error),
+        }
+    }
+}
+
+#[cfg(feature = "rpki-json")]
+impl std::error::Error for VrpJsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VrpJsonError::Json(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "rpki-json")]
+impl From<serde_json::Error> for VrpJsonError {
+    fn from(error: serde_json::Error) -> Self {
+        VrpJsonError::Json(error)
+    }
+}
+
+#[cfg(feature = "rpki-json")]
+#[derive(serde::Deserialize)]
+struct JsonVrps {
+    roas: Vec<JsonVrp>,
+}
+
+#[cfg(feature = "rpki-json")]
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonVrp {
+    prefix: String,
+    /// Wider than a prefix length, so that an out of range one invalidates
+    /// only its VRP rather than the whole document.
+    max_length: i64,
+    asn: JsonAsn,
+    ta: Option<String>,
+}
+
+/// Routinator writes `"AS64496"`, rpki-client a bare number.
+#[cfg(feature = "rpki-json")]
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum JsonAsn {
+    Number(u32),
+    String(String),
+}
+
+/// Parse the JSON export of Routinator (`--format json`) or rpki-client
+/// (`-j`), i.e. an object with a `roas` array of `prefix`, `maxLength`, `asn`
+/// and `ta`.
+///
+/// Like [`VrpReader`], each VRP is parsed on its own; an invalid one is
+/// reported with its 1-based index in `roas` as the line number and its
+/// prefix as the line.
+#[cfg(feature = "rpki-json")]
+pub fn parse_vrps_json(json: &str) -> Result<Vec<Result<Vrp, InvalidEntry>>, VrpJsonError> {
+    let vrps: JsonVrps = serde_json::from_str(json)?;
+    Ok(vrps
+        .roas
+        .into_iter()
+        .enumerate()
+        .map(|(index, vrp)| {
+            let parsed = (|| {
+                let asn = match &vrp.asn {
+                    JsonAsn::Number(asn) => *asn,
+                    JsonAsn::String(asn) => parse_asn(asn)?,
+                };
+                let ta = vrp.ta.clone().filter(|ta| !ta.is_empty());
+                let prefix: EitherIpRange = vrp.prefix.parse()?;
+                let max_length = u8::try_from(vrp.max_length).map_err(|_| {
+                    match u32::try_from(vrp.max_length) {
+                        Ok(max_length) => ParseCidrError::PrefixTooLong {
+                            prefix_len: max_length,
+                            max_len: if prefix.is_v4() { 32 } else { 128 },
+                        }
+                        .into(),
+                        Err(_) => RecordError::Malformed,
+                    }
+                })?;
+                Vrp::new(prefix, max_length, asn, ta)
+            })();
+            parsed.map_err(|error| InvalidEntry {
+                line_number: index + 1,
+                line: vrp.prefix,
+                error,
+            })
+        })
+        .collect())
+}
+
+/// The prefix length of `range`, or `None` if it is not a CIDR block.
+fn prefix_len(range: EitherIpRange) -> Option<u8> {
+    let (first, mask, width) = match range {
+        EitherIpRange::V4(range) => {
+            let (first, last) = range.into_cidr_pair_decimal();
+            (first as u128, (last - first) as u128, 32)
+        }
+        EitherIpRange::V6(range) => {
+            let (first, last) = range.into_cidr_pair_decimal();
+            (first, last - first, 128)
+        }
+    };
+    (mask & mask.wrapping_add(1) == 0 && first & mask == 0)
+        .then(|| (width - mask.count_ones()) as u8)
+}
+
+/// The `len`-bit prefix containing `range`.
+fn supernet(range: EitherIpRange, len: u8) -> EitherIpRange {
+    match range {
+        EitherIpRange::V4(range) => {
+            let (first, _) = range.into_cidr_pair_decimal();
+            let hostmask = u32::MAX.checked_shr(len as u32).unwrap_or(0);
+            Ipv4Range::from_cidr_pair_decimal((first & !hostmask, first | hostmask)).into()
+        }
+        EitherIpRange::V6(range) => {
+            let (first, _) = range.into_cidr_pair_decimal();
+            let hostmask = u128::MAX.checked_shr(len as u32).unwrap_or(0);
+            Ipv6Range::from_cidr_pair_decimal((first & !hostmask, first | hostmask)).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::Aggregator;
+
+    fn range(s: &str) -> EitherIpRange {
+        s.parse().unwrap()
+    }
+
+    fn vrp(prefix: &str, max_length: u8, asn: u32) -> Vrp {
+        Vrp::new(range(prefix), max_length, asn, None).unwrap()
+    }
+
+    fn read(input: &str) -> Vec<Result<Vrp, InvalidEntry>> {
+        VrpReader::new(input.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn csv_formats() {
+        let routinator = "ASN,IP Prefix,Max Length,Trust Anchor\nAS13335,1.0.0.0/24,24,apnic\n";
+        let rpki_client = "ASN,IP Prefix,Max Length,Trust Anchor,Expires\n\
+                           AS13335,1.0.0.0/24,24,apnic,1700000000\n";
+        let csvcompat = "\"ASN\",\"IP Prefix\",\"Max Length\",\"Trust Anchor\"\n\
+                         \"AS13335\",\"1.0.0.0/24\",\"24\",\"apnic\"\n";
+        let csvext = "URI,ASN,IP Prefix,Max Length,Not Before,Not After\n\
+                      rsync://example.net/a.roa,AS13335,1.0.0.0/24,24,2024-01-01,2024-02-01\n";
+        let expected = Vrp {
+            trust_anchor: Some("apnic".into()),
+            ..vrp("1.0.0.0/24", 24, 13335)
+        };
+        for input in [routinator, rpki_client, csvcompat] {
+            assert_eq!(read(input), [Ok(expected.clone())]);
+        }
+        assert_eq!(read(csvext), [Ok(vrp("1.0.0.0/24", 24, 13335))]);
+        // Without a header, the usual columns are assumed
+        assert_eq!(
+            read("64496,2001:db8::/32,48,\n"),
+            [Ok(vrp("2001:db8::/32", 48, 64496))]
+        );
+    }
+
+    #[test]
+    fn invalid_records() {
+        let input = "\
+ASN,IP Prefix,Max Length,Trust Anchor
+AS64496,192.0.2.0/24,23,apnic
+AS64496,192.0.2.0/24,33,apnic
+AS64496,192.0.2.1/24,24,apnic
+AS64496,192.0.2.0-192.0.2.2,32,apnic
+ASX,192.0.2.0/24,24,apnic
+AS64496,192.0.2.0/24
+";
+        let errors: Vec<_> = read(input)
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.unwrap_err();
+                (entry.line_number, entry.error)
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (2, RecordError::Malformed),
+                (
+                    3,
+                    RecordError::Cidr(ParseCidrError::PrefixTooLong {
+                        prefix_len: 33,
+                        max_len: 32
+                    })
+                ),
+                (
+                    4,
+                    RecordError::Cidr(ParseCidrError::HostBitsSet { prefix_len: 24 })
+                ),
+                (5, RecordError::Cidr(ParseCidrError::InvalidAddress)),
+                (6, RecordError::InvalidAsn),
+                (7, RecordError::MissingColumn),
+            ]
+        );
+    }
+
+    #[test]
+    fn origin_validation() {
+        let vrps: VrpSet = [
+            vrp("10.0.0.0/8", 16, 64496),
+            vrp("10.1.0.0/16", 24, 64497),
+            vrp("192.0.2.0/24", 24, 0),
+            vrp("2001:db8::/32", 48, 64496),
+        ]
+        .into_iter()
+        .collect();
+        let state = |prefix: &str, origin| vrps.validate(range(prefix), origin);
+        assert_eq!(state("10.0.0.0/8", 64496), ValidationState::Valid);
+        assert_eq!(state("10.2.0.0/16", 64496), ValidationState::Valid);
+        assert_eq!(state("10.2.0.0/17", 64496), ValidationState::Invalid);
+        // Covered by both; either may authorize it
+        assert_eq!(state("10.1.0.0/16", 64496), ValidationState::Valid);
+        assert_eq!(state("10.1.2.0/24", 64497), ValidationState::Valid);
+        assert_eq!(state("10.1.2.0/24", 64496), ValidationState::Invalid);
+        assert_eq!(state("0.0.0.0/0", 64496), ValidationState::NotFound);
+        assert_eq!(state("11.0.0.0/8", 64496), ValidationState::NotFound);
+        // AS 0 matches nothing, not even an announcement without an origin
+        assert_eq!(state("192.0.2.0/24", 0), ValidationState::Invalid);
+        assert_eq!(state("2001:db8:1::/48", 64496), ValidationState::Valid);
+        assert_eq!(state("2001:db8::/29", 64496), ValidationState::NotFound);
+    }
+
+    #[test]
+    fn authorized_space() {
+        let vrps: VrpSet = [
+            vrp("10.0.0.0/9", 24, 64496),
+            vrp("10.128.0.0/9", 9, 64496),
+            vrp("10.0.0.0/8", 8, 64496),
+            vrp("10.1.0.0/16", 24, 64497),
+            vrp("2001:db8::/32", 48, 64497),
+            vrp("192.0.2.0/24", 32, 0),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(vrps.len(), 6);
+        let space = vrps.authorized_space();
+        assert_eq!(space.keys().copied().collect::<Vec<_>>(), [64496, 64497]);
+        assert_eq!(space[&64496].0.clone().normalized().export(), "10.0.0.0/8");
+        assert_eq!(
+            space[&64497].1.clone().normalized().export(),
+            "2001:db8::/32"
+        );
+
+        let filter = VrpFilter {
+            asns: vec![64497],
+            ..Default::default()
+        };
+        let (v4, v6) = filter.collect(vrps.iter());
+        assert_eq!(v4.normalized().export(), "10.1.0.0/16");
+        assert_eq!(v6.len(), 1);
+
+        let (v4, v6) = VrpFilter::default().collect(vrps.iter());
+        assert_eq!(v4.normalized().export(), "10.0.0.0/8");
+        assert_eq!(v6.len(), 1);
+        let as0 = VrpFilter {
+            asns: vec![0],
+            ..Default::default()
+        };
+        assert_eq!(as0.collect(vrps.iter()), (vec![], vec![]));
+    }
+
+    #[test]
+    fn announcements() {
+        let input = "# prefix origin\n192.0.2.0/24 AS64496\n2001:db8::/32,64497\n\n192.0.2.0/24\n";
+        let items: Vec<_> = AnnouncementReader::new(input.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            items[..2],
+            [
+                Ok(Announcement {
+                    line_number: 2,
+                    prefix: range("192.0.2.0/24"),
+                    origin: 64496,
+                }),
+                Ok(Announcement {
+                    line_number: 3,
+                    prefix: range("2001:db8::/32"),
+                    origin: 64497,
+                }),
+            ]
+        );
+        assert!(matches!(&items[2], Err(entry) if entry.line_number == 5));
+    }
+
+    #[cfg(feature = "rpki-json")]
+    #[test]
+    fn json_exports() {
+        let routinator = r#"{ "metadata": { "generated": 1700000000 }, "roas": [
+            { "asn": "AS13335", "prefix": "1.0.0.0/24", "maxLength": 24, "ta": "apnic" } ] }"#;
+        let rpki_client = r#"{ "metadata": { "buildtime": "2024-01-01T00:00:00Z" }, "roas": [
+            { "asn": 13335, "prefix": "1.0.0.0/24", "maxLength": 24, "ta": "apnic",
+              "expires": 1700000000 } ], "aspas": [] }"#;
+        for json in [routinator, rpki_client] {
+            assert_eq!(
+                parse_vrps_json(json).unwrap(),
+                [Ok(Vrp {
+                    trust_anchor: Some("apnic".into()),
+                    ..vrp("1.0.0.0/24", 24, 13335)
+                })]
+            );
+        }
+        let items = parse_vrps_json(
+            r#"{ "roas": [
+                { "asn": 1, "prefix": "1.0.0.0/24", "maxLength": 8 },
+                { "asn": "AS+1", "prefix": "1.0.0.0/24", "maxLength": 24 },
+                { "asn": 1, "prefix": "1.0.0.0/24", "maxLength": 300 },
+                { "asn": 1, "prefix": "1.0.0.0/24", "maxLength": 24 } ] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            items,
+            [
+                Err(InvalidEntry {
+                    line_number: 1,
+                    line: "1.0.0.0/24".into(),
+                    error: RecordError::Malformed,
+                }),
+                Err(InvalidEntry {
+                    line_number: 2,
+                    line: "1.0.0.0/24".into(),
+                    error: RecordError::InvalidAsn,
+                }),
+                Err(InvalidEntry {
+                    line_number: 3,
+                    line: "1.0.0.0/24".into(),
+                    error: RecordError::Cidr(ParseCidrError::PrefixTooLong {
+                        prefix_len: 300,
+                        max_len: 32,
+                    }),
+                }),
+                Ok(vrp("1.0.0.0/24", 24, 1)),
+            ]
+        );
+        assert!(matches!(
+            parse_vrps_json(r#"{ "vrps": [] }"#),
+            Err(VrpJsonError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,526 @@
+//! RPSL objects (RFC 2622) from IRR database dumps, such as RIPE's
+//! `ripe.db.route.gz`, and the `route` / `route6` and `as-set` objects among
+//! them.
+//!
+//! Objects are runs of `attribute: value` lines separated by blank lines.
+//! Lines starting with whitespace or `+` continue the value of the attribute
+//! before them, `#` starts a comment running to the end of the line, and
+//! lines starting with `%` or `#` are skipped.
+//!
+//! ```
+//! use cidr_aggregator::aggregator::Aggregator;
+//! use cidr_aggregator::formats::rpsl::{AsSets, RouteObject, RpslFilter, RpslReader};
+//!
+//! let dump = "\
+//! as-set:   AS-EXAMPLE
+//! members:  AS64496, AS-EXAMPLE-CUSTOMERS
+//!
+//! as-set:   AS-EXAMPLE-CUSTOMERS
+//! members:  AS64497
+//!
+//! route:    192.0.2.0/25
+//! origin:   AS64496
+//!
+//! route:    192.0.2.128/25
+//! origin:   AS64497
+//!
+//! route:    198.51.100.0/24
+//! origin:   AS64498
+//! ";
+//! let objects = RpslReader::new(dump.as_bytes())
+//!     .map(|object| object.map(Result::unwrap))
+//!     .collect::<std::io::Result<Vec<_>>>()?;
+//!
+//! let mut as_sets = AsSets::default();
+//! objects.iter().for_each(|object| { as_sets.insert(object); });
+//! let (origins, missing) = as_sets.expand("AS-EXAMPLE");
+//! assert_eq!(origins, [64496, 64497]);
+//! assert!(missing.is_empty());
+//!
+//! let filter = RpslFilter { origins, ..Default::default() };
+//! let mut v4 = Vec::new();
+//! for object in &objects {
+//!     if let Some(route) = RouteObject::from_rpsl(object) {
+//!         let route = route.unwrap();
+//!         if filter.matches(&route) {
+//!             v4.extend(route.prefix.into_v4());
+//!         }
+//!     }
+//! }
+//! assert_eq!(v4.aggregated().normalized().export(), "192.0.2.0/24");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead};
+
+use crate::formats::{any_of, parse_asn};
+use crate::parser::{InvalidEntry, LineReader, RecordError};
+use crate::EitherIpRange;
+
+/// An RPSL object: its attributes in order, with names in lowercase and
+/// continuation lines joined by single spaces.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RpslObject {
+    /// 1-based line number of the first attribute in the input.
+    pub line_number: usize,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl RpslObject {
+    /// The object class, i.e. the name of the first attribute, e.g. `route`.
+    pub fn class(&self) -> &str {
+        self.attributes.first().map_or("", |(name, _)| name)
+    }
+
+    /// The value of the first attribute, e.g. the prefix of a route object.
+    pub fn key(&self) -> &str {
+        self.attributes.first().map_or("", |(_, value)| value)
+    }
+
+    /// The value of the first attribute named `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The values of all attributes named `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.attributes
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads objects one by one from any [`BufRead`].
+///
+/// Like [`CidrReader`](crate::parser::CidrReader), each item is an I/O error or
+/// the outcome of parsing one object. An object with a line that is neither an
+/// attribute nor a continuation is invalid with [`RecordError::Malformed`],
+/// reported at that line.
+#[derive(Debug)]
+pub struct RpslReader<R: BufRead> {
+    lines: LineReader<R>,
+}
+
+impl<R: BufRead> RpslReader<R> {
+    pub fn new(reader: R) -> Self {
+        RpslReader {
+            lines: LineReader::new(reader),
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl<R: BufRead> Iterator for RpslReader<R> {
+    type Item = io::Result<Result<RpslObject, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut object = RpslObject {
+            line_number: 0,
+            attributes: Vec::new(),
+        };
+        let mut invalid = None;
+        loop {
+            let (line_number, line) = match self.lines.next_line() {
+                None => break,
+                Some(Ok((line_number, line))) => (line_number, line.trim_end()),
+                Some(Err(e)) => return Some(Err(e)),
+            };
+            if line.trim_start().is_empty() {
+                if object.attributes.is_empty() && invalid.is_none() {
+                    continue;
+                }
+                break;
+            }
+            if line.starts_with(['%', '#']) {
+                continue;
+            }
+            // Strip end-of-line comments
+            let content = line.split('#').next().unwrap_or_default();
+            if let Some(continuation) = content
+                .strip_prefix([' ', '\t', '+'])
+                .filter(|_| !object.attributes.is_empty())
+            {
+                let (_, value) = object.attributes.last_mut().unwrap();
+                let continuation = continuation.trim();
+                if !continuation.is_empty() {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(continuation);
+                }
+                continue;
+            }
+            let attribute = content.split_once(':').filter(|(name, _)| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+            match attribute {
+                Some((name, value)) => {
+                    if object.attributes.is_empty() {
+                        object.line_number = line_number;
+                    }
+                    object
+                        .attributes
+                        .push((name.to_ascii_lowercase(), value.trim().to_owned()));
+                }
+                None => {
+                    invalid.get_or_insert_with(|| InvalidEntry {
+                        line_number,
+                        line: line.to_owned(),
+                        error: RecordError::Malformed,
+                    });
+                }
+            }
+        }
+        match invalid {
+            Some(invalid) => Some(Ok(Err(invalid))),
+            None if object.attributes.is_empty() => None,
+            None => Some(Ok(Ok(object))),
+        }
+    }
+}
+
+/// A `route` or `route6` object.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RouteObject {
+    /// 1-based line number of the object in the input.
+    pub line_number: usize,
+    pub prefix: EitherIpRange,
+    /// The AS expected to originate the prefix.
+    pub origin: u32,
+    /// The `mnt-by` maintainers.
+    pub maintainers: Vec<String>,
+    /// The registry holding the object, e.g. `RIPE`.
+    pub source: Option<String>,
+}
+
+impl RouteObject {
+    /// Read a `route` or `route6` object, or return `None` for objects of
+    /// other classes.
+    ///
+    /// An invalid prefix is reported with its error, an invalid `origin` with
+    /// [`RecordError::InvalidAsn`], and a missing one, or a prefix of the
+    /// wrong family for the class, with [`RecordError::Malformed`].
+    pub fn from_rpsl(object: &RpslObject) -> Option<Result<Self, InvalidEntry>> {
+        let class = object.class();
+        if class != "route" && class != "route6" {
+            return None;
+        }
+        let invalid = |error| InvalidEntry {
+            line_number: object.line_number,
+            line: format!("{}: {}", class, object.key()),
+            error,
+        };
+        let prefix = match object.key().parse() {
+            Ok(prefix) => prefix,
+            Err(error) => return Some(Err(invalid(RecordError::from(error)))),
+        };
+        if matches!(prefix, EitherIpRange::V6(_)) != (class == "route6") {
+            return Some(Err(invalid(RecordError::Malformed)));
+        }
+        let origin = match object.get("origin").map(parse_asn) {
+            Some(Ok(origin)) => origin,
+            Some(Err(error)) => return Some(Err(invalid(error))),
+            None => return Some(Err(invalid(RecordError::Malformed))),
+        };
+        Some(Ok(RouteObject {
+            line_number: object.line_number,
+            prefix,
+            origin,
+            maintainers: object
+                .get_all("mnt-by")
+                .flat_map(|value| value.split(','))
+                .map(|name| name.trim().to_owned())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            source: object.get("source").map(str::to_owned),
+        }))
+    }
+}
+
+/// Selects route objects by origin, maintainer and source.
+///
+/// Each non-empty list must contain the corresponding field of an object,
+/// with names compared case-insensitively; for maintainers, any of them.
+/// Empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RpslFilter {
+    pub origins: Vec<u32>,
+    pub maintainers: Vec<String>,
+    pub sources: Vec<String>,
+}
+
+impl RpslFilter {
+    pub fn matches(&self, route: &RouteObject) -> bool {
+        (self.origins.is_empty() || self.origins.contains(&route.origin))
+            && any_of(
+                &self.maintainers,
+                route.maintainers.iter().map(String::as_str),
+            )
+            && any_of(&self.sources, route.source.as_deref())
+    }
+}
+
+/// The members of `as-set` objects, for expanding sets into AS numbers
+/// offline.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AsSets {
+    /// Members by uppercase set name.
+    sets: HashMap<String, Vec<String>>,
+}
+
+impl AsSets {
+    /// Record the `members` and `mp-members` of `object` if it is an `as-set`,
+    /// returning whether it was one. A set seen again replaces the earlier one.
+    pub fn insert(&mut self, object: &RpslObject) -> bool {
+        if object.class() != "as-set" {
+            return false;
+        }
+        let members = object
+            .get_all("members")
+            .chain(object.get_all("mp-members"))
+            .flat_map(|value| value.split([',', ' ', '\t']))
+            .filter(|member| !member.is_empty())
+            .map(str::to_ascii_uppercase)
+            .collect();
+        self.sets.insert(object.key().to_ascii_uppercase(), members);
+        true
+    }
+
+    /// Number of sets recorded.
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    /// The AS numbers in the set `name` and, recursively, the sets it
+    /// contains, sorted; along with the names of contained sets, or `name`
+    /// itself, that were never recorded.
+    pub fn expand(&self, name: &str) -> (Vec<u32>, Vec<String>) {
+        let mut asns = BTreeSet::new();
+        let mut missing = BTreeSet::new();
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![name.to_ascii_uppercase()];
+        while let Some(name) = stack.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let Some(members) = self.sets.get(&name) else {
+                missing.insert(name);
+                continue;
+            };
+            for member in members {
+                match parse_asn(member) {
+                    Ok(asn) => {
+                        asns.insert(asn);
+                    }
+                    Err(_) => stack.push(member.clone()),
+                }
+            }
+        }
+        (asns.into_iter().collect(), missing.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseCidrError;
+
+    fn read(input: &str) -> Vec<Result<RpslObject, InvalidEntry>> {
+        RpslReader::new(input.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    fn objects(input: &str) -> Vec<RpslObject> {
+        read(input).into_iter().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn continuation_and_comments() {
+        let objects = objects(
+            "% This is the RIPE Database dump\n\
+             %\n\
+             \n\
+             \n\
+             route:          193.0.0.0/21\n\
+             descr:          RIPE-NCC # a comment\n\
+             \x20               spanning lines\n\
+             \t\tand tabs\n\
+             +\n\
+             +               and pluses\n\
+             # a comment line\n\
+             Origin:         AS3333\n\
+             mnt-by:         RIPE-NCC-MNT\n\
+             source:         RIPE\n\
+             \n\
+             route6:         2001:67c:2e8::/48\n\
+             origin:         AS3333\n",
+        );
+        assert_eq!(objects.len(), 2);
+        let route = &objects[0];
+        assert_eq!(route.line_number, 5);
+        assert_eq!(route.class(), "route");
+        assert_eq!(route.key(), "193.0.0.0/21");
+        assert_eq!(
+            route.get("descr"),
+            Some("RIPE-NCC spanning lines and tabs and pluses")
+        );
+        assert_eq!(route.get("origin"), Some("AS3333"));
+        assert_eq!(objects[1].line_number, 16);
+        assert_eq!(objects[1].get("source"), None);
+    }
+
+    #[test]
+    fn malformed_objects() {
+        let objects = read(
+            "route: 192.0.2.0/24\n\
+             this line is not an attribute\n\
+             origin: AS64496\n\
+             \n\
+             \x20 a continuation with nothing to continue\n\
+             \n\
+             route: 198.51.100.0/24\n\
+             origin: AS64496\n",
+        );
+        let errors: Vec<_> = objects[..2]
+            .iter()
+            .map(|object| object.as_ref().unwrap_err().line_number)
+            .collect();
+        assert_eq!(errors, [2, 5]);
+        assert_eq!(objects[2].as_ref().unwrap().line_number, 7);
+    }
+
+    #[test]
+    fn route_objects() {
+        let objects = objects(
+            "route: 192.0.2.0/24\n\
+             origin: as64496\n\
+             mnt-by: MAINT-A, MAINT-B\n\
+             mnt-by: MAINT-C\n\
+             source: RADB\n\
+             \n\
+             route6: 2001:db8::/32\n\
+             origin: AS4200000000\n\
+             \n\
+             route: 192.0.2.1/24\n\
+             origin: AS64496\n\
+             \n\
+             route: 192.0.2.0/24\n\
+             origin: AS+64496\n\
+             \n\
+             route: 192.0.2.0/24\n\
+             descr: no origin\n\
+             \n\
+             route: 2001:db8::/32\n\
+             origin: AS64496\n\
+             \n\
+             route6: 192.0.2.0/24\n\
+             origin: AS64496\n\
+             \n\
+             aut-num: AS64496\n",
+        );
+        let routes: Vec<_> = objects.iter().map(RouteObject::from_rpsl).collect();
+        let route = routes[0].clone().unwrap().unwrap();
+        assert_eq!(route.origin, 64496);
+        assert_eq!(route.maintainers, ["MAINT-A", "MAINT-B", "MAINT-C"]);
+        assert_eq!(route.source.as_deref(), Some("RADB"));
+        assert_eq!(
+            routes[1].clone().unwrap().unwrap().prefix,
+            "2001:db8::/32".parse().unwrap()
+        );
+        let invalid = routes[2].clone().unwrap().unwrap_err();
+        assert_eq!(invalid.line, "route: 192.0.2.1/24");
+        assert!(matches!(
+            invalid.error,
+            RecordError::Cidr(ParseCidrError::HostBitsSet { .. })
+        ));
+        assert_eq!(
+            routes[3].clone().unwrap().unwrap_err().error,
+            RecordError::InvalidAsn
+        );
+        assert_eq!(
+            routes[4].clone().unwrap().unwrap_err().error,
+            RecordError::Malformed
+        );
+        for route in &routes[5..7] {
+            assert_eq!(
+                route.clone().unwrap().unwrap_err().error,
+                RecordError::Malformed
+            );
+        }
+        assert!(routes[7].is_none());
+    }
+
+    #[test]
+    fn filter() {
+        let route = |origin, maintainers: &[&str], source: Option<&str>| RouteObject {
+            line_number: 1,
+            prefix: "192.0.2.0/24".parse().unwrap(),
+            origin,
+            maintainers: maintainers.iter().map(|&name| name.to_owned()).collect(),
+            source: source.map(str::to_owned),
+        };
+        let filter = RpslFilter {
+            origins: vec![64496, 64497],
+            maintainers: vec!["maint-a".into()],
+            sources: vec!["ripe".into()],
+        };
+        assert!(filter.matches(&route(64496, &["MAINT-B", "MAINT-A"], Some("RIPE"))));
+        assert!(!filter.matches(&route(64498, &["MAINT-A"], Some("RIPE"))));
+        assert!(!filter.matches(&route(64496, &["MAINT-B"], Some("RIPE"))));
+        assert!(!filter.matches(&route(64496, &["MAINT-A"], None)));
+        assert!(RpslFilter::default().matches(&route(64498, &[], None)));
+    }
+
+    #[test]
+    fn as_set_expansion() {
+        let mut as_sets = AsSets::default();
+        for object in objects(
+            "as-set: AS-TOP\n\
+             members: AS1, as-middle\n\
+             mp-members: AS2 AS-MISSING\n\
+             \n\
+             as-set: AS-MIDDLE\n\
+             members: AS3,\n\
+             \x20 AS-TOP, AS1:AS-HIERARCHICAL\n\
+             \n\
+             as-set: AS1:AS-HIERARCHICAL\n\
+             members: AS4\n\
+             \n\
+             aut-num: AS1\n",
+        ) {
+            as_sets.insert(&object);
+        }
+        assert_eq!(as_sets.len(), 3);
+        assert_eq!(
+            as_sets.expand("as-top"),
+            (vec![1, 2, 3, 4], vec!["AS-MISSING".to_owned()])
+        );
+        assert_eq!(as_sets.expand("AS-MIDDLE").0, [1, 2, 3, 4]);
+        assert_eq!(
+            as_sets.expand("AS-NOWHERE"),
+            (vec![], vec!["AS-NOWHERE".to_owned()])
+        );
+    }
+}
@@ -1,10 +1,12 @@
-//! Parse CIDR strings, aggregate, reverse, and difference IP ranges, then
-//! normalize back to CIDR notation.
+//! Parse CIDR strings, aggregate, reverse, difference, intersect, and union IP
+//! ranges, then normalize back to CIDR notation.
 //!
 //! Supports both IPv4 and IPv6. The core abstraction is the [`IpRange`] trait,
 //! implemented by [`Ipv4Range`] and [`Ipv6Range`]. Operations like
 //! `.aggregate()`, `.reverse()`, `.normalize()`, and `.export()` are provided
-//! on `Vec` of either range type via the [`Aggregator`] trait.
+//! on `Vec` of either range type via the [`Aggregator`] trait. [`IpSet`] wraps
+//! such a `Vec` and keeps it sorted and aggregated, so set operations need no
+//! manual preparation.
 //!
 //! # Quick start
 //!
@@ -46,15 +48,14 @@
 //! A WASM build of this crate powers the web app at
 //! <https://cidr-aggregator.pages.dev>.
 
+use std::cmp::{max, min};
 use std::fmt::{self, Debug, Display};
 use std::hash::Hash;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 
-use crate::utils::{
-    ip_addr_to_bit_length, ip_addr_trailing_zeros, MathLog2, IPV4_RESERVED, IPV6_RESERVED,
-};
-use num_traits::{Bounded, NumAssignOps, NumCast, PrimInt, WrappingAdd, Zero};
+use crate::utils::{MathLog2, IPV4_RESERVED, IPV6_RESERVED};
+use num_traits::{Bounded, CheckedAdd, NumAssignOps, NumCast, One, PrimInt, WrappingAdd, Zero};
 
 /// An inclusive IPv4 range `[first, last]` stored as `u32`.
 ///
@@ -72,6 +73,11 @@ pub struct Ipv6Range(u128, u128);
 
 /// Either an IPv4 or IPv6 range, used for parsing CIDR strings.
 ///
+/// [`from_str`](EitherIpRange::from_str) accepts CIDR blocks and bare
+/// addresses only, so a parsed range is always a CIDR block. Address ranges
+/// like `10.0.0.5-10.0.0.77`, which need not be, are read by
+/// [`parser::parse_entry`].
+///
 /// Use [`into_v4`](EitherIpRange::into_v4) / [`into_v6`](EitherIpRange::into_v6)
 /// or match to extract the inner range.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -121,28 +127,10 @@ impl From<Ipv6Range> for EitherIpRange {
 }
 
 impl FromStr for EitherIpRange {
-    type Err = ();
+    type Err = ParseCidrError;
 
     fn from_str(s: &str) -> Result<EitherIpRange, Self::Err> {
-        if let Some((ip, cidr)) = s.split_once("/").or(Some((s, ""))) {
-            let ip = ip.parse::<IpAddr>().map_err(|_| ())?;
-            let cidr = if cidr.is_empty() {
-                ip_addr_to_bit_length(ip) as u8
-            } else {
-                cidr.parse::<u8>().map_err(|_| ())?
-            };
-            if cidr as u32 > ip_addr_to_bit_length(ip)
-                || ip_addr_trailing_zeros(ip) < ip_addr_to_bit_length(ip) - cidr as u32
-            {
-                return Err(()); // a host instead of a range
-            }
-            Ok(match ip {
-                IpAddr::V4(ip) => EitherIpRange::V4(Ipv4Range::from_cidr_pair((ip, cidr))),
-                IpAddr::V6(ip) => EitherIpRange::V6(Ipv6Range::from_cidr_pair((ip, cidr))),
-            })
-        } else {
-            Err(())
-        }
+        parser::parse_cidr(s, &ParseOptions::default()).map(|(range, _)| range)
     }
 }
 
@@ -155,7 +143,7 @@ impl FromStr for EitherIpRange {
 /// Note: [`Display`] (and therefore [`export`](Aggregator::export)) **panics**
 /// if the range has not been normalized — its length must be a power of two.
 pub trait IpRange: Copy + Eq + Ord + Display + Debug + Hash + 'static {
-    type Address;
+    type Address: Copy + Display + Debug + Into<Self::AddressDecimal> + From<Self::AddressDecimal>;
     type AddressDecimal: PrimInt + NumAssignOps + WrappingAdd + Bounded + Display + Debug;
 
     fn first_address(&self) -> Self::Address;
@@ -180,6 +168,71 @@ pub trait IpRange: Copy + Eq + Ord + Display + Debug + Hash + 'static {
 
     /// Reserved / special-purpose address blocks (RFC 5735, RFC 6890).
     fn reserved() -> &'static [Self];
+
+    /// Returns `true` if `addr` falls within this range.
+    fn contains_addr(&self, addr: Self::Address) -> bool {
+        let addr = addr.into();
+        self.first_address_as_decimal() <= addr && addr <= self.last_address_as_decimal()
+    }
+
+    /// Returns `true` if every address of `other` falls within this range.
+    fn contains_range(&self, other: &Self) -> bool {
+        self.first_address_as_decimal() <= other.first_address_as_decimal()
+            && other.last_address_as_decimal() <= self.last_address_as_decimal()
+    }
+
+    /// Returns `true` if the two ranges share at least one address.
+    fn overlaps(&self, other: &Self) -> bool {
+        self.first_address_as_decimal() <= other.last_address_as_decimal()
+            && other.first_address_as_decimal() <= self.last_address_as_decimal()
+    }
+
+    /// Returns `true` if one range ends right before the other begins.
+    ///
+    /// The address space does not wrap around: a range ending at `max_value`
+    /// is not adjacent to one starting at `0`.
+    fn is_adjacent_to(&self, other: &Self) -> bool {
+        let one = Self::AddressDecimal::one();
+        self.last_address_as_decimal().checked_add(&one) == Some(other.first_address_as_decimal())
+            || other.last_address_as_decimal().checked_add(&one)
+                == Some(self.first_address_as_decimal())
+    }
+
+    /// The smallest range covering both, if they overlap or are adjacent.
+    fn merge_with(&self, other: &Self) -> Option<Self> {
+        if self.overlaps(other) || self.is_adjacent_to(other) {
+            Some(Self::from_cidr_pair_decimal((
+                min(
+                    self.first_address_as_decimal(),
+                    other.first_address_as_decimal(),
+                ),
+                max(
+                    self.last_address_as_decimal(),
+                    other.last_address_as_decimal(),
+                ),
+            )))
+        } else {
+            None
+        }
+    }
+
+    /// The addresses shared by both ranges, if any.
+    fn intersection_with(&self, other: &Self) -> Option<Self> {
+        if self.overlaps(other) {
+            Some(Self::from_cidr_pair_decimal((
+                max(
+                    self.first_address_as_decimal(),
+                    other.first_address_as_decimal(),
+                ),
+                min(
+                    self.last_address_as_decimal(),
+                    other.last_address_as_decimal(),
+                ),
+            )))
+        } else {
+            None
+        }
+    }
 }
 
 macro_rules! impl_ip_range {
@@ -302,11 +355,16 @@ impl_ip_range!(Ipv4Range, Ipv4Addr, u32, IPV4_RESERVED);
 impl_ip_range!(Ipv6Range, Ipv6Addr, u128, IPV6_RESERVED);
 
 pub mod aggregator;
+pub mod delimited;
+pub mod extract;
+pub mod formats;
 pub mod parser;
+pub mod set;
 mod utils;
 
 pub use aggregator::Aggregator;
-pub use parser::parse_cidrs;
+pub use parser::{parse_cidrs, InvalidEntry, ParseCidrError, ParseOptions, RecordError};
+pub use set::{DualStackIpSet, IpSet};
 
 #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
 mod wasm;
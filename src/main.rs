@@ -1,13 +1,39 @@
-use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context};
-use clap::Parser;
+use clap::{ArgGroup, Parser};
 
 use cidr_aggregator::aggregator;
 use cidr_aggregator::aggregator::Aggregator;
-use cidr_aggregator::parser::parse_cidrs;
+use cidr_aggregator::delimited::{Column, DelimitedOptions, DelimitedReader, Header};
+use cidr_aggregator::extract::extract_ranges;
+use cidr_aggregator::formats::access_log::{AccessLogReader, HitCounts, HitFilter, LogFormat};
+#[cfg(feature = "cloud-formats")]
+use cidr_aggregator::formats::cloud::{parse_cloud_ranges, CloudFilter};
+use cidr_aggregator::formats::firewall::{
+    Direction, IpsetFilter, IpsetReader, IptablesFilter, IptablesReader, NftFilter, NftReader,
+    RangesByName,
+};
+use cidr_aggregator::formats::geoip::{
+    read_geolite2_locations, GeoFilter, GeoLite2BlocksReader, GeoNetwork,
+};
+use cidr_aggregator::formats::geoip_dat::{
+    merge_geoip_tags, read_geoip_dat, write_geoip_dat, GeoIpTag,
+};
+use cidr_aggregator::formats::mmdb::{MmdbReader, MmdbValue, MmdbWriter};
+use cidr_aggregator::formats::mrt::{MrtReader, RouteFilter};
+use cidr_aggregator::formats::rir::{RirFilter, RirReader, Status};
+#[cfg(feature = "rpki-json")]
+use cidr_aggregator::formats::rpki::parse_vrps_json;
+use cidr_aggregator::formats::rpki::{AnnouncementReader, Vrp, VrpFilter, VrpReader, VrpSet};
+use cidr_aggregator::formats::rpsl::{AsSets, RouteObject, RpslFilter, RpslReader};
+use cidr_aggregator::formats::{self, decompress};
+use cidr_aggregator::parser::{
+    CidrReader, HostBits, InvalidEntry, LineReader, ParseCidrError, ParseOptions,
+};
+use cidr_aggregator::{EitherIpRange, Ipv4Range, Ipv6Range};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -15,6 +41,13 @@ use cidr_aggregator::parser::parse_cidrs;
     version,
     about = "Aggregate, normalize, reverse, and difference CIDR IP ranges."
 )]
+#[command(group(ArgGroup::new("input_format").multiple(false)))]
+#[command(group(ArgGroup::new("by_country").args(["rir", "mmdb", "geolite2", "geoip_dat"])))]
+#[command(group(ArgGroup::new("geoip").args(["mmdb", "geolite2"])))]
+#[command(group(ArgGroup::new("by_table").args(["iptables", "nft"])))]
+#[command(group(ArgGroup::new("by_set").args(["ipset", "nft"])))]
+#[command(group(ArgGroup::new("geoip_dat_tags").args(["geoip_dat", "geoip_dat_tag"]).multiple(true)))]
+#[command(group(ArgGroup::new("by_asn").args(["mmdb", "geolite2", "mrt", "rpsl", "vrps"])))]
 struct Opt {
     /// Process IPv4 only
     #[arg(short = '4', long)]
@@ -36,6 +69,222 @@ struct Opt {
     #[arg(short = 'i', long)]
     ignore_invalid: bool,
 
+    /// Reject entries with host bits set, e.g. 192.168.1.5/24 (default)
+    #[arg(long, overrides_with_all = ["truncate_host_bits", "warn_host_bits"])]
+    strict: bool,
+
+    /// Accept entries with host bits set by masking them off, e.g. 192.168.1.5/24 → 192.168.1.0/24
+    #[arg(long, overrides_with_all = ["strict", "warn_host_bits"])]
+    truncate_host_bits: bool,
+
+    /// Like --truncate-host-bits, but print a warning for each entry truncated
+    #[arg(long, overrides_with_all = ["strict", "truncate_host_bits"])]
+    warn_host_bits: bool,
+
+    /// Accept abbreviated IPv4 prefixes like 10/8, 172.16/12 or 192.168.1 (classful)
+    #[arg(long)]
+    ipv4_shorthand: bool,
+
+    /// Marker that starts a comment running to the end of the line; may be repeated (e.g. -c '#' -c ';')
+    #[arg(
+        short = 'c',
+        long = "comment-marker",
+        value_name = "MARKER",
+        default_value = "#"
+    )]
+    comment_markers: Vec<String>,
+
+    /// Pick out every IP address and CIDR anywhere in the input, e.g. in prose, HTML or emails, ignoring everything else
+    #[arg(short = 'e', long, group = "input_format")]
+    extract: bool,
+
+    /// Read delimited records (CSV by default) and take the range from one column
+    #[arg(long, group = "input_format")]
+    csv: bool,
+
+    /// Column holding the ranges, as a 1-based number or a header name
+    #[arg(long, value_name = "N|NAME", default_value = "1", value_parser = parse_column, requires = "csv")]
+    column: Column,
+
+    /// Field delimiter for --csv, a single character or `tab`
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = parse_delimiter, requires = "csv")]
+    delimiter: char,
+
+    /// Treat the first record as a header (detected by default)
+    #[arg(long, overrides_with = "no_header", requires = "csv")]
+    header: bool,
+
+    /// Treat the first record as data, not a header
+    #[arg(long, overrides_with = "header", requires = "csv")]
+    no_header: bool,
+
+    /// Read an RIR delegated statistics file, e.g. delegated-apnic-extended-latest
+    #[arg(long, group = "input_format")]
+    rir: bool,
+
+    /// With --rir, --mmdb, --geolite2 or --geoip-dat, keep only ranges of this country code (e.g. CN); may be repeated
+    #[arg(long, value_name = "CC", requires = "by_country")]
+    country: Vec<String>,
+
+    /// With --rir, keep only delegations by this registry (e.g. apnic, ripencc); may be repeated
+    #[arg(long, value_name = "NAME", requires = "rir")]
+    registry: Vec<String>,
+
+    /// With --rir, keep only delegations of this status: allocated, assigned, available or reserved; may be repeated
+    #[arg(long, value_name = "STATUS", value_parser = parse_status, requires = "rir")]
+    status: Vec<Status>,
+
+    /// Read a MaxMind DB file (.mmdb), e.g. GeoLite2-Country.mmdb or GeoLite2-ASN.mmdb
+    #[arg(long, group = "input_format")]
+    mmdb: bool,
+
+    /// Read a GeoLite2 / GeoIP2 blocks CSV file, e.g. GeoLite2-Country-Blocks-IPv4.csv
+    #[arg(long, group = "input_format")]
+    geolite2: bool,
+
+    /// With --geolite2, the locations CSV file the blocks refer to, e.g. GeoLite2-Country-Locations-en.csv
+    #[arg(long, value_name = "FILE", requires = "geolite2")]
+    locations: Option<PathBuf>,
+
+    /// With --mmdb or --geolite2, keep only networks on this continent (e.g. EU); may be repeated
+    #[arg(long, value_name = "CODE", requires = "geoip")]
+    continent: Vec<String>,
+
+    /// With --mmdb or --geolite2, keep only networks of this autonomous system number, with --mrt or --rpsl only routes it originates, with --vrps only the space it is authorized for; may be repeated
+    #[arg(long, value_name = "ASN", value_parser = parse_asn, requires = "by_asn")]
+    asn: Vec<u32>,
+
+    /// Read a v2ray / Xray geoip.dat file; --country selects its tags (e.g. CN, PRIVATE)
+    #[arg(long, group = "input_format")]
+    geoip_dat: bool,
+
+    /// With --geoip-dat, merge in the tags of another geoip.dat file; may be repeated
+    #[arg(long, value_name = "FILE", requires = "geoip_dat")]
+    merge_geoip_dat: Vec<PathBuf>,
+
+    /// Read a BGP routing table dump in MRT format (TABLE_DUMP_V2), e.g. a RIPE RIS bview or RouteViews rib file; gzip and bzip2 need the matching features
+    #[arg(long, group = "input_format")]
+    mrt: bool,
+
+    /// Read route and route6 objects from an RPSL IRR database dump, e.g. ripe.db.route.gz; gzip and bzip2 need the matching features
+    #[arg(long, group = "input_format")]
+    rpsl: bool,
+
+    /// With --rpsl, keep only routes maintained by this mntner (e.g. RIPE-NCC-MNT); may be repeated
+    #[arg(long, value_name = "MNTNER", requires = "rpsl")]
+    mnt_by: Vec<String>,
+
+    /// With --rpsl, keep only routes from this IRR source (e.g. RIPE, RADB); may be repeated
+    #[arg(long, value_name = "NAME", requires = "rpsl")]
+    source: Vec<String>,
+
+    /// With --rpsl, keep only routes originated by the members of this as-set, expanded recursively; may be repeated
+    #[arg(long, value_name = "NAME", requires = "rpsl")]
+    as_set: Vec<String>,
+
+    /// With --as-set, another RPSL dump to look up as-set objects in besides the input, e.g. ripe.db.as-set.gz; may be repeated
+    #[arg(long, value_name = "FILE", requires = "as_set")]
+    as_set_db: Vec<PathBuf>,
+
+    /// Read RPKI Validated ROA Payloads as CSV or JSON, e.g. from `routinator vrps` or rpki-client, and output the address space they authorize; JSON needs the `rpki-json` feature
+    #[arg(long, group = "input_format")]
+    vrps: bool,
+
+    /// With --vrps, keep only VRPs of this trust anchor (e.g. apnic, ripe); may be repeated
+    #[arg(long, value_name = "NAME", requires = "vrps")]
+    trust_anchor: Vec<String>,
+
+    /// Validate announcements, one `PREFIX ASN` per line, against the VRPs in this file and print each with its state: Valid, Invalid or NotFound
+    #[arg(long, value_name = "FILE", group = "input_format")]
+    validate: Option<PathBuf>,
+
+    /// Read iptables-save or ip6tables-save output and take the addresses rules jumping to --target match on with -s (or -d)
+    #[arg(long, requires = "target", group = "input_format")]
+    iptables: bool,
+
+    /// With --iptables, keep only rules of this chain (e.g. INPUT); may be repeated
+    #[arg(long, value_name = "NAME", requires = "iptables")]
+    chain: Vec<String>,
+
+    /// With --iptables, keep only rules jumping to this target (e.g. ACCEPT, DROP); required, may be repeated
+    #[arg(long, value_name = "NAME", requires = "iptables")]
+    target: Vec<String>,
+
+    /// With --iptables, take the destination addresses (-d) of rules instead of the source addresses (-s)
+    #[arg(long, requires = "iptables")]
+    destination: bool,
+
+    /// Read `ipset save` output and take the entries of its sets, minus the nomatch ones
+    #[arg(long, group = "input_format")]
+    ipset: bool,
+
+    /// Read `nft list ruleset` output and take the elements of its named sets and maps
+    #[arg(long, group = "input_format")]
+    nft: bool,
+
+    /// With --iptables or --nft, keep only rules or sets of this table (e.g. filter); may be repeated
+    #[arg(long, value_name = "NAME", requires = "by_table")]
+    table: Vec<String>,
+
+    /// With --ipset or --nft, keep only the elements of this set; may be repeated
+    #[arg(long, value_name = "NAME", requires = "by_set")]
+    set: Vec<String>,
+
+    /// Read a web server access log and take the client addresses, e.g. of nginx or Apache in the Common or Combined Log Format; gzip and bzip2 need the matching features
+    #[arg(long, group = "input_format")]
+    access_log: bool,
+
+    /// With --access-log, where lines have the client address: common, combined or vhost_combined
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "combined",
+        requires = "access_log"
+    )]
+    log_format: LogFormat,
+
+    /// With --access-log, read JSON lines and take the client address from this field, e.g. remote_addr or http.client.ip
+    #[cfg(feature = "log-json")]
+    #[arg(
+        long,
+        value_name = "FIELD",
+        requires = "access_log",
+        conflicts_with = "log_format"
+    )]
+    log_field: Option<String>,
+
+    /// With --access-log, keep only clients with N or more requests (inclusive, so 1 keeps every client)
+    #[arg(long, value_name = "N", default_value = "1", requires = "access_log")]
+    min_hits: u64,
+
+    /// With --access-log, widen each IPv4 client to the network of this prefix length around it (e.g. 24)
+    #[arg(long, value_name = "LEN", value_parser = clap::value_parser!(u8).range(0..=32), requires = "access_log")]
+    widen_ipv4: Option<u8>,
+
+    /// With --access-log, widen each IPv6 client to the network of this prefix length around it (e.g. 64)
+    #[arg(long, value_name = "LEN", value_parser = clap::value_parser!(u8).range(0..=128), requires = "access_log")]
+    widen_ipv6: Option<u8>,
+
+    /// Read a cloud provider's published IP range JSON: AWS ip-ranges.json, GCP cloud.json, Azure Service Tags or Cloudflare's API response
+    #[cfg(feature = "cloud-formats")]
+    #[arg(long, group = "input_format")]
+    cloud: bool,
+
+    /// With --cloud, keep only prefixes of this service (e.g. EC2, AzureStorage); may be repeated
+    #[cfg(feature = "cloud-formats")]
+    #[arg(long, value_name = "NAME", requires = "cloud")]
+    service: Vec<String>,
+
+    /// With --cloud, keep only prefixes of this region or scope (e.g. us-east-1); may be repeated
+    #[cfg(feature = "cloud-formats")]
+    #[arg(long, value_name = "NAME", requires = "cloud")]
+    region: Vec<String>,
+
+    /// With --cloud, keep only prefixes of this Azure service tag (e.g. Storage.WestUS); may be repeated
+    #[cfg(feature = "cloud-formats")]
+    #[arg(long, value_name = "NAME", requires = "cloud")]
+    tag: Vec<String>,
+
     /// Input file (reads from stdin if not provided)
     #[arg(short = 'f', long, value_name = "FILE")]
     input: Option<PathBuf>,
@@ -44,11 +293,699 @@ struct Opt {
     #[arg(short = 'o', long, value_name = "FILE")]
     output: Option<PathBuf>,
 
+    /// Also write the result as a MaxMind DB file, for lookups with libmaxminddb and the like
+    #[arg(long, value_name = "FILE")]
+    mmdb_output: Option<PathBuf>,
+
+    /// With --mmdb-output, a string field of the data stored for every range (e.g. list=blocked); may be repeated
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_field, requires = "mmdb_output")]
+    mmdb_field: Vec<(String, String)>,
+
+    /// Also write the result as a geoip.dat file; with --geoip-dat, each tag is processed separately, otherwise --geoip-dat-tag names the one tag
+    #[arg(long, value_name = "FILE", requires = "geoip_dat_tags")]
+    geoip_dat_output: Option<PathBuf>,
+
+    /// With --geoip-dat-output, the tag to store the result under, unless reading --geoip-dat
+    #[arg(long, value_name = "TAG", requires = "geoip_dat_output")]
+    geoip_dat_tag: Option<String>,
+
     /// Print address and line counts to stderr
     #[arg(short = 's', long)]
     stats: bool,
 }
 
+/// How to read the input, as chosen by the flags of the `input_format` group
+/// other than --validate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Cidr,
+    Extract,
+    Csv,
+    Rir,
+    Mmdb,
+    GeoLite2,
+    GeoipDat,
+    Mrt,
+    Rpsl,
+    Vrps,
+    Iptables,
+    Ipset,
+    Nft,
+    AccessLog,
+    #[cfg(feature = "cloud-formats")]
+    Cloud,
+}
+
+impl Format {
+    /// What the lines of the input should be, for reporting those that are not.
+    fn describe(self) -> &'static str {
+        match self {
+            Format::Cidr => "CIDRs, IPs, IP ranges, or comments",
+            Format::Extract => "text",
+            Format::Csv => "records with a CIDR, IP or IP range in the range column",
+            Format::Rir => "RIR delegation records",
+            Format::Mmdb => "MaxMind DB networks",
+            Format::GeoLite2 => "GeoLite2 blocks records",
+            Format::GeoipDat => "geoip.dat tags",
+            Format::Mrt => "MRT records",
+            Format::Rpsl => "RPSL objects",
+            Format::Vrps => "VRPs",
+            Format::Iptables => "iptables rules",
+            Format::Ipset => "ipset entries",
+            Format::Nft => "nftables set elements",
+            Format::AccessLog => "access log lines with a client address",
+            #[cfg(feature = "cloud-formats")]
+            Format::Cloud => "cloud IP ranges",
+        }
+    }
+}
+
+impl Opt {
+    fn format(&self) -> Format {
+        #[cfg(feature = "cloud-formats")]
+        if self.cloud {
+            return Format::Cloud;
+        }
+        [
+            (self.extract, Format::Extract),
+            (self.csv, Format::Csv),
+            (self.rir, Format::Rir),
+            (self.mmdb, Format::Mmdb),
+            (self.geolite2, Format::GeoLite2),
+            (self.geoip_dat, Format::GeoipDat),
+            (self.mrt, Format::Mrt),
+            (self.rpsl, Format::Rpsl),
+            (self.vrps, Format::Vrps),
+            (self.iptables, Format::Iptables),
+            (self.ipset, Format::Ipset),
+            (self.nft, Format::Nft),
+            (self.access_log, Format::AccessLog),
+        ]
+        .into_iter()
+        .find_map(|(set, format)| set.then_some(format))
+        .unwrap_or(Format::Cidr)
+    }
+}
+
+fn parse_column(s: &str) -> Result<Column, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("columns are numbered from 1".to_owned()),
+        Ok(n) => Ok(Column::Index(n - 1)),
+        Err(_) => Ok(Column::Name(s.to_owned())),
+    }
+}
+
+fn parse_status(s: &str) -> Result<Status, String> {
+    s.parse()
+        .map_err(|_| "expected allocated, assigned, available or reserved".to_owned())
+}
+
+fn parse_asn(s: &str) -> Result<u32, String> {
+    formats::parse_asn(s).map_err(|_| "expected an AS number like 13335 or AS13335".to_owned())
+}
+
+fn parse_field(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| "expected KEY=VALUE".to_owned())
+}
+
+fn parse_delimiter(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (s, chars.next(), chars.next()) {
+        ("tab" | "\\t", _, _) => Ok('\t'),
+        (_, Some(c), None) => Ok(c),
+        _ => Err("expected a single character or `tab`".to_owned()),
+    }
+}
+
+/// Ranges collected from the input, split by address family.
+#[derive(Default)]
+struct Ranges {
+    v4: Vec<Ipv4Range>,
+    v6: Vec<Ipv6Range>,
+    invalid: Vec<InvalidEntry>,
+    /// The tags read with --geoip-dat, whose ranges are also in `v4` and `v6`.
+    tags: Vec<GeoIpTag>,
+}
+
+impl Ranges {
+    fn push(&mut self, range: EitherIpRange) {
+        match range {
+            EitherIpRange::V4(range) => self.v4.push(range),
+            EitherIpRange::V6(range) => self.v6.push(range),
+        }
+    }
+
+    fn extend_split(&mut self, (v4, v6): (Vec<Ipv4Range>, Vec<Ipv6Range>)) {
+        self.v4.extend(v4);
+        self.v6.extend(v6);
+    }
+
+    /// Add the ranges of all `groups`, the chains or sets of a firewall dump,
+    /// unless there are several and the user did not pick any: unrelated
+    /// chains or sets are rarely meant to be merged.
+    fn extend_groups(
+        &mut self,
+        groups: RangesByName,
+        kind: &str,
+        option: &str,
+        picked: bool,
+    ) -> anyhow::Result<()> {
+        if groups.len() > 1 && !picked {
+            let names: Vec<_> = groups.into_keys().collect();
+            bail!(
+                "The input has several {}: {}; pick some with {}",
+                kind,
+                names.join(", "),
+                option
+            );
+        }
+        groups
+            .into_values()
+            .for_each(|ranges| self.extend_split(ranges));
+        Ok(())
+    }
+}
+
+impl Extend<EitherIpRange> for Ranges {
+    fn extend<I: IntoIterator<Item = EitherIpRange>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|range| self.push(range));
+    }
+}
+
+fn read_ranges(
+    opt: &Opt,
+    input: Box<dyn BufRead>,
+    options: ParseOptions,
+) -> anyhow::Result<Ranges> {
+    let mut ranges = Ranges::default();
+    match opt.format() {
+        Format::Cidr => read_cidrs(opt, input, options, &mut ranges)?,
+        Format::Extract => read_extract(input, options, &mut ranges)?,
+        Format::Csv => read_csv(opt, input, options, &mut ranges)?,
+        Format::Rir => read_rir(opt, input, &mut ranges)?,
+        Format::Mmdb => read_mmdb(opt, input, &mut ranges)?,
+        Format::GeoLite2 => read_geolite2(opt, input, &mut ranges)?,
+        Format::GeoipDat => read_geoip_dat_tags(opt, input, &mut ranges)?,
+        Format::Mrt => read_mrt(opt, input, &mut ranges)?,
+        Format::Rpsl => read_rpsl(opt, input, &mut ranges)?,
+        Format::Vrps => {
+            let filter = VrpFilter {
+                asns: opt.asn.clone(),
+                trust_anchors: opt.trust_anchor.clone(),
+            };
+            let vrps = read_vrps(opt, input, &mut ranges.invalid)?;
+            ranges.extend_split(filter.collect(&vrps));
+        }
+        Format::Iptables => {
+            let groups = read_iptables(opt, input, &mut ranges.invalid)?;
+            ranges.extend_groups(groups, "chains", "--chain", !opt.chain.is_empty())?;
+        }
+        Format::Ipset => {
+            let groups = read_ipset(opt, input, &mut ranges.invalid)?;
+            ranges.extend_groups(groups, "sets", "--set", !opt.set.is_empty())?;
+        }
+        Format::Nft => {
+            let groups = read_nft(opt, input, &mut ranges.invalid)?;
+            ranges.extend_groups(groups, "sets", "--set", !opt.set.is_empty())?;
+        }
+        Format::AccessLog => read_access_log(opt, input, &mut ranges)?,
+        #[cfg(feature = "cloud-formats")]
+        Format::Cloud => {
+            let json = io::read_to_string(input).context("Failed to read input")?;
+            let filter = CloudFilter {
+                services: opt.service.clone(),
+                regions: opt.region.clone(),
+                tags: opt.tag.clone(),
+            };
+            ranges.extend_split(filter.collect(&parse_cloud_ranges(&json)?));
+        }
+    }
+    Ok(ranges)
+}
+
+/// Keep the items read successfully, setting the invalid ones aside unless
+/// --ignore-invalid.
+fn read_all<T>(
+    items: impl Iterator<Item = io::Result<Result<T, InvalidEntry>>>,
+    opt: &Opt,
+    invalid: &mut Vec<InvalidEntry>,
+) -> anyhow::Result<Vec<T>> {
+    let mut kept = Vec::new();
+    for item in items {
+        match item.context("Failed to read input")? {
+            Ok(item) => kept.push(item),
+            Err(entry) if !opt.ignore_invalid => invalid.push(entry),
+            Err(_) => {}
+        }
+    }
+    Ok(kept)
+}
+
+fn read_cidrs(
+    opt: &Opt,
+    input: Box<dyn BufRead>,
+    options: ParseOptions,
+    ranges: &mut Ranges,
+) -> anyhow::Result<()> {
+    let warn = options.host_bits == HostBits::Warn;
+    for item in CidrReader::new(input, options) {
+        match item.context("Failed to read input")? {
+            Ok(entry) => {
+                if let (true, Some(warning)) = (warn, entry.warning) {
+                    warn_truncated(entry.line_number, entry.range, warning);
+                }
+                ranges.push(entry.range)
+            }
+            Err(entry) if !opt.ignore_invalid => ranges.invalid.push(entry),
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_extract(
+    input: Box<dyn BufRead>,
+    options: ParseOptions,
+    ranges: &mut Ranges,
+) -> anyhow::Result<()> {
+    // Free text may well not be UTF-8 throughout, which is fine as addresses are ASCII
+    let mut lines = LineReader::lossy(input);
+    while let Some(line) = lines.next_line() {
+        let (_, line) = line.context("Failed to read input")?;
+        ranges.extend(extract_ranges(line, &options));
+    }
+    Ok(())
+}
+
+fn read_csv(
+    opt: &Opt,
+    input: Box<dyn BufRead>,
+    options: ParseOptions,
+    ranges: &mut Ranges,
+) -> anyhow::Result<()> {
+    let options = DelimitedOptions {
+        delimiter: opt.delimiter,
+        column: opt.column.clone(),
+        header: match (opt.header, opt.no_header) {
+            (true, _) => Header::Present,
+            (_, true) => Header::Absent,
+            _ => Header::Auto,
+        },
+        parse: options,
+    };
+    let warn = options.parse.host_bits == HostBits::Warn;
+    for item in DelimitedReader::new(input, options) {
+        match item.context("Failed to read input")? {
+            Ok(record) => {
+                if let (true, Some(warning)) = (warn, record.warning) {
+                    warn_truncated(record.line_number, record.range, warning);
+                }
+                ranges.push(record.range)
+            }
+            Err(entry) if !opt.ignore_invalid => ranges.invalid.push(entry),
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_rir(opt: &Opt, input: Box<dyn BufRead>, ranges: &mut Ranges) -> anyhow::Result<()> {
+    let filter = RirFilter {
+        countries: opt.country.clone(),
+        registries: opt.registry.clone(),
+        statuses: opt.status.clone(),
+    };
+    for item in RirReader::new(input) {
+        match item.context("Failed to read input")? {
+            Ok(delegation) if filter.matches(&delegation) => ranges.push(delegation.range),
+            Ok(_) => {}
+            Err(entry) if !opt.ignore_invalid => ranges.invalid.push(entry),
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn geo_filter(opt: &Opt) -> GeoFilter {
+    GeoFilter {
+        countries: opt.country.clone(),
+        continents: opt.continent.clone(),
+        asns: opt.asn.clone(),
+    }
+}
+
+fn read_mmdb(opt: &Opt, mut input: Box<dyn BufRead>, ranges: &mut Ranges) -> anyhow::Result<()> {
+    let mut buf = Vec::new();
+    input
+        .read_to_end(&mut buf)
+        .context("Failed to read input")?;
+    let reader = MmdbReader::from_bytes(buf).context("Failed to read MaxMind DB")?;
+    let networks = reader
+        .networks()
+        .map(|network| network.map(|(range, data)| GeoNetwork::from_mmdb(range, &data)))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to read MaxMind DB")?;
+    ranges.extend_split(geo_filter(opt).collect(networks));
+    Ok(())
+}
+
+fn read_geolite2(opt: &Opt, input: Box<dyn BufRead>, ranges: &mut Ranges) -> anyhow::Result<()> {
+    let locations = match &opt.locations {
+        Some(path) => {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to open locations file: {}", path.display()))?;
+            read_geolite2_locations(BufReader::new(file))
+                .with_context(|| format!("Failed to read locations file: {}", path.display()))?
+        }
+        None => Default::default(),
+    };
+    let networks = read_all(
+        GeoLite2BlocksReader::new(input, locations),
+        opt,
+        &mut ranges.invalid,
+    )?;
+    ranges.extend_split(geo_filter(opt).collect(networks));
+    Ok(())
+}
+
+fn read_geoip_dat_tags(
+    opt: &Opt,
+    mut input: Box<dyn BufRead>,
+    ranges: &mut Ranges,
+) -> anyhow::Result<()> {
+    let mut buf = Vec::new();
+    input
+        .read_to_end(&mut buf)
+        .context("Failed to read input")?;
+    let mut tags = read_geoip_dat(&buf).context("Failed to read geoip.dat")?;
+    for path in &opt.merge_geoip_dat {
+        let buf = fs::read(path)
+            .with_context(|| format!("Failed to open geoip.dat: {}", path.display()))?;
+        tags.extend(
+            read_geoip_dat(&buf)
+                .with_context(|| format!("Failed to read geoip.dat: {}", path.display()))?,
+        );
+    }
+    let selected = |tag: &GeoIpTag| {
+        opt.country.is_empty()
+            || opt
+                .country
+                .iter()
+                .any(|code| code.eq_ignore_ascii_case(&tag.code))
+    };
+    ranges.tags = merge_geoip_tags(tags)
+        .into_iter()
+        .filter(selected)
+        .collect();
+    for tag in &ranges.tags {
+        ranges.v4.extend_from_slice(&tag.v4);
+        ranges.v6.extend_from_slice(&tag.v6);
+    }
+    Ok(())
+}
+
+/// How many malformed records of a binary input to list.
+const MAX_REPORTED_RECORDS: usize = 10;
+
+fn read_mrt(opt: &Opt, input: Box<dyn BufRead>, ranges: &mut Ranges) -> anyhow::Result<()> {
+    let filter = RouteFilter {
+        origins: opt.asn.clone(),
+    };
+    let mut malformed = Vec::new();
+    for item in MrtReader::new(decompress(input).context("Failed to read input")?) {
+        match item.context("Failed to read input")? {
+            Ok(route) if filter.matches(&route) => ranges.push(route.prefix),
+            Ok(_) => {}
+            Err(e) if !opt.ignore_invalid => malformed.push(e),
+            Err(_) => {}
+        }
+    }
+    // Records are binary, so unlike lines they are reported by number only
+    if !malformed.is_empty() {
+        eprintln!("The following MRT records are malformed:\n");
+        for e in malformed.iter().take(MAX_REPORTED_RECORDS) {
+            eprintln!("{}", e);
+        }
+        if malformed.len() > MAX_REPORTED_RECORDS {
+            eprintln!("and {} more", malformed.len() - MAX_REPORTED_RECORDS);
+        }
+        eprintln!();
+        bail!("{} MRT records are malformed", malformed.len());
+    }
+    Ok(())
+}
+
+fn read_rpsl(opt: &Opt, input: Box<dyn BufRead>, ranges: &mut Ranges) -> anyhow::Result<()> {
+    let mut as_sets = AsSets::default();
+    for path in &opt.as_set_db {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open as-set dump: {}", path.display()))?;
+        let objects = RpslReader::new(BufReader::new(decompress(BufReader::new(file))?));
+        for object in objects {
+            let object = object
+                .with_context(|| format!("Failed to read as-set dump: {}", path.display()))?;
+            if let Ok(object) = object {
+                as_sets.insert(&object);
+            }
+        }
+    }
+    // Hold on to the routes, as the input may have as-sets after them
+    let mut routes = Vec::new();
+    let objects = RpslReader::new(BufReader::new(
+        decompress(input).context("Failed to read input")?,
+    ));
+    for item in objects {
+        match item.context("Failed to read input")? {
+            Ok(object) => {
+                as_sets.insert(&object);
+                match RouteObject::from_rpsl(&object) {
+                    Some(Ok(route)) => routes.push(route),
+                    Some(Err(entry)) if !opt.ignore_invalid => ranges.invalid.push(entry),
+                    _ => {}
+                }
+            }
+            Err(entry) if !opt.ignore_invalid => ranges.invalid.push(entry),
+            Err(_) => {}
+        }
+    }
+    let mut origins = opt.asn.clone();
+    for name in &opt.as_set {
+        let (asns, missing) = as_sets.expand(name);
+        if !missing.is_empty() {
+            eprintln!(
+                "Warning: as-sets not found, so left out of {}: {}",
+                name,
+                missing.join(", ")
+            );
+        }
+        origins.extend(asns);
+    }
+    if !opt.as_set.is_empty() && origins.is_empty() {
+        bail!("The as-sets have no AS numbers to select routes by");
+    }
+    let filter = RpslFilter {
+        origins,
+        maintainers: opt.mnt_by.clone(),
+        sources: opt.source.clone(),
+    };
+    ranges.extend(
+        routes
+            .into_iter()
+            .filter(|route| filter.matches(route))
+            .map(|route| route.prefix),
+    );
+    Ok(())
+}
+
+fn read_iptables(
+    opt: &Opt,
+    input: Box<dyn BufRead>,
+    invalid: &mut Vec<InvalidEntry>,
+) -> anyhow::Result<RangesByName> {
+    let filter = IptablesFilter {
+        tables: opt.table.clone(),
+        chains: opt.chain.clone(),
+        targets: opt.target.clone(),
+    };
+    let rules = read_all(IptablesReader::new(input), opt, invalid)?;
+    let direction = if opt.destination {
+        Direction::Destination
+    } else {
+        Direction::Source
+    };
+    Ok(by_display_name(filter.collect(rules, direction)))
+}
+
+fn read_ipset(
+    opt: &Opt,
+    input: Box<dyn BufRead>,
+    invalid: &mut Vec<InvalidEntry>,
+) -> anyhow::Result<RangesByName> {
+    let filter = IpsetFilter {
+        sets: opt.set.clone(),
+    };
+    let entries = read_all(IpsetReader::new(input), opt, invalid)?;
+    Ok(filter.collect(entries))
+}
+
+fn read_nft(
+    opt: &Opt,
+    input: Box<dyn BufRead>,
+    invalid: &mut Vec<InvalidEntry>,
+) -> anyhow::Result<RangesByName> {
+    let filter = NftFilter {
+        tables: opt.table.clone(),
+        sets: opt.set.clone(),
+    };
+    let elements = read_all(NftReader::new(input), opt, invalid)?;
+    Ok(by_display_name(filter.collect(elements)))
+}
+
+/// Key chains or sets by their display name, to report them.
+fn by_display_name<K: std::fmt::Display>(groups: RangesByName<K>) -> RangesByName {
+    groups
+        .into_iter()
+        .map(|(key, ranges)| (key.to_string(), ranges))
+        .collect()
+}
+
+fn read_access_log(opt: &Opt, input: Box<dyn BufRead>, ranges: &mut Ranges) -> anyhow::Result<()> {
+    #[cfg(feature = "log-json")]
+    let format = match &opt.log_field {
+        Some(field) => LogFormat::Json(field.clone()),
+        None => opt.log_format.clone(),
+    };
+    #[cfg(not(feature = "log-json"))]
+    let format = opt.log_format.clone();
+    let input = BufReader::new(decompress(input).context("Failed to read input")?);
+    let mut counts = HitCounts::default();
+    for item in AccessLogReader::new(input, format) {
+        match item.context("Failed to read input")? {
+            Ok(hit) => counts.insert(hit.client),
+            Err(entry) if !opt.ignore_invalid => ranges.invalid.push(entry),
+            Err(_) => {}
+        }
+    }
+    let filter = HitFilter {
+        min_hits: opt.min_hits,
+        ipv4_prefix_len: opt.widen_ipv4,
+        ipv6_prefix_len: opt.widen_ipv6,
+    };
+    ranges.extend_split(filter.collect(&counts));
+    Ok(())
+}
+
+/// Read VRPs from CSV, or JSON if the input looks like it.
+fn read_vrps(
+    opt: &Opt,
+    mut input: Box<dyn BufRead>,
+    invalid: &mut Vec<InvalidEntry>,
+) -> anyhow::Result<Vec<Vrp>> {
+    let is_json = input
+        .fill_buf()
+        .context("Failed to read VRPs")?
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        == Some(&b'{');
+    let items: Vec<Result<Vrp, InvalidEntry>> = if is_json {
+        #[cfg(feature = "rpki-json")]
+        {
+            let json = io::read_to_string(input).context("Failed to read VRPs")?;
+            parse_vrps_json(&json)?
+        }
+        #[cfg(not(feature = "rpki-json"))]
+        bail!("Reading VRPs as JSON needs the `rpki-json` feature");
+    } else {
+        VrpReader::new(input)
+            .collect::<io::Result<_>>()
+            .context("Failed to read VRPs")?
+    };
+    let mut vrps = Vec::new();
+    for item in items {
+        match item {
+            Ok(vrp) => vrps.push(vrp),
+            Err(entry) if !opt.ignore_invalid => invalid.push(entry),
+            Err(_) => {}
+        }
+    }
+    Ok(vrps)
+}
+
+/// Tell that an entry was accepted only by masking off its host bits.
+fn warn_truncated(line_number: usize, range: EitherIpRange, warning: ParseCidrError) {
+    let range = match range {
+        EitherIpRange::V4(range) => range.to_string(),
+        EitherIpRange::V6(range) => range.to_string(),
+    };
+    eprintln!(
+        "Warning: line {}: {}, kept as {}",
+        line_number, warning, range
+    );
+}
+
+/// Print the invalid entries and fail, if there are any. `expected` names what
+/// they should have been, e.g. `VRPs`.
+fn check_invalid(invalid_entries: &[InvalidEntry], expected: &str) -> anyhow::Result<()> {
+    if !invalid_entries.is_empty() {
+        eprintln!("The following lines are not valid {}:\n", expected);
+        for entry in invalid_entries {
+            eprintln!("{}", entry);
+        }
+        eprintln!();
+        bail!("Some lines are invalid");
+    }
+    Ok(())
+}
+
+fn open_output(opt: &Opt) -> anyhow::Result<Box<dyn Write>> {
+    Ok(match &opt.output {
+        Some(path) => Box::new(
+            File::create(path)
+                .with_context(|| format!("Failed to create output file: {}", path.display()))?,
+        ),
+        None => Box::new(io::stdout()),
+    })
+}
+
+/// Print each announcement of the input with its Route Origin Validation state.
+fn validate(opt: &Opt, input: Box<dyn BufRead>, path: &Path) -> anyhow::Result<()> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open VRP file: {}", path.display()))?;
+    let mut invalid_entries = Vec::new();
+    let vrps: VrpSet = read_vrps(opt, Box::new(BufReader::new(file)), &mut invalid_entries)
+        .with_context(|| format!("Failed to read VRP file: {}", path.display()))?
+        .into_iter()
+        .collect();
+    if !invalid_entries.is_empty() {
+        eprintln!("In the VRP file {}:", path.display());
+        check_invalid(&invalid_entries, Format::Vrps.describe())?;
+    }
+
+    let mut announcements = Vec::new();
+    for item in AnnouncementReader::new(input) {
+        match item.context("Failed to read input")? {
+            Ok(announcement) => announcements.push(announcement),
+            Err(entry) if !opt.ignore_invalid => invalid_entries.push(entry),
+            Err(_) => {}
+        }
+    }
+    check_invalid(&invalid_entries, "announcements of a prefix by an AS")?;
+
+    let mut output = open_output(opt)?;
+    for announcement in announcements {
+        let prefix = match announcement.prefix {
+            EitherIpRange::V4(range) if !opt.v6only || opt.v4only => range.to_string(),
+            EitherIpRange::V6(range) if !opt.v4only || opt.v6only => range.to_string(),
+            _ => continue,
+        };
+        let state = vrps.validate(announcement.prefix, announcement.origin);
+        writeln!(output, "{} AS{} {}", prefix, announcement.origin, state)?;
+    }
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     let opt = Opt::parse();
     let (v4, v6) = if !(opt.v4only ^ opt.v6only) {
@@ -57,31 +994,41 @@ fn main() -> anyhow::Result<()> {
         (opt.v4only, opt.v6only)
     };
 
-    // Read input
-    let input = match &opt.input {
-        Some(path) => std::fs::read_to_string(path)
-            .with_context(|| format!("Failed to read input file: {}", path.display()))?,
-        None => {
-            let mut buf = String::new();
-            io::stdin()
-                .lock()
-                .read_to_string(&mut buf)
-                .context("Failed to read from stdin")?;
-            buf
+    let options = ParseOptions {
+        host_bits: if opt.warn_host_bits {
+            HostBits::Warn
+        } else if opt.truncate_host_bits {
+            HostBits::Truncate
+        } else {
+            HostBits::Reject
+        },
+        ipv4_shorthand: opt.ipv4_shorthand,
+        comment_markers: opt.comment_markers.clone(),
+    };
+
+    // Read input line by line so that memory is bounded by the number of ranges
+    let input: Box<dyn BufRead> = match &opt.input {
+        Some(path) => {
+            Box::new(BufReader::new(File::open(path).with_context(|| {
+                format!("Failed to open input file: {}", path.display())
+            })?))
         }
+        None => Box::new(io::stdin().lock()),
     };
 
-    let (v4ranges, v6ranges, invalid_entries) = parse_cidrs(&input);
+    if let Some(path) = &opt.validate {
+        return validate(&opt, input, path);
+    }
+
+    let Ranges {
+        v4: v4ranges,
+        v6: v6ranges,
+        invalid: invalid_entries,
+        tags,
+    } = read_ranges(&opt, input, options)?;
 
     // Handle invalid entries
-    if !opt.ignore_invalid && !invalid_entries.is_empty() {
-        eprintln!("The following lines are not valid CIDRs, IPs, or \"#\"-prefixed comments:\n");
-        for entry in &invalid_entries {
-            eprintln!("{}", entry);
-        }
-        eprintln!();
-        bail!("Some lines are invalid");
-    }
+    check_invalid(&invalid_entries, opt.format().describe())?;
 
     // Process
     let v4ranges = aggregator::process(v4ranges, opt.reverse, opt.exclude_reserved);
@@ -103,14 +1050,62 @@ fn main() -> anyhow::Result<()> {
         );
     }
 
+    if let Some(path) = &opt.mmdb_output {
+        let mut writer = if v6 {
+            MmdbWriter::ipv6()
+        } else {
+            MmdbWriter::ipv4()
+        };
+        writer.database_type = "cidr-aggregator".to_owned();
+        let data = MmdbValue::Map(
+            opt.mmdb_field
+                .iter()
+                .map(|(key, value)| (key.clone(), MmdbValue::String(value.clone())))
+                .collect(),
+        );
+        let v4_blocks = if v4 { v4ranges.as_slice() } else { &[] };
+        let v6_blocks = if v6 { v6ranges.as_slice() } else { &[] };
+        for &range in v4_blocks {
+            writer.insert(range.into(), &data)?;
+        }
+        for &range in v6_blocks {
+            writer.insert(range.into(), &data)?;
+        }
+        fs::write(path, writer.to_bytes()?)
+            .with_context(|| format!("Failed to write MaxMind DB: {}", path.display()))?;
+    }
+
+    if let Some(path) = &opt.geoip_dat_output {
+        let mut tags = if opt.geoip_dat {
+            // Each tag goes through the pipeline on its own
+            let mut tags = tags;
+            for tag in &mut tags {
+                tag.process(opt.reverse, opt.exclude_reserved);
+            }
+            tags
+        } else {
+            vec![GeoIpTag {
+                // Required by the `geoip_dat_tags` group without --geoip-dat
+                code: opt.geoip_dat_tag.clone().unwrap_or_default(),
+                v4: v4ranges.clone(),
+                v6: v6ranges.clone(),
+                reverse_match: false,
+            }]
+        };
+        for tag in &mut tags {
+            if !v4 {
+                tag.v4.clear();
+            }
+            if !v6 {
+                tag.v6.clear();
+            }
+        }
+        fs::write(path, write_geoip_dat(&tags))
+            .with_context(|| format!("Failed to write geoip.dat: {}", path.display()))?;
+    }
+
     // Write output
-    let mut output: Box<dyn Write> = match &opt.output {
-        Some(path) => Box::new(
-            File::create(path)
-                .with_context(|| format!("Failed to create output file: {}", path.display()))?,
-        ),
-        None => Box::new(io::stdout()),
-    };
+    let mut output = open_output(&opt)?;
 
     if v4 && !v4ranges.is_empty() {
         writeln!(output, "{}", v4ranges.export())?;
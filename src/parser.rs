@@ -1,39 +1,670 @@
 //! CIDR string parser.
 
-use crate::{EitherIpRange, Ipv4Range, Ipv6Range};
+use std::cmp::max;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::utils::{ip_addr_to_bit_length, ip_addr_trailing_zeros, truncate_host_bits};
+use crate::{EitherIpRange, IpRange, Ipv4Range, Ipv6Range};
+
+/// Why a string could not be parsed as an [`EitherIpRange`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ParseCidrError {
+    /// The part before `/` is not a valid IPv4 or IPv6 address.
+    InvalidAddress,
+    /// The part after `/` is neither a decimal number nor, for IPv4, a dotted
+    /// netmask or wildcard mask.
+    InvalidPrefixLength,
+    /// The prefix length exceeds the address width (32 for IPv4, 128 for IPv6).
+    PrefixTooLong { prefix_len: u32, max_len: u32 },
+    /// The address has bits set beyond the prefix, e.g. `192.168.1.5/24`.
+    HostBitsSet { prefix_len: u32 },
+    /// A dotted mask whose one bits are not contiguous, e.g. `255.0.255.0`.
+    NonContiguousMask,
+    /// The two ends of a `first-last` range are of different address families.
+    MixedAddressFamilies,
+    /// The last address of a `first-last` range precedes the first.
+    ReversedRange,
+}
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseCidrError::InvalidAddress => write!(f, "invalid IP address"),
+            ParseCidrError::InvalidPrefixLength => {
+                write!(f, "prefix length is neither a number nor a dotted mask")
+            }
+            ParseCidrError::PrefixTooLong {
+                prefix_len,
+                max_len,
+            } => write!(
+                f,
+                "prefix length /{} exceeds the maximum of /{}",
+                prefix_len, max_len
+            ),
+            ParseCidrError::HostBitsSet { prefix_len } => {
+                write!(f, "host bits are set beyond the /{} prefix", prefix_len)
+            }
+            ParseCidrError::NonContiguousMask => {
+                write!(f, "mask is neither a netmask nor a wildcard mask")
+            }
+            ParseCidrError::MixedAddressFamilies => {
+                write!(f, "range mixes IPv4 and IPv6 addresses")
+            }
+            ParseCidrError::ReversedRange => {
+                write!(f, "last address of the range precedes the first")
+            }
+        }
+    }
+}
+
+impl Error for ParseCidrError {}
+
+/// Why a record of the input was rejected, as carried by [`InvalidEntry`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RecordError {
+    /// The address or prefix of the record is invalid.
+    Cidr(ParseCidrError),
+    /// A delimited record has fewer columns than the one holding the range,
+    /// or a structured record lacks the field holding it.
+    MissingColumn,
+    /// An AS number is neither a decimal number nor one prefixed with `AS`.
+    InvalidAsn,
+    /// A record of a structured format, such as an RIR statistics file, lacks
+    /// fields or has values of the wrong kind.
+    Malformed,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::Cidr(error) => error.fmt(f),
+            RecordError::MissingColumn => write!(f, "record has no such column"),
+            RecordError::InvalidAsn => write!(f, "invalid AS number"),
+            RecordError::Malformed => write!(f, "malformed record"),
+        }
+    }
+}
+
+impl Error for RecordError {}
+
+impl From<ParseCidrError> for RecordError {
+    fn from(error: ParseCidrError) -> Self {
+        RecordError::Cidr(error)
+    }
+}
+
+/// A line of input that could not be parsed, as collected by [`parse_cidrs`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InvalidEntry {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    /// The offending line, with surrounding whitespace trimmed.
+    pub line: String,
+    pub error: RecordError,
+}
+
+impl fmt::Display for InvalidEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {} ({})",
+            self.line_number, self.line, self.error
+        )
+    }
+}
+
+/// What to do with entries whose address has bits set beyond the prefix,
+/// such as `192.168.1.5/24`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum HostBits {
+    /// Reject the entry as invalid.
+    #[default]
+    Reject,
+    /// Mask off the host bits and keep the network, e.g. `192.168.1.0/24`.
+    Truncate,
+    /// Like `Truncate`, but also record the entry in [`ParsedCidrs::truncated`].
+    Warn,
+}
+
+/// Options controlling how lenient the parser is.
+///
+/// The default is as strict as [`EitherIpRange::from_str`](std::str::FromStr),
+/// except that address ranges are accepted, with `#` starting a comment.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParseOptions {
+    pub host_bits: HostBits,
+    /// Accept abbreviated IPv4 addresses with trailing zero octets left out,
+    /// as in `10/8`, `172.16/12` or `192.168.1`.
+    ///
+    /// Without a prefix length, a partial address gets its classful prefix
+    /// (`/8` below `128.0.0.0`, `/16` below `192.0.0.0`, `/24` below
+    /// `224.0.0.0`, `/4` above), widened to at least cover the octets given:
+    /// `10` is `10.0.0.0/8`, `10.1` is `10.1.0.0/16` and `192.168` is
+    /// `192.168.0.0/24`.
+    pub ipv4_shorthand: bool,
+    /// Markers that start a comment, such as `#`, `;` or `//`. Everything from
+    /// the first marker to the end of the line is kept as the annotation of
+    /// the entry; lines holding only a comment are skipped.
+    pub comment_markers: Vec<String>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            host_bits: HostBits::default(),
+            ipv4_shorthand: false,
+            comment_markers: vec!["#".to_owned()],
+        }
+    }
+}
+
+/// A successfully parsed line, as yielded by [`parse_entries`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Entry {
+    /// 1-based line number in the input.
+    pub line_number: usize,
+    pub range: EitherIpRange,
+    /// The trailing comment, without its marker and surrounding whitespace.
+    pub annotation: Option<String>,
+    /// The error tolerated to accept the entry, if any, e.g.
+    /// [`ParseCidrError::HostBitsSet`] under [`HostBits::Truncate`].
+    pub warning: Option<ParseCidrError>,
+}
+
+/// Everything collected by [`parse_cidrs_with_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ParsedCidrs {
+    pub v4: Vec<Ipv4Range>,
+    pub v6: Vec<Ipv6Range>,
+    /// Entries that were rejected.
+    pub invalid: Vec<InvalidEntry>,
+    /// Entries that were invalid as written but accepted after masking off
+    /// host bits. Only filled under [`HostBits::Warn`].
+    pub truncated: Vec<InvalidEntry>,
+}
+
+/// Parse a single entry: a CIDR block, a bare IP address as a single-host
+/// range, or an explicit `first-last` address range.
+///
+/// IPv4 prefixes may also be given as a dotted netmask or ACL wildcard mask,
+/// after either `/` or whitespace: `10.0.0.0/255.255.0.0`,
+/// `10.0.0.0 255.255.0.0` and `10.0.0.0 0.0.255.255` all mean `10.0.0.0/16`.
+///
+/// Ranges may have spaces around the hyphen, and IPv4 ranges may abbreviate
+/// the last address to its final octet, as in `10.0.0.5-77`. They are not
+/// necessarily CIDR blocks, so normalize them before display.
+///
+/// On success, also returns the error that was tolerated to produce the range,
+/// if any — currently only [`ParseCidrError::HostBitsSet`] when `options`
+/// allows truncation.
+pub fn parse_entry(
+    s: &str,
+    options: &ParseOptions,
+) -> Result<(EitherIpRange, Option<ParseCidrError>), ParseCidrError> {
+    match s.split_once('-').filter(|_| !s.contains('/')) {
+        Some((first, last)) => {
+            parse_address_range(first.trim(), last.trim()).map(|range| (range, None))
+        }
+        None => parse_cidr(s, options),
+    }
+}
+
+/// Like [`parse_entry`], but only for CIDR blocks and bare addresses, so the
+/// range is always a CIDR block.
+pub(crate) fn parse_cidr(
+    s: &str,
+    options: &ParseOptions,
+) -> Result<(EitherIpRange, Option<ParseCidrError>), ParseCidrError> {
+    let (ip, cidr) = match s.split_once('/') {
+        Some(split) => split,
+        // Cisco/Windows style: the address and a dotted mask separated by spaces
+        None => match s.split_once(char::is_whitespace) {
+            Some((ip, mask)) if mask.contains('.') => (ip, mask.trim()),
+            _ => (s, ""),
+        },
+    };
+    let (mut ip, implied_len) = match ip.parse::<IpAddr>() {
+        Ok(ip) => (ip, None),
+        Err(_) if options.ipv4_shorthand => expand_ipv4_shorthand(ip)
+            .map(|(ip, len)| (IpAddr::V4(ip), Some(len)))
+            .ok_or(ParseCidrError::InvalidAddress)?,
+        Err(_) => return Err(ParseCidrError::InvalidAddress),
+    };
+    let max_len = ip_addr_to_bit_length(ip);
+    let cidr = if cidr.is_empty() {
+        implied_len.unwrap_or(max_len)
+    } else if cidr.contains('.') {
+        match (ip, cidr.parse::<Ipv4Addr>()) {
+            (IpAddr::V4(ip), Ok(mask)) => mask_to_prefix_len(ip, mask)?,
+            _ => return Err(ParseCidrError::InvalidPrefixLength),
+        }
+    } else if cidr.bytes().all(|b| b.is_ascii_digit()) {
+        // All digits, so parsing only fails on overflow, i.e. a huge prefix
+        cidr.parse::<u32>().unwrap_or(u32::MAX)
+    } else {
+        return Err(ParseCidrError::InvalidPrefixLength);
+    };
+    if cidr > max_len {
+        return Err(ParseCidrError::PrefixTooLong {
+            prefix_len: cidr,
+            max_len,
+        });
+    }
+    let mut tolerated = None;
+    if ip_addr_trailing_zeros(ip) < max_len - cidr {
+        // a host instead of a range
+        let error = ParseCidrError::HostBitsSet { prefix_len: cidr };
+        if options.host_bits == HostBits::Reject {
+            return Err(error);
+        }
+        ip = truncate_host_bits(ip, cidr);
+        tolerated = Some(error);
+    }
+    let range = match ip {
+        IpAddr::V4(ip) => EitherIpRange::V4(Ipv4Range::from_cidr_pair((ip, cidr as u8))),
+        IpAddr::V6(ip) => EitherIpRange::V6(Ipv6Range::from_cidr_pair((ip, cidr as u8))),
+    };
+    Ok((range, tolerated))
+}
+
+/// Expand an IPv4 address with one to three octets given, e.g. `172.16`.
+///
+/// Returns the address padded with zero octets, together with the prefix
+/// length implied when none is written. See [`ParseOptions::ipv4_shorthand`].
+fn expand_ipv4_shorthand(s: &str) -> Option<(Ipv4Addr, u32)> {
+    let mut octets = [0u8; 4];
+    let mut given = 0;
+    for part in s.split('.') {
+        // Same rules as `Ipv4Addr`: decimal only, no leading zeros
+        if given == 3
+            || part.is_empty()
+            || !part.bytes().all(|b| b.is_ascii_digit())
+            || (part.len() > 1 && part.starts_with('0'))
+        {
+            return None;
+        }
+        octets[given] = part.parse().ok()?;
+        given += 1;
+    }
+    let classful_len = match octets[0] {
+        0..=127 => 8,
+        128..=191 => 16,
+        192..=223 => 24,
+        224..=255 => 4,
+    };
+    Some((octets.into(), max(classful_len, 8 * given as u32)))
+}
+
+/// Convert a dotted IPv4 netmask (`255.255.0.0`) or wildcard mask
+/// (`0.0.255.255`) to a prefix length.
+///
+/// `0.0.0.0` and `255.255.255.255` are valid as either kind. They are read as
+/// netmasks, except that `0.0.0.0` after an address other than `0.0.0.0` is
+/// read as the host wildcard of an ACL, since `/0` would not fit it.
+fn mask_to_prefix_len(ip: Ipv4Addr, mask: Ipv4Addr) -> Result<u32, ParseCidrError> {
+    let mask = u32::from(mask);
+    let is_netmask = |m: u32| m.leading_ones() + m.trailing_zeros() == 32;
+    if is_netmask(mask) && !(mask == 0 && u32::from(ip) != 0) {
+        Ok(mask.leading_ones())
+    } else if is_netmask(!mask) {
+        Ok(mask.leading_zeros())
+    } else {
+        Err(ParseCidrError::NonContiguousMask)
+    }
+}
+
+/// Parse the two ends of a `first-last` range.
+fn parse_address_range(first: &str, last: &str) -> Result<EitherIpRange, ParseCidrError> {
+    let first = first
+        .parse::<IpAddr>()
+        .map_err(|_| ParseCidrError::InvalidAddress)?;
+    let last = match (first, last.parse::<u8>()) {
+        // Short form: only the final octet of the last address is given
+        (IpAddr::V4(first), Ok(octet)) if !last.starts_with('+') => {
+            let [a, b, c, _] = first.octets();
+            IpAddr::V4([a, b, c, octet].into())
+        }
+        _ => last
+            .parse::<IpAddr>()
+            .map_err(|_| ParseCidrError::InvalidAddress)?,
+    };
+    match (first, last) {
+        (IpAddr::V4(first), IpAddr::V4(last)) if first <= last => Ok(EitherIpRange::V4(
+            Ipv4Range::from_cidr_pair_decimal((first.into(), last.into())),
+        )),
+        (IpAddr::V6(first), IpAddr::V6(last)) if first <= last => Ok(EitherIpRange::V6(
+            Ipv6Range::from_cidr_pair_decimal((first.into(), last.into())),
+        )),
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+            Err(ParseCidrError::ReversedRange)
+        }
+        _ => Err(ParseCidrError::MixedAddressFamilies),
+    }
+}
+
+/// Split a line into its content and the comment after the earliest marker.
+fn split_comment<'a>(line: &'a str, markers: &[String]) -> (&'a str, Option<&'a str>) {
+    match markers
+        .iter()
+        .filter(|marker| !marker.is_empty())
+        .filter_map(|marker| line.find(marker.as_str()).map(|i| (i, marker.len())))
+        .min()
+    {
+        Some((i, len)) => {
+            let comment = line[i + len..].trim();
+            (
+                line[..i].trim_end(),
+                Some(comment).filter(|c| !c.is_empty()),
+            )
+        }
+        None => (line, None),
+    }
+}
+
+/// The outcome of parsing one line, borrowing from it.
+type ParsedLine<'a> =
+    Result<(EitherIpRange, Option<&'a str>, Option<ParseCidrError>), ParseCidrError>;
+
+/// Parse one trimmed line, or return `None` if it holds no entry.
+fn parse_line<'a>(line: &'a str, options: &ParseOptions) -> Option<ParsedLine<'a>> {
+    let (content, annotation) = split_comment(line, &options.comment_markers);
+    if content.is_empty() {
+        return None;
+    }
+    Some(parse_entry(content, options).map(|(range, warning)| (range, annotation, warning)))
+}
 
 /// Parse a string of CIDR entries (one per line).
 ///
-/// Lines starting with `#` are treated as comments and skipped.
-/// Empty lines and whitespace-only lines are also skipped.
+/// Each line holds a CIDR block, a bare IP address, or an address range such
+/// as `10.0.0.5-10.0.0.77` (see [`parse_entry`]). Anything after a `#` is a
+/// comment, and lines holding only a comment are skipped. Empty lines and
+/// whitespace-only lines are also skipped.
+///
+/// Ranges are returned as written, so they are not necessarily CIDR blocks;
+/// aggregate and normalize them before export.
 ///
-/// Returns a triple of `(IPv4 ranges, IPv6 ranges, invalid lines)`.
-pub fn parse_cidrs(cidrs: &str) -> (Vec<Ipv4Range>, Vec<Ipv6Range>, Vec<String>) {
-    let mut v4ranges = Vec::new();
-    let mut v6ranges = Vec::new();
-    let mut invalid_entries = Vec::new();
-    for line in cidrs
+/// Returns a triple of `(IPv4 ranges, IPv6 ranges, invalid entries)`.
+pub fn parse_cidrs(cidrs: &str) -> (Vec<Ipv4Range>, Vec<Ipv6Range>, Vec<InvalidEntry>) {
+    let parsed = parse_cidrs_with_options(cidrs, &ParseOptions::default());
+    (parsed.v4, parsed.v6, parsed.invalid)
+}
+
+/// Like [`parse_cidrs`], with the leniency controlled by `options`.
+///
+/// ```
+/// use cidr_aggregator::parser::{parse_cidrs_with_options, HostBits, ParseOptions};
+///
+/// let options = ParseOptions {
+///     host_bits: HostBits::Warn,
+///     ..Default::default()
+/// };
+/// let parsed = parse_cidrs_with_options("192.168.1.5/24\n10.0.0.0/8", &options);
+/// assert_eq!(parsed.v4.len(), 2);
+/// assert_eq!(parsed.truncated[0].line_number, 1);
+/// ```
+pub fn parse_cidrs_with_options(cidrs: &str, options: &ParseOptions) -> ParsedCidrs {
+    let mut parsed = ParsedCidrs::default();
+    for (line_number, line) in cidrs
         .lines()
         .map(str::trim)
-        .filter(|&line| !line.is_empty() && !line.starts_with('#'))
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
     {
-        if let Ok(range) = line.parse::<EitherIpRange>() {
-            match range {
-                EitherIpRange::V4(r) => v4ranges.push(r),
-                EitherIpRange::V6(r) => v6ranges.push(r),
+        match parse_line(line, options) {
+            None => {}
+            Some(Ok((range, _, warning))) => {
+                match range {
+                    EitherIpRange::V4(r) => parsed.v4.push(r),
+                    EitherIpRange::V6(r) => parsed.v6.push(r),
+                }
+                if let (Some(error), HostBits::Warn) = (warning, options.host_bits) {
+                    parsed.truncated.push(InvalidEntry {
+                        line_number,
+                        line: line.to_owned(),
+                        error: error.into(),
+                    });
+                }
             }
-        } else {
-            invalid_entries.push(line.to_owned());
+            Some(Err(error)) => parsed.invalid.push(InvalidEntry {
+                line_number,
+                line: line.to_owned(),
+                error: error.into(),
+            }),
         }
     }
 
-    (v4ranges, v6ranges, invalid_entries)
+    parsed
+}
+
+/// Parse lines one at a time, keeping the comment of each entry.
+///
+/// ```
+/// use cidr_aggregator::parser::{parse_entries, ParseOptions};
+///
+/// let options = ParseOptions {
+///     comment_markers: vec!["#".into(), ";".into()],
+///     ..Default::default()
+/// };
+/// let input = "10.0.0.0/8  # corp VPN\n1.2.3.0/24 ; spamhaus SBL123";
+/// let annotations = parse_entries(input, &options)
+///     .map(|entry| entry.unwrap().annotation.unwrap())
+///     .collect::<Vec<_>>();
+/// assert_eq!(annotations, ["corp VPN", "spamhaus SBL123"]);
+/// ```
+pub fn parse_entries<'a>(
+    input: &'a str,
+    options: &'a ParseOptions,
+) -> impl Iterator<Item = Result<Entry, InvalidEntry>> + 'a {
+    input
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter_map(move |(i, line)| parse_line_to_entry(i + 1, line, options))
+}
+
+/// Like [`parse_line`], with owned results carrying the line number.
+fn parse_line_to_entry(
+    line_number: usize,
+    line: &str,
+    options: &ParseOptions,
+) -> Option<Result<Entry, InvalidEntry>> {
+    parse_line(line, options).map(|parsed| match parsed {
+        Ok((range, annotation, warning)) => Ok(Entry {
+            line_number,
+            range,
+            annotation: annotation.map(str::to_owned),
+            warning,
+        }),
+        Err(error) => Err(InvalidEntry {
+            line_number,
+            line: line.to_owned(),
+            error: error.into(),
+        }),
+    })
+}
+
+/// Numbered lines of any [`BufRead`], the base of the streaming readers of
+/// this crate.
+///
+/// Each line is returned without its line terminator, together with its
+/// 1-based line number. A read error, including a line that is not valid
+/// UTF-8, is returned once and ends the stream. The failed line is counted
+/// all the same, so [`line_number`](LineReader::line_number) stays accurate.
+///
+/// ```
+/// use cidr_aggregator::parser::LineReader;
+///
+/// let mut lines = LineReader::new(&b"10.0.0.0/8\r\n\xff\n192.0.2.0/24\n"[..]);
+/// assert_eq!(lines.next_line().unwrap()?, (1, "10.0.0.0/8"));
+/// assert!(lines.next_line().unwrap().is_err());
+/// assert!(lines.next_line().is_none());
+/// assert_eq!(lines.line_number(), 2);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct LineReader<R: BufRead> {
+    reader: R,
+    buf: Vec<u8>,
+    line: String,
+    line_number: usize,
+    lossy: bool,
+    done: bool,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(reader: R) -> Self {
+        LineReader {
+            reader,
+            buf: Vec::new(),
+            line: String::new(),
+            line_number: 0,
+            lossy: false,
+            done: false,
+        }
+    }
+
+    /// Like [`new`](LineReader::new), but replace invalid UTF-8 with
+    /// U+FFFD instead of failing, for text that need not be UTF-8
+    /// throughout, such as logs.
+    pub fn lossy(reader: R) -> Self {
+        LineReader {
+            lossy: true,
+            ..LineReader::new(reader)
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// The line last read, without its line terminator.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the next line and its line number, or return `None` at the end of
+    /// the input or after an error.
+    pub fn next_line(&mut self) -> Option<io::Result<(usize, &str)>> {
+        if self.done {
+            return None;
+        }
+        self.buf.clear();
+        self.line.clear();
+        match self.reader.read_until(b'\n', &mut self.buf) {
+            Ok(0) => {
+                self.done = true;
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.line_number += 1;
+        let bytes = self.buf.strip_suffix(b"\n").unwrap_or(&self.buf);
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        match std::str::from_utf8(bytes) {
+            Ok(line) => self.line.push_str(line),
+            Err(_) if self.lossy => self.line.push_str(&String::from_utf8_lossy(bytes)),
+            Err(e) => {
+                self.done = true;
+                return Some(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+            }
+        }
+        Some(Ok((self.line_number, &self.line)))
+    }
+}
+
+/// Streaming counterpart of [`parse_entries`] over any [`BufRead`].
+///
+/// Lines are read and parsed one at a time, so memory use does not grow with
+/// the size of the input. Each item is the outcome of parsing one line, or an
+/// I/O error, which ends the stream as described for [`LineReader`].
+///
+/// ```
+/// use std::io::BufReader;
+/// use cidr_aggregator::parser::{CidrReader, ParseOptions};
+///
+/// let input = BufReader::new("10.0.0.0/8\n# comment\nbad\n".as_bytes());
+/// let mut ranges = Vec::new();
+/// let mut invalid = Vec::new();
+/// for item in CidrReader::new(input, ParseOptions::default()) {
+///     match item.unwrap() {
+///         Ok(entry) => ranges.push(entry.range),
+///         Err(entry) => invalid.push(entry.line_number),
+///     }
+/// }
+/// assert_eq!(ranges.len(), 1);
+/// assert_eq!(invalid, [3]);
+/// ```
+#[derive(Debug)]
+pub struct CidrReader<R: BufRead> {
+    lines: LineReader<R>,
+    options: ParseOptions,
+}
+
+impl<R: BufRead> CidrReader<R> {
+    pub fn new(reader: R, options: ParseOptions) -> Self {
+        CidrReader {
+            lines: LineReader::new(reader),
+            options,
+        }
+    }
+
+    /// Number of lines read so far.
+    pub fn line_number(&self) -> usize {
+        self.lines.line_number()
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.lines.into_inner()
+    }
+}
+
+impl<R: BufRead> Iterator for CidrReader<R> {
+    type Item = io::Result<Result<Entry, InvalidEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line_number, line) = match self.lines.next_line()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(parsed) = parse_line_to_entry(line_number, line.trim(), &self.options) {
+                return Some(Ok(parsed));
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn cidr_errors(invalid: &[InvalidEntry]) -> Vec<ParseCidrError> {
+        invalid
+            .iter()
+            .map(|entry| match entry.error {
+                RecordError::Cidr(error) => error,
+                error => panic!("not an address error: {}", error),
+            })
+            .collect()
+    }
+
     #[test]
     fn empty_input() {
         let (v4, v6, invalid) = parse_cidrs("");
@@ -93,8 +724,420 @@ mod tests {
         let (v4, _v6, invalid) = parse_cidrs("not-a-cidr\n10.0.0.0/8\nalso-not-valid");
         assert_eq!(v4.len(), 1);
         assert_eq!(invalid.len(), 2);
-        assert_eq!(invalid[0], "not-a-cidr");
-        assert_eq!(invalid[1], "also-not-valid");
+        assert_eq!(invalid[0].line, "not-a-cidr");
+        assert_eq!(invalid[0].line_number, 1);
+        assert_eq!(invalid[1].line, "also-not-valid");
+        assert_eq!(invalid[1].line_number, 3);
+    }
+
+    #[test]
+    fn line_numbers_count_skipped_lines() {
+        let (_, _, invalid) = parse_cidrs("# header\n\n10.0.0.0/8\n  bad  \n");
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].line_number, 4);
+        assert_eq!(invalid[0].line, "bad");
+        assert_eq!(
+            invalid[0].error,
+            RecordError::Cidr(ParseCidrError::InvalidAddress)
+        );
+        assert_eq!(invalid[0].to_string(), "line 4: bad (invalid IP address)");
+    }
+
+    #[test]
+    fn reports_error_kinds() {
+        let (_, _, invalid) =
+            parse_cidrs("300.0.0.0/8\n10.0.0.0/x\n10.0.0.0/33\n::/129\n192.168.1.5/24");
+        let errors = cidr_errors(&invalid);
+        assert_eq!(
+            errors,
+            vec![
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::InvalidPrefixLength,
+                ParseCidrError::PrefixTooLong {
+                    prefix_len: 33,
+                    max_len: 32
+                },
+                ParseCidrError::PrefixTooLong {
+                    prefix_len: 129,
+                    max_len: 128
+                },
+                ParseCidrError::HostBitsSet { prefix_len: 24 },
+            ]
+        );
+    }
+
+    #[test]
+    fn truncate_host_bits() {
+        let options = ParseOptions {
+            host_bits: HostBits::Truncate,
+            ..Default::default()
+        };
+        let parsed = parse_cidrs_with_options("192.168.1.5/24\n2001:db8::1/32\n10.0.0.1", &options);
+        assert!(parsed.invalid.is_empty());
+        assert!(parsed.truncated.is_empty());
+        assert_eq!(
+            parsed.v4,
+            crate::tests::v4s(&["192.168.1.0/24", "10.0.0.1/32"])
+        );
+        assert_eq!(parsed.v6, crate::tests::v6s(&["2001:db8::/32"]));
+    }
+
+    #[test]
+    fn truncate_to_zero_prefix() {
+        let options = ParseOptions {
+            host_bits: HostBits::Truncate,
+            ..Default::default()
+        };
+        let parsed = parse_cidrs_with_options("1.2.3.4/0\n::1/0", &options);
+        assert_eq!(parsed.v4, vec![Ipv4Range::full()]);
+        assert_eq!(parsed.v6, vec![Ipv6Range::full()]);
+    }
+
+    #[test]
+    fn warn_records_truncated_lines() {
+        let options = ParseOptions {
+            host_bits: HostBits::Warn,
+            ..Default::default()
+        };
+        let parsed = parse_cidrs_with_options("10.0.0.0/8\n192.168.1.5/24\nbad", &options);
+        assert_eq!(
+            parsed.v4,
+            crate::tests::v4s(&["10.0.0.0/8", "192.168.1.0/24"])
+        );
+        assert_eq!(
+            parsed.truncated,
+            vec![InvalidEntry {
+                line_number: 2,
+                line: "192.168.1.5/24".to_owned(),
+                error: RecordError::Cidr(ParseCidrError::HostBitsSet { prefix_len: 24 }),
+            }]
+        );
+        assert_eq!(parsed.invalid.len(), 1);
+        assert_eq!(parsed.invalid[0].line, "bad");
+    }
+
+    #[test]
+    fn truncation_does_not_hide_other_errors() {
+        let options = ParseOptions {
+            host_bits: HostBits::Truncate,
+            ..Default::default()
+        };
+        let parsed = parse_cidrs_with_options("10.0.0.1/33\n10.0.0.1/x", &options);
+        assert_eq!(parsed.invalid.len(), 2);
+    }
+
+    #[test]
+    fn address_ranges() {
+        let (v4, v6, invalid) = parse_cidrs(
+            "10.0.0.5-10.0.0.77\n10.0.1.0 - 10.0.1.255\n10.0.2.5-77\n2001:db8::-2001:db8::ff",
+        );
+        assert!(invalid.is_empty());
+        assert_eq!(
+            v4,
+            vec![
+                Ipv4Range::from_cidr_pair_decimal((0x0a000005, 0x0a00004d)),
+                Ipv4Range::from_cidr_pair_decimal((0x0a000100, 0x0a0001ff)),
+                Ipv4Range::from_cidr_pair_decimal((0x0a000205, 0x0a00024d)),
+            ]
+        );
+        assert_eq!(
+            v6,
+            vec![Ipv6Range::from_cidr_pair_decimal((
+                0x2001_0db8 << 96,
+                (0x2001_0db8 << 96) | 0xff
+            ))]
+        );
+    }
+
+    #[test]
+    fn address_range_single_host() {
+        let (v4, _, invalid) = parse_cidrs("10.0.0.5-10.0.0.5\n10.0.0.9-9");
+        assert!(invalid.is_empty());
+        assert_eq!(v4, crate::tests::v4s(&["10.0.0.5/32", "10.0.0.9/32"]));
+    }
+
+    #[test]
+    fn address_range_normalizes() {
+        use crate::Aggregator;
+
+        let (v4, _, _) = parse_cidrs("10.0.0.5-10.0.0.77\n10.0.0.0-10.0.0.4");
+        assert_eq!(
+            v4.aggregated().normalized().export(),
+            "10.0.0.0/26\n10.0.0.64/29\n10.0.0.72/30\n10.0.0.76/31"
+        );
+    }
+
+    #[test]
+    fn address_range_errors() {
+        let (_, _, invalid) = parse_cidrs(
+            "10.0.0.77-10.0.0.5\n10.0.0.5-4\n10.0.0.1-::1\n10.0.0.1-256\n10.0.0.0/24-10.0.1.0\n::1-ff",
+        );
+        let errors = cidr_errors(&invalid);
+        assert_eq!(
+            errors,
+            vec![
+                ParseCidrError::ReversedRange,
+                ParseCidrError::ReversedRange,
+                ParseCidrError::MixedAddressFamilies,
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::InvalidPrefixLength,
+                ParseCidrError::InvalidAddress,
+            ]
+        );
+    }
+
+    #[test]
+    fn netmasks_and_wildcards() {
+        let (v4, _, invalid) = parse_cidrs(
+            "10.0.0.0/255.255.0.0\n10.1.0.0 255.255.0.0\n10.2.0.0\t0.0.255.255\n10.3.0.0 255.255.255.128",
+        );
+        assert!(invalid.is_empty(), "{:?}", invalid);
+        assert_eq!(
+            v4,
+            crate::tests::v4s(&["10.0.0.0/16", "10.1.0.0/16", "10.2.0.0/16", "10.3.0.0/25"])
+        );
+    }
+
+    #[test]
+    fn ambiguous_masks() {
+        let (v4, _, invalid) = parse_cidrs(
+            "0.0.0.0 0.0.0.0\n10.0.0.1 0.0.0.0\n10.0.0.2 255.255.255.255\n10.0.0.3/0.0.0.0",
+        );
+        assert!(invalid.is_empty(), "{:?}", invalid);
+        assert_eq!(
+            v4,
+            crate::tests::v4s(&["0.0.0.0/0", "10.0.0.1/32", "10.0.0.2/32", "10.0.0.3/32"])
+        );
+    }
+
+    #[test]
+    fn mask_errors() {
+        let (_, _, invalid) = parse_cidrs(
+            "10.0.0.0 255.0.255.0\n10.0.0.0/0.255.0.255\n10.0.0.0 255.255.0.1\n2001:db8::/255.255.0.0\n10.0.0.0/255.255.0\n10.0.0.0 24\n10.1.0.0 255.0.0.0",
+        );
+        let errors = cidr_errors(&invalid);
+        assert_eq!(
+            errors,
+            vec![
+                ParseCidrError::NonContiguousMask,
+                ParseCidrError::NonContiguousMask,
+                ParseCidrError::NonContiguousMask,
+                ParseCidrError::InvalidPrefixLength,
+                ParseCidrError::InvalidPrefixLength,
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::HostBitsSet { prefix_len: 8 },
+            ]
+        );
+    }
+
+    #[test]
+    fn ipv4_shorthand_with_prefix() {
+        let options = ParseOptions {
+            ipv4_shorthand: true,
+            ..Default::default()
+        };
+        let parsed =
+            parse_cidrs_with_options("10/8\n172.16/12\n192.168/16\n192.168.1/24", &options);
+        assert!(parsed.invalid.is_empty(), "{:?}", parsed.invalid);
+        assert_eq!(
+            parsed.v4,
+            crate::tests::v4s(&[
+                "10.0.0.0/8",
+                "172.16.0.0/12",
+                "192.168.0.0/16",
+                "192.168.1.0/24"
+            ])
+        );
+    }
+
+    #[test]
+    fn ipv4_shorthand_classful() {
+        let options = ParseOptions {
+            ipv4_shorthand: true,
+            ..Default::default()
+        };
+        let parsed = parse_cidrs_with_options(
+            "10\n10.1\n172.16\n192.168\n192.168.1\n224\n10.0.0.1",
+            &options,
+        );
+        assert!(parsed.invalid.is_empty(), "{:?}", parsed.invalid);
+        assert_eq!(
+            parsed.v4,
+            crate::tests::v4s(&[
+                "10.0.0.0/8",
+                "10.1.0.0/16",
+                "172.16.0.0/16",
+                "192.168.0.0/24",
+                "192.168.1.0/24",
+                "224.0.0.0/8",
+                "10.0.0.1/32",
+            ])
+        );
+    }
+
+    #[test]
+    fn ipv4_shorthand_is_opt_in() {
+        let (v4, _, invalid) = parse_cidrs("10/8\n192.168.1");
+        assert!(v4.is_empty());
+        assert_eq!(invalid.len(), 2);
+    }
+
+    #[test]
+    fn ipv4_shorthand_errors() {
+        let options = ParseOptions {
+            ipv4_shorthand: true,
+            ..Default::default()
+        };
+        let parsed = parse_cidrs_with_options(
+            "10./8\n10..1\n010/8\n256/8\n1.2.3.4.5\nab/8\n10/4\n10.1/33",
+            &options,
+        );
+        let errors = cidr_errors(&parsed.invalid);
+        assert_eq!(
+            errors,
+            vec![
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::InvalidAddress,
+                ParseCidrError::HostBitsSet { prefix_len: 4 },
+                ParseCidrError::PrefixTooLong {
+                    prefix_len: 33,
+                    max_len: 32
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_comments() {
+        let (v4, v6, invalid) =
+            parse_cidrs("10.0.0.0/8  # corp VPN\n2001:db8::/32#lab\n# only a comment");
+        assert!(invalid.is_empty(), "{:?}", invalid);
+        assert_eq!(v4, crate::tests::v4s(&["10.0.0.0/8"]));
+        assert_eq!(v6, crate::tests::v6s(&["2001:db8::/32"]));
+    }
+
+    #[test]
+    fn other_markers_are_opt_in() {
+        let (_, _, invalid) = parse_cidrs("1.2.3.0/24 ; spamhaus SBL123\n10.0.0.0/8 // corp");
+        assert_eq!(invalid.len(), 2);
+
+        let options = ParseOptions {
+            comment_markers: vec!["#".into(), ";".into(), "//".into()],
+            ..Default::default()
+        };
+        let parsed = parse_cidrs_with_options(
+            "1.2.3.0/24 ; spamhaus SBL123\n10.0.0.0/8 // corp\n; header\n// header",
+            &options,
+        );
+        assert!(parsed.invalid.is_empty(), "{:?}", parsed.invalid);
+        assert_eq!(parsed.v4, crate::tests::v4s(&["1.2.3.0/24", "10.0.0.0/8"]));
+    }
+
+    #[test]
+    fn entries_keep_annotations() {
+        let options = ParseOptions {
+            comment_markers: vec!["#".into(), ";".into()],
+            ..Default::default()
+        };
+        let entries = parse_entries(
+            "# header\n10.0.0.0/8 # corp VPN ; eu\n1.2.3.0/24;\n192.168.1.5/24 ;x\nbad ; why",
+            &options,
+        )
+        .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(
+            entries[0],
+            Ok(Entry {
+                line_number: 2,
+                range: crate::EitherIpRange::V4(crate::tests::v4("10.0.0.0/8")),
+                annotation: Some("corp VPN ; eu".to_owned()),
+                warning: None,
+            })
+        );
+        assert_eq!(entries[1].as_ref().unwrap().annotation, None);
+        assert_eq!(
+            entries[2].as_ref().unwrap_err().error,
+            RecordError::Cidr(ParseCidrError::HostBitsSet { prefix_len: 24 })
+        );
+        let invalid = entries[3].as_ref().unwrap_err();
+        assert_eq!(invalid.line_number, 5);
+        assert_eq!(invalid.line, "bad ; why");
+        assert_eq!(
+            invalid.error,
+            RecordError::Cidr(ParseCidrError::InvalidAddress)
+        );
+    }
+
+    #[test]
+    fn entries_report_tolerated_errors() {
+        let options = ParseOptions {
+            host_bits: HostBits::Truncate,
+            ..Default::default()
+        };
+        let entry = parse_entries("192.168.1.5/24", &options)
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            entry.range,
+            crate::EitherIpRange::V4(crate::tests::v4("192.168.1.0/24"))
+        );
+        assert_eq!(
+            entry.warning,
+            Some(ParseCidrError::HostBitsSet { prefix_len: 24 })
+        );
+    }
+
+    #[test]
+    fn reader_matches_parse_entries() {
+        let input = "# list\n10.0.0.0/8 # corp\n\r\n  2001:db8::/32  \r\nbad\n10.0.0.5-77";
+        let options = ParseOptions::default();
+        let streamed = CidrReader::new(input.as_bytes(), options.clone())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let in_memory = parse_entries(input, &options).collect::<Vec<_>>();
+        assert_eq!(streamed, in_memory);
+        assert_eq!(streamed.len(), 4);
+        assert_eq!(streamed[2].as_ref().unwrap_err().line_number, 5);
+    }
+
+    #[test]
+    fn reader_without_trailing_newline() {
+        let mut reader = CidrReader::new(
+            "10.0.0.0/8\n10.1.0.0/16".as_bytes(),
+            ParseOptions::default(),
+        );
+        assert!(reader.next().unwrap().unwrap().is_ok());
+        let last = reader.next().unwrap().unwrap().unwrap();
+        assert_eq!(last.line_number, 2);
+        assert!(reader.next().is_none());
+        assert_eq!(reader.line_number(), 2);
+    }
+
+    #[test]
+    fn reader_surfaces_io_errors() {
+        let input: &[u8] = b"10.0.0.0/8\n\xff\xfe\n192.0.2.0/24\n";
+        let mut reader = CidrReader::new(input, ParseOptions::default());
+        assert!(reader.next().unwrap().unwrap().is_ok());
+        let error = reader.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // The undecodable line is counted, and ends the stream
+        assert_eq!(reader.line_number(), 2);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn lossy_lines() {
+        let mut lines = LineReader::lossy(&b"a\xffb\r\n\nlast"[..]);
+        assert_eq!(lines.next_line().unwrap().unwrap(), (1, "a\u{fffd}b"));
+        assert_eq!(lines.next_line().unwrap().unwrap(), (2, ""));
+        assert_eq!(lines.next_line().unwrap().unwrap(), (3, "last"));
+        assert_eq!(lines.line(), "last");
+        assert!(lines.next_line().is_none());
     }
 
     #[test]
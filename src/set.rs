@@ -0,0 +1,801 @@
+//! [`IpSet`], a collection of IP ranges that is always sorted and aggregated.
+//!
+//! Unlike the free-standing [`Aggregator`] methods on `Vec<R>`, an `IpSet`
+//! upholds the preconditions of every set operation by construction, and only
+//! hands out CIDR blocks through [`Cidrs`], which normalizes on the fly.
+//!
+//! Sets compose with the usual operators: `|` (union), `&` (intersection),
+//! `-` (difference), `^` (symmetric difference) and `!` (complement).
+
+use std::cmp::min;
+use std::iter::FromIterator;
+use std::mem;
+use std::net::IpAddr;
+use std::ops::{BitAnd, BitOr, BitXor, Not, Sub};
+
+use itertools::{Either, Itertools};
+use num_traits::{Bounded, One, PrimInt, Zero};
+
+use crate::{
+    aggregator::Aggregator, utils::MathLog2, EitherIpRange, IpRange, Ipv4Range, Ipv6Range,
+};
+
+/// A set of IP addresses backed by a sorted, aggregated `Vec<R>`.
+///
+/// ```
+/// use cidr_aggregator::{EitherIpRange, IpRange, IpSet, Ipv4Range};
+///
+/// let v4 = |s: &str| s.parse::<EitherIpRange>().unwrap().into_v4().unwrap();
+///
+/// let mut set = IpSet::<Ipv4Range>::new();
+/// set.insert(v4("10.0.0.0/24"));
+/// set.insert(v4("10.0.1.0/24"));
+/// set.remove(v4("10.0.0.0/25"));
+/// assert!(set.contains(v4("10.0.1.0/24")));
+/// assert_eq!(set.export(), "10.0.0.128/25\n10.0.1.0/24");
+///
+/// let public = IpSet::full();
+/// let reserved: IpSet<_> = Ipv4Range::reserved().iter().copied().collect();
+/// let blocked: IpSet<_> = [v4("1.2.3.0/24")].into_iter().collect();
+/// let allowed = public & (!reserved - blocked);
+/// assert!(allowed.contains(v4("8.8.8.0/24")));
+/// assert!(!allowed.contains(v4("1.2.3.4/32")));
+/// assert!(!allowed.contains(v4("10.0.0.0/8")));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IpSet<R: IpRange> {
+    ranges: Vec<R>,
+}
+
+impl<R: IpRange> IpSet<R> {
+    /// An empty set.
+    pub fn new() -> Self {
+        IpSet { ranges: Vec::new() }
+    }
+
+    /// The set covering the full IP space.
+    pub fn full() -> Self {
+        IpSet {
+            ranges: vec![R::full()],
+        }
+    }
+
+    /// Build a set from arbitrary (unsorted, overlapping) ranges.
+    pub fn from_ranges(ranges: Vec<R>) -> Self {
+        IpSet {
+            ranges: ranges.aggregated(),
+        }
+    }
+
+    /// Returns `true` if the set contains no address.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Number of addresses in the set.
+    ///
+    /// Like [`Aggregator::count_address`], this wraps to `0` for the full IP space.
+    pub fn count_address(&self) -> R::AddressDecimal {
+        self.ranges.count_address()
+    }
+
+    /// Add every address in `range` to the set.
+    pub fn insert(&mut self, range: R) {
+        self.ranges.union(&[range]);
+    }
+
+    /// Remove every address in `range` from the set.
+    pub fn remove(&mut self, range: R) {
+        self.ranges.difference(&[range]);
+    }
+
+    /// Returns `true` if every address in `range` is in the set.
+    pub fn contains(&self, range: R) -> bool {
+        self.aggregated_range_containing(range.first_address_as_decimal())
+            .is_some_and(|r| r.last_address_as_decimal() >= range.last_address_as_decimal())
+    }
+
+    /// Returns `true` if `addr` is in the set. Runs in `O(log n)`.
+    pub fn contains_addr(&self, addr: R::Address) -> bool {
+        self.aggregated_range_containing(addr.into()).is_some()
+    }
+
+    /// The CIDR block of the set's normalized form that contains `addr`, if any.
+    ///
+    /// ```
+    /// use std::net::Ipv4Addr;
+    /// use cidr_aggregator::{EitherIpRange, IpSet, Ipv4Range};
+    ///
+    /// let v4 = |s: &str| s.parse::<EitherIpRange>().unwrap().into_v4().unwrap();
+    ///
+    /// let set: IpSet<Ipv4Range> = [v4("203.0.113.0/25"), v4("203.0.113.128/26")]
+    ///     .into_iter()
+    ///     .collect();
+    /// let hit = set.covering_range(Ipv4Addr::new(203, 0, 113, 150));
+    /// assert_eq!(hit, Some(v4("203.0.113.128/26")));
+    /// assert_eq!(set.covering_range(Ipv4Addr::new(203, 0, 113, 200)), None);
+    /// ```
+    pub fn covering_range(&self, addr: R::Address) -> Option<R> {
+        let addr = addr.into();
+        let (mut first, mut last) = self
+            .aggregated_range_containing(addr)?
+            .into_cidr_pair_decimal();
+        loop {
+            let (block, rest) = split_first_block::<R>(first, last);
+            if block.last_address_as_decimal() >= addr {
+                return Some(block);
+            }
+            (first, last) = rest.expect("address lies within the range");
+        }
+    }
+
+    /// Binary search for the aggregated range containing `addr`.
+    fn aggregated_range_containing(&self, addr: R::AddressDecimal) -> Option<&R> {
+        // Aggregated ranges never touch, so only the last range starting at or
+        // before `addr` can contain it.
+        let i = self
+            .ranges
+            .partition_point(|r| r.first_address_as_decimal() <= addr);
+        self.ranges[..i]
+            .last()
+            .filter(|r| r.last_address_as_decimal() >= addr)
+    }
+
+    /// Addresses in `self` or `other`.
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        IpSet {
+            ranges: self.ranges.clone().unioned(&other.ranges),
+        }
+    }
+
+    /// Addresses in both `self` and `other`.
+    #[must_use]
+    pub fn intersection(&self, other: &Self) -> Self {
+        IpSet {
+            ranges: self.ranges.clone().intersected(&other.ranges),
+        }
+    }
+
+    /// Addresses in `self` but not in `other`.
+    #[must_use]
+    pub fn difference(&self, other: &Self) -> Self {
+        IpSet {
+            ranges: self.ranges.clone().differenced(&other.ranges),
+        }
+    }
+
+    /// Addresses in exactly one of `self` and `other`.
+    #[must_use]
+    pub fn symmetric_difference(&self, other: &Self) -> Self {
+        IpSet {
+            ranges: self.ranges.clone().symmetric_differenced(&other.ranges),
+        }
+    }
+
+    /// Addresses not in `self`.
+    #[must_use]
+    pub fn complement(&self) -> Self {
+        IpSet {
+            ranges: self.ranges.clone().reversed(),
+        }
+    }
+
+    /// Iterate over the set as canonical CIDR blocks, in ascending order.
+    pub fn iter(&self) -> Cidrs<'_, R> {
+        Cidrs {
+            ranges: &self.ranges,
+            splitter: Splitter::new(),
+        }
+    }
+
+    /// Serialize the set to CIDR notation, one block per line.
+    pub fn export(&self) -> String {
+        self.iter().join("\n")
+    }
+
+    /// Unwrap into the underlying sorted, aggregated ranges.
+    ///
+    /// The ranges are not normalized, so they may not be valid CIDR blocks.
+    pub fn into_ranges(self) -> Vec<R> {
+        self.ranges
+    }
+}
+
+impl<R: IpRange> Default for IpSet<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: IpRange> From<Vec<R>> for IpSet<R> {
+    fn from(ranges: Vec<R>) -> Self {
+        Self::from_ranges(ranges)
+    }
+}
+
+/// A pair of [`IpSet`]s covering both address families.
+///
+/// Useful for checking a client address, whichever its family, against a list
+/// such as a blocklist.
+///
+/// ```
+/// use std::net::IpAddr;
+/// use cidr_aggregator::{parse_cidrs, DualStackIpSet};
+///
+/// let (v4, v6, _) = parse_cidrs("203.0.113.0/24\n2001:db8::/32");
+/// let blocklist = DualStackIpSet::from_ranges(v4, v6);
+/// assert!(blocklist.contains_addr("203.0.113.7".parse::<IpAddr>().unwrap()));
+/// assert!(blocklist.contains_addr("2001:db8::1".parse::<IpAddr>().unwrap()));
+/// assert!(!blocklist.contains_addr("198.51.100.1".parse::<IpAddr>().unwrap()));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct DualStackIpSet {
+    pub v4: IpSet<Ipv4Range>,
+    pub v6: IpSet<Ipv6Range>,
+}
+
+impl DualStackIpSet {
+    /// An empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a set from arbitrary IPv4 and IPv6 ranges, e.g. the output of
+    /// [`parse_cidrs`](crate::parse_cidrs).
+    pub fn from_ranges(v4: Vec<Ipv4Range>, v6: Vec<Ipv6Range>) -> Self {
+        DualStackIpSet {
+            v4: IpSet::from_ranges(v4),
+            v6: IpSet::from_ranges(v6),
+        }
+    }
+
+    /// Returns `true` if neither family contains any address.
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_empty() && self.v6.is_empty()
+    }
+
+    /// Add every address in `range` to the set of its family.
+    pub fn insert(&mut self, range: EitherIpRange) {
+        match range {
+            EitherIpRange::V4(r) => self.v4.insert(r),
+            EitherIpRange::V6(r) => self.v6.insert(r),
+        }
+    }
+
+    /// Returns `true` if `addr` is in the set.
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`), as reported by dual-stack
+    /// sockets, are looked up in the IPv4 set as `a.b.c.d`, then in the IPv6
+    /// set as they are, so that ranges inserted either way match them.
+    pub fn contains_addr(&self, addr: IpAddr) -> bool {
+        self.covering_range(addr).is_some()
+    }
+
+    /// The CIDR block containing `addr`, if any. See [`IpSet::covering_range`].
+    ///
+    /// IPv4-mapped IPv6 addresses are looked up in both sets, as in
+    /// [`contains_addr`](Self::contains_addr), and the IPv4 block wins.
+    pub fn covering_range(&self, addr: IpAddr) -> Option<EitherIpRange> {
+        let v4 = match addr.to_canonical() {
+            IpAddr::V4(v4) => self.v4.covering_range(v4).map(EitherIpRange::V4),
+            IpAddr::V6(_) => None,
+        };
+        v4.or_else(|| match addr {
+            IpAddr::V4(_) => None,
+            IpAddr::V6(v6) => self.v6.covering_range(v6).map(EitherIpRange::V6),
+        })
+    }
+}
+
+impl FromIterator<EitherIpRange> for DualStackIpSet {
+    fn from_iter<I: IntoIterator<Item = EitherIpRange>>(iter: I) -> Self {
+        let (v4, v6) = iter.into_iter().partition_map(|range| match range {
+            EitherIpRange::V4(r) => Either::Left(r),
+            EitherIpRange::V6(r) => Either::Right(r),
+        });
+        Self::from_ranges(v4, v6)
+    }
+}
+
+impl<R: IpRange> FromIterator<R> for IpSet<R> {
+    fn from_iter<I: IntoIterator<Item = R>>(iter: I) -> Self {
+        Self::from_ranges(iter.into_iter().collect())
+    }
+}
+
+impl<R: IpRange> Extend<R> for IpSet<R> {
+    fn extend<I: IntoIterator<Item = R>>(&mut self, iter: I) {
+        self.ranges.extend(iter);
+        self.ranges.aggregate();
+    }
+}
+
+impl<R: IpRange> IntoIterator for IpSet<R> {
+    type Item = R;
+    type IntoIter = IntoCidrs<R>;
+
+    fn into_iter(self) -> IntoCidrs<R> {
+        IntoCidrs {
+            ranges: self.ranges,
+            splitter: Splitter::new(),
+        }
+    }
+}
+
+impl<'a, R: IpRange> IntoIterator for &'a IpSet<R> {
+    type Item = R;
+    type IntoIter = Cidrs<'a, R>;
+
+    fn into_iter(self) -> Cidrs<'a, R> {
+        self.iter()
+    }
+}
+
+/// Implement a binary operator for every owned/borrowed combination of
+/// `IpSet` operands in terms of an [`Aggregator`] method.
+macro_rules! impl_set_op {
+    ($op_trait: ident, $op_method: ident, $aggregator_method: ident) => {
+        impl<R: IpRange> $op_trait<IpSet<R>> for IpSet<R> {
+            type Output = IpSet<R>;
+
+            fn $op_method(self, rhs: IpSet<R>) -> IpSet<R> {
+                IpSet {
+                    ranges: self.ranges.$aggregator_method(&rhs.ranges),
+                }
+            }
+        }
+
+        impl<R: IpRange> $op_trait<&IpSet<R>> for IpSet<R> {
+            type Output = IpSet<R>;
+
+            fn $op_method(self, rhs: &IpSet<R>) -> IpSet<R> {
+                IpSet {
+                    ranges: self.ranges.$aggregator_method(&rhs.ranges),
+                }
+            }
+        }
+
+        impl<R: IpRange> $op_trait<IpSet<R>> for &IpSet<R> {
+            type Output = IpSet<R>;
+
+            fn $op_method(self, rhs: IpSet<R>) -> IpSet<R> {
+                IpSet {
+                    ranges: self.ranges.clone().$aggregator_method(&rhs.ranges),
+                }
+            }
+        }
+
+        impl<R: IpRange> $op_trait<&IpSet<R>> for &IpSet<R> {
+            type Output = IpSet<R>;
+
+            fn $op_method(self, rhs: &IpSet<R>) -> IpSet<R> {
+                IpSet {
+                    ranges: self.ranges.clone().$aggregator_method(&rhs.ranges),
+                }
+            }
+        }
+    };
+}
+
+impl_set_op!(BitOr, bitor, unioned);
+impl_set_op!(BitAnd, bitand, intersected);
+impl_set_op!(Sub, sub, differenced);
+impl_set_op!(BitXor, bitxor, symmetric_differenced);
+
+impl<R: IpRange> Not for IpSet<R> {
+    type Output = IpSet<R>;
+
+    fn not(self) -> IpSet<R> {
+        IpSet {
+            ranges: self.ranges.reversed(),
+        }
+    }
+}
+
+impl<R: IpRange> Not for &IpSet<R> {
+    type Output = IpSet<R>;
+
+    fn not(self) -> IpSet<R> {
+        self.complement()
+    }
+}
+
+/// An inclusive `(first, last)` range in decimal form.
+type DecimalPair<R> = (
+    <R as IpRange>::AddressDecimal,
+    <R as IpRange>::AddressDecimal,
+);
+
+/// Split the leading CIDR block off the inclusive range `[first, last]`.
+///
+/// Returns the block and what is left of the range, if anything.
+fn split_first_block<R: IpRange>(
+    first: R::AddressDecimal,
+    last: R::AddressDecimal,
+) -> (R, Option<DecimalPair<R>>) {
+    let bits = (mem::size_of::<R::AddressDecimal>() * 8) as u32;
+    let span = last - first;
+    // The largest block that fits in what is left of the range, limited by
+    // the alignment of `first`.
+    let size_bits = min(
+        if span == R::AddressDecimal::max_value() {
+            bits
+        } else {
+            (span + R::AddressDecimal::one()).log2()
+        },
+        if first == R::AddressDecimal::zero() {
+            bits
+        } else {
+            first.trailing_zeros()
+        },
+    );
+    let block_last = if size_bits == bits {
+        R::AddressDecimal::max_value()
+    } else {
+        first + ((R::AddressDecimal::one() << size_bits as usize) - R::AddressDecimal::one())
+    };
+    let rest = if block_last != last {
+        Some((block_last + R::AddressDecimal::one(), last))
+    } else {
+        None
+    };
+    (R::from_cidr_pair_decimal((first, block_last)), rest)
+}
+
+/// Iterator over the canonical CIDR blocks of an [`IpSet`].
+///
+/// Created by [`IpSet::iter`]. Each aggregated range is split lazily, so the
+/// normalized form is never materialized as a whole.
+/// Position of a CIDR walk over a slice of aggregated ranges.
+///
+/// Shared by [`Cidrs`] and [`IntoCidrs`], which only differ in whether they
+/// borrow or own the ranges.
+#[derive(Debug, Clone)]
+struct Splitter<R: IpRange> {
+    next: usize,
+    pending: Option<DecimalPair<R>>,
+}
+
+impl<R: IpRange> Splitter<R> {
+    fn new() -> Self {
+        Splitter {
+            next: 0,
+            pending: None,
+        }
+    }
+
+    fn next_block(&mut self, ranges: &[R]) -> Option<R> {
+        let (first, last) = match self.pending.take() {
+            Some(pending) => pending,
+            None => {
+                let range = *ranges.get(self.next)?;
+                self.next += 1;
+                range.into_cidr_pair_decimal()
+            }
+        };
+        let (block, rest) = split_first_block::<R>(first, last);
+        self.pending = rest;
+        Some(block)
+    }
+}
+
+/// Iterator over the canonical CIDR blocks of an [`IpSet`].
+///
+/// Created by [`IpSet::iter`]. Each aggregated range is split lazily, so the
+/// normalized form is never materialized as a whole.
+#[derive(Debug, Clone)]
+pub struct Cidrs<'a, R: IpRange> {
+    ranges: &'a [R],
+    splitter: Splitter<R>,
+}
+
+impl<R: IpRange> Iterator for Cidrs<'_, R> {
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        self.splitter.next_block(self.ranges)
+    }
+}
+
+/// Owning iterator over the canonical CIDR blocks of an [`IpSet`].
+///
+/// Created by the [`IntoIterator`] implementation of `IpSet`.
+#[derive(Debug, Clone)]
+pub struct IntoCidrs<R: IpRange> {
+    ranges: Vec<R>,
+    splitter: Splitter<R>,
+}
+
+impl<R: IpRange> Iterator for IntoCidrs<R> {
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        self.splitter.next_block(&self.ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{v4, v4s, v6, v6s};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn new_is_empty() {
+        let set = IpSet::<Ipv4Range>::new();
+        assert!(set.is_empty());
+        assert_eq!(set.iter().count(), 0);
+        assert_eq!(set.export(), "");
+    }
+
+    #[test]
+    fn from_ranges_aggregates() {
+        let set = IpSet::from_ranges(v4s(&["10.0.1.0/24", "10.0.0.0/24", "10.0.0.128/25"]));
+        assert_eq!(set.into_ranges(), v4s(&["10.0.0.0/23"]));
+    }
+
+    #[test]
+    fn insert_merges() {
+        let mut set = IpSet::new();
+        set.insert(v4("10.0.2.0/24"));
+        set.insert(v4("10.0.0.0/24"));
+        set.insert(v4("10.0.1.0/24"));
+        assert_eq!(
+            set.into_ranges(),
+            vec![Ipv4Range::from_cidr_pair_decimal((0x0a000000, 0x0a0002ff))]
+        );
+    }
+
+    #[test]
+    fn remove_splits() {
+        let mut set = IpSet::from_ranges(v4s(&["10.0.0.0/23"]));
+        set.remove(v4("10.0.0.128/25"));
+        assert_eq!(set.export(), "10.0.0.0/25\n10.0.1.0/24");
+        set.remove(v4("10.0.0.0/16"));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn contains_range() {
+        let set = IpSet::from_ranges(v4s(&["10.0.0.0/24", "10.0.1.0/24", "192.168.0.0/16"]));
+        assert!(set.contains(v4("10.0.0.0/23")));
+        assert!(set.contains(v4("10.0.1.7/32")));
+        assert!(set.contains(v4("192.168.255.0/24")));
+        assert!(!set.contains(v4("10.0.0.0/22")));
+        assert!(!set.contains(v4("9.255.255.255/32")));
+        assert!(!set.contains(v4("192.169.0.0/24")));
+        assert!(!IpSet::new().contains(v4("10.0.0.0/8")));
+    }
+
+    #[test]
+    fn contains_addr() {
+        let set = IpSet::from_ranges(v4s(&["10.0.0.0/24", "10.0.1.0/24", "192.168.0.0/16"]));
+        assert!(set.contains_addr(Ipv4Addr::new(10, 0, 0, 0)));
+        assert!(set.contains_addr(Ipv4Addr::new(10, 0, 1, 255)));
+        assert!(set.contains_addr(Ipv4Addr::new(192, 168, 3, 4)));
+        assert!(!set.contains_addr(Ipv4Addr::new(10, 0, 2, 0)));
+        assert!(!set.contains_addr(Ipv4Addr::new(9, 255, 255, 255)));
+        assert!(!set.contains_addr(Ipv4Addr::new(0, 0, 0, 0)));
+        assert!(!set.contains_addr(Ipv4Addr::new(255, 255, 255, 255)));
+        assert!(!IpSet::<Ipv4Range>::new().contains_addr(Ipv4Addr::new(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn contains_addr_at_edges_of_space() {
+        let set = IpSet::from_ranges(v4s(&["0.0.0.0/32", "255.255.255.255/32"]));
+        assert!(set.contains_addr(Ipv4Addr::new(0, 0, 0, 0)));
+        assert!(set.contains_addr(Ipv4Addr::new(255, 255, 255, 255)));
+        assert!(!set.contains_addr(Ipv4Addr::new(0, 0, 0, 1)));
+        assert!(IpSet::<Ipv6Range>::full().contains_addr(Ipv6Addr::UNSPECIFIED));
+    }
+
+    #[test]
+    fn covering_range() {
+        // Aggregates to [10.0.0.0, 10.0.2.255], normalized as 10.0.0.0/23 + 10.0.2.0/24
+        let set = IpSet::from_ranges(v4s(&["10.0.0.0/24", "10.0.1.0/24", "10.0.2.0/24"]));
+        assert_eq!(
+            set.covering_range(Ipv4Addr::new(10, 0, 1, 9)),
+            Some(v4("10.0.0.0/23"))
+        );
+        assert_eq!(
+            set.covering_range(Ipv4Addr::new(10, 0, 2, 255)),
+            Some(v4("10.0.2.0/24"))
+        );
+        assert_eq!(set.covering_range(Ipv4Addr::new(10, 0, 3, 0)), None);
+        assert_eq!(
+            IpSet::<Ipv4Range>::full().covering_range(Ipv4Addr::new(1, 2, 3, 4)),
+            Some(Ipv4Range::full())
+        );
+    }
+
+    #[test]
+    fn covering_range_ipv6() {
+        let set = IpSet::from_ranges(v6s(&["2001:db8::/33", "2001:db8:8000::/34"]));
+        let addr = "2001:db8:a000::1".parse::<Ipv6Addr>().unwrap();
+        assert!(set.contains_addr(addr));
+        assert_eq!(set.covering_range(addr), Some(v6("2001:db8:8000::/34")));
+        assert_eq!(set.covering_range("2001:db8:c000::".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn dual_stack_lookup() {
+        let set: DualStackIpSet = [
+            EitherIpRange::V4(v4("203.0.113.0/24")),
+            EitherIpRange::V6(v6("2001:db8::/32")),
+        ]
+        .into_iter()
+        .collect();
+        let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(set.contains_addr(addr("203.0.113.7")));
+        assert!(set.contains_addr(addr("::ffff:203.0.113.7")));
+        assert!(set.contains_addr(addr("2001:db8::1")));
+        assert!(!set.contains_addr(addr("198.51.100.1")));
+        assert!(!set.contains_addr(addr("2001:db9::1")));
+        assert_eq!(
+            set.covering_range(addr("203.0.113.7")),
+            Some(EitherIpRange::V4(v4("203.0.113.0/24")))
+        );
+        assert_eq!(
+            set.covering_range(addr("2001:db8::1")),
+            Some(EitherIpRange::V6(v6("2001:db8::/32")))
+        );
+    }
+
+    #[test]
+    fn dual_stack_insert() {
+        let mut set = DualStackIpSet::new();
+        assert!(set.is_empty());
+        set.insert(EitherIpRange::V6(v6("2001:db8::/32")));
+        assert!(set.v4.is_empty());
+        assert!(!set.is_empty());
+        assert!(set.contains_addr("2001:db8::".parse().unwrap()));
+    }
+
+    #[test]
+    fn dual_stack_mapped_ranges() {
+        let mut set = DualStackIpSet::new();
+        set.insert(EitherIpRange::V6(v6("::ffff:0:0/96")));
+        let mapped = "::ffff:192.0.2.1".parse().unwrap();
+        assert!(set.contains_addr(mapped));
+        assert_eq!(
+            set.covering_range(mapped),
+            Some(EitherIpRange::V6(v6("::ffff:0:0/96")))
+        );
+        assert!(!set.contains_addr("192.0.2.1".parse().unwrap()));
+
+        set.insert(EitherIpRange::V4(v4("192.0.2.0/24")));
+        assert_eq!(
+            set.covering_range(mapped),
+            Some(EitherIpRange::V4(v4("192.0.2.0/24")))
+        );
+    }
+
+    #[test]
+    fn set_operations() {
+        let a = IpSet::from_ranges(v4s(&["10.0.0.0/23"]));
+        let b = IpSet::from_ranges(v4s(&["10.0.1.0/24", "10.0.2.0/24"]));
+        assert_eq!(
+            a.union(&b).into_ranges(),
+            vec![Ipv4Range::from_cidr_pair_decimal((0x0a000000, 0x0a0002ff))]
+        );
+        assert_eq!(a.intersection(&b).into_ranges(), v4s(&["10.0.1.0/24"]));
+        assert_eq!(a.difference(&b).into_ranges(), v4s(&["10.0.0.0/24"]));
+        assert_eq!(b.difference(&a).into_ranges(), v4s(&["10.0.2.0/24"]));
+    }
+
+    #[test]
+    fn complement() {
+        assert_eq!(IpSet::<Ipv4Range>::new().complement(), IpSet::full());
+        assert!(IpSet::<Ipv6Range>::full().complement().is_empty());
+        let set = IpSet::from_ranges(v4s(&["0.0.0.0/1"]));
+        assert_eq!(set.complement().export(), "128.0.0.0/1");
+    }
+
+    #[test]
+    fn iter_normalizes() {
+        // [10.0.0.0, 10.0.2.255] is not a CIDR block on its own
+        let set = IpSet::from_ranges(v4s(&["10.0.0.0/24", "10.0.1.0/24", "10.0.2.0/24"]));
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            v4s(&["10.0.0.0/23", "10.0.2.0/24"])
+        );
+    }
+
+    #[test]
+    fn iter_matches_normalized() {
+        let ranges = vec![
+            Ipv4Range::from_cidr_pair_decimal((0x0a000001, 0x0a0000fe)),
+            Ipv4Range::from_cidr_pair_decimal((0x0b000003, 0x0b100005)),
+        ];
+        let set = IpSet::from_ranges(ranges.clone());
+        assert_eq!(set.iter().collect::<Vec<_>>(), ranges.normalized());
+    }
+
+    #[test]
+    fn iter_full_and_edges() {
+        assert_eq!(IpSet::<Ipv4Range>::full().export(), "0.0.0.0/0");
+        assert_eq!(IpSet::<Ipv6Range>::full().export(), "::/0");
+        let set = IpSet::from_ranges(vec![Ipv4Range::from_cidr_pair_decimal((1, u32::MAX))]);
+        let cidrs = set.iter().collect::<Vec<_>>();
+        assert_eq!(cidrs.len(), 32);
+        assert_eq!(cidrs.first().unwrap().to_string(), "0.0.0.1/32");
+        assert_eq!(cidrs.last().unwrap().to_string(), "128.0.0.0/1");
+    }
+
+    #[test]
+    fn symmetric_difference() {
+        let a = IpSet::from_ranges(v4s(&["10.0.0.0/23"]));
+        let b = IpSet::from_ranges(v4s(&["10.0.1.0/24", "10.0.2.0/24"]));
+        assert_eq!(
+            a.symmetric_difference(&b).export(),
+            "10.0.0.0/24\n10.0.2.0/24"
+        );
+    }
+
+    #[test]
+    fn operators_match_methods() {
+        let a = IpSet::from_ranges(v4s(&["10.0.0.0/8", "192.168.0.0/16"]));
+        let b = IpSet::from_ranges(v4s(&["10.128.0.0/9", "172.16.0.0/12"]));
+        assert_eq!(&a | &b, a.union(&b));
+        assert_eq!(&a & &b, a.intersection(&b));
+        assert_eq!(&a - &b, a.difference(&b));
+        assert_eq!(&a ^ &b, a.symmetric_difference(&b));
+        assert_eq!(!&a, a.complement());
+        assert_eq!(a.clone() | b.clone(), a.union(&b));
+        assert_eq!(a.clone() & &b, a.intersection(&b));
+        assert_eq!(&a - b.clone(), a.difference(&b));
+        assert_eq!(a.clone() ^ b.clone(), a.symmetric_difference(&b));
+        assert_eq!(!a.clone(), a.complement());
+    }
+
+    #[test]
+    fn operator_expression() {
+        let public = IpSet::<Ipv4Range>::full();
+        let reserved = IpSet::from_ranges(Ipv4Range::reserved().to_vec());
+        let blocked = IpSet::from_ranges(v4s(&["1.2.3.0/24"]));
+        let allowed = public & (!reserved.clone() - &blocked);
+        assert_eq!(allowed, (!reserved).difference(&blocked));
+        assert!(allowed.contains(v4("8.8.8.0/24")));
+        assert!(!allowed.contains(v4("1.2.3.0/24")));
+        assert!(!allowed.contains(v4("192.168.0.0/16")));
+    }
+
+    #[test]
+    fn not_is_involutive() {
+        let a = IpSet::from_ranges(v6s(&["::/128", "2001:db8::/32"]));
+        assert_eq!(!!a.clone(), a);
+        assert_eq!(!IpSet::<Ipv6Range>::new(), IpSet::full());
+    }
+
+    #[test]
+    fn from_iterator_and_extend() {
+        let mut set: IpSet<_> = v4s(&["10.0.1.0/24", "10.0.0.0/24"]).into_iter().collect();
+        assert_eq!(set.export(), "10.0.0.0/23");
+        set.extend(v4s(&["10.0.2.0/23", "10.0.0.0/25"]));
+        assert_eq!(set.export(), "10.0.0.0/22");
+    }
+
+    #[test]
+    fn into_iterator() {
+        let set = IpSet::from_ranges(v4s(&["10.0.0.0/24", "10.0.1.0/24", "10.0.2.0/24"]));
+        let borrowed = (&set).into_iter().collect::<Vec<_>>();
+        let mut from_loop = Vec::new();
+        for cidr in &set {
+            from_loop.push(cidr);
+        }
+        let owned = set.into_iter().collect::<Vec<_>>();
+        assert_eq!(borrowed, v4s(&["10.0.0.0/23", "10.0.2.0/24"]));
+        assert_eq!(from_loop, borrowed);
+        assert_eq!(owned, borrowed);
+    }
+
+    #[test]
+    fn ipv6_set() {
+        let mut set = IpSet::from_ranges(v6s(&["2001:db8::/32"]));
+        set.remove(v6("2001:db8::/33"));
+        assert!(set.contains(v6("2001:db8:8000::/33")));
+        assert!(!set.contains(v6("2001:db8::1/128")));
+        assert_eq!(set.export(), "2001:db8:8000::/33");
+    }
+}
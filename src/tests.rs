@@ -2,7 +2,7 @@
 
 use std::net::{Ipv4Addr, Ipv6Addr};
 
-use crate::{aggregator::Aggregator, EitherIpRange, IpRange, Ipv4Range, Ipv6Range};
+use crate::{aggregator::Aggregator, EitherIpRange, IpRange, Ipv4Range, Ipv6Range, ParseCidrError};
 
 // -- Shared helpers --
 
@@ -101,6 +101,141 @@ fn ipv6_full() {
     assert_eq!(r.last_address_as_decimal(), u128::MAX);
 }
 
+// -- Range predicates --
+
+#[test]
+fn ipv4_contains_addr() {
+    let r = v4("192.168.1.0/24");
+    assert!(r.contains_addr(Ipv4Addr::new(192, 168, 1, 0)));
+    assert!(r.contains_addr(Ipv4Addr::new(192, 168, 1, 255)));
+    assert!(!r.contains_addr(Ipv4Addr::new(192, 168, 2, 0)));
+    assert!(!r.contains_addr(Ipv4Addr::new(192, 168, 0, 255)));
+    assert!(Ipv4Range::full().contains_addr(Ipv4Addr::new(255, 255, 255, 255)));
+}
+
+#[test]
+fn ipv6_contains_addr() {
+    let r = v6("2001:db8::/32");
+    assert!(r.contains_addr(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+    assert!(!r.contains_addr(Ipv6Addr::new(0x2001, 0xdb9, 0, 0, 0, 0, 0, 0)));
+    assert!(Ipv6Range::full().contains_addr(Ipv6Addr::UNSPECIFIED));
+}
+
+#[test]
+fn ipv4_contains_range() {
+    let r = v4("10.0.0.0/16");
+    assert!(r.contains_range(&v4("10.0.1.0/24")));
+    assert!(r.contains_range(&r));
+    assert!(!r.contains_range(&v4("10.0.0.0/15")));
+    assert!(!v4("10.0.1.0/24").contains_range(&r));
+    assert!(Ipv4Range::full().contains_range(&v4("255.255.255.255/32")));
+}
+
+#[test]
+fn ipv6_contains_range() {
+    assert!(v6("2001:db8::/32").contains_range(&v6("2001:db8:1::/48")));
+    assert!(!v6("2001:db8:1::/48").contains_range(&v6("2001:db8::/32")));
+}
+
+#[test]
+fn ipv4_overlaps() {
+    let a = Ipv4Range::from_cidr_pair_decimal((10, 20));
+    assert!(a.overlaps(&Ipv4Range::from_cidr_pair_decimal((20, 30))));
+    assert!(a.overlaps(&Ipv4Range::from_cidr_pair_decimal((0, 10))));
+    assert!(a.overlaps(&Ipv4Range::from_cidr_pair_decimal((12, 13))));
+    assert!(!a.overlaps(&Ipv4Range::from_cidr_pair_decimal((21, 30))));
+    assert!(!a.overlaps(&Ipv4Range::from_cidr_pair_decimal((0, 9))));
+}
+
+#[test]
+fn ipv6_overlaps() {
+    assert!(v6("2001:db8::/32").overlaps(&v6("2001:db8:ffff::/48")));
+    assert!(!v6("2001:db8::/32").overlaps(&v6("2001:db9::/32")));
+}
+
+#[test]
+fn ipv4_is_adjacent_to() {
+    let a = v4("10.0.0.0/24");
+    assert!(a.is_adjacent_to(&v4("10.0.1.0/24")));
+    assert!(v4("10.0.1.0/24").is_adjacent_to(&a));
+    assert!(!a.is_adjacent_to(&v4("10.0.2.0/24")));
+    assert!(!a.is_adjacent_to(&v4("10.0.0.128/25")));
+}
+
+#[test]
+fn ipv4_is_adjacent_to_does_not_wrap() {
+    let top = v4("255.255.255.255/32");
+    let bottom = v4("0.0.0.0/32");
+    assert!(!top.is_adjacent_to(&bottom));
+    assert!(!bottom.is_adjacent_to(&top));
+    assert!(!Ipv4Range::full().is_adjacent_to(&Ipv4Range::full()));
+}
+
+#[test]
+fn ipv6_is_adjacent_to() {
+    assert!(v6("2001:db8::/33").is_adjacent_to(&v6("2001:db8:8000::/33")));
+    let top = v6("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff/128");
+    assert!(!top.is_adjacent_to(&v6("::/128")));
+    assert!(top.is_adjacent_to(&Ipv6Range::from_cidr_pair_decimal((0, u128::MAX - 1))));
+}
+
+#[test]
+fn ipv4_merge_with() {
+    assert_eq!(
+        v4("10.0.0.0/24").merge_with(&v4("10.0.1.0/24")),
+        Some(v4("10.0.0.0/23"))
+    );
+    assert_eq!(
+        v4("10.0.0.0/23").merge_with(&v4("10.0.1.0/24")),
+        Some(v4("10.0.0.0/23"))
+    );
+    assert_eq!(v4("10.0.0.0/24").merge_with(&v4("10.0.2.0/24")), None);
+    assert_eq!(
+        v4("0.0.0.0/1").merge_with(&v4("128.0.0.0/1")),
+        Some(Ipv4Range::full())
+    );
+    assert_eq!(v4("255.255.255.255/32").merge_with(&v4("0.0.0.0/32")), None);
+}
+
+#[test]
+fn ipv6_merge_with() {
+    assert_eq!(
+        v6("2001:db8::/33").merge_with(&v6("2001:db8:8000::/33")),
+        Some(v6("2001:db8::/32"))
+    );
+    assert_eq!(v6("2001:db8::/33").merge_with(&v6("2001:db9::/32")), None);
+}
+
+#[test]
+fn ipv4_intersection_with() {
+    let a = Ipv4Range::from_cidr_pair_decimal((10, 20));
+    assert_eq!(
+        a.intersection_with(&Ipv4Range::from_cidr_pair_decimal((15, 30))),
+        Some(Ipv4Range::from_cidr_pair_decimal((15, 20)))
+    );
+    assert_eq!(
+        a.intersection_with(&Ipv4Range::from_cidr_pair_decimal((21, 30))),
+        None
+    );
+    assert_eq!(
+        Ipv4Range::full().intersection_with(&v4("255.255.255.255/32")),
+        Some(v4("255.255.255.255/32"))
+    );
+}
+
+#[test]
+fn ipv6_intersection_with() {
+    assert_eq!(
+        v6("2001:db8::/32").intersection_with(&v6("2001:db8:1::/48")),
+        Some(v6("2001:db8:1::/48"))
+    );
+    assert_eq!(
+        Ipv6Range::full().intersection_with(&Ipv6Range::full()),
+        Some(Ipv6Range::full())
+    );
+    assert_eq!(v6("::/128").intersection_with(&v6("::1/128")), None);
+}
+
 // -- Display tests --
 
 #[test]
@@ -176,23 +311,95 @@ fn parse_bare_ipv6_treated_as_128() {
 fn parse_rejects_host_address() {
     // 192.168.1.5/24 has host bits set (the .5)
     assert!("192.168.1.5/24".parse::<EitherIpRange>().is_err());
+    assert_eq!(
+        "192.168.1.5/24".parse::<EitherIpRange>(),
+        Err(ParseCidrError::HostBitsSet { prefix_len: 24 })
+    );
+    assert_eq!(
+        "2001:db8::1/64".parse::<EitherIpRange>(),
+        Err(ParseCidrError::HostBitsSet { prefix_len: 64 })
+    );
 }
 
 #[test]
 fn parse_rejects_invalid_ip() {
     assert!("not.an.ip/24".parse::<EitherIpRange>().is_err());
     assert!("abc/24".parse::<EitherIpRange>().is_err());
+    assert_eq!(
+        "10.0.0.256/32".parse::<EitherIpRange>(),
+        Err(ParseCidrError::InvalidAddress)
+    );
+}
+
+#[test]
+fn parse_rejects_address_range() {
+    // Ranges need not be CIDR blocks, so only `parser::parse_entry` reads them
+    for input in [
+        "10.0.0.0-10.0.0.2",
+        "10.0.0.0-255",
+        "2001:db8::-2001:db8::ff",
+    ] {
+        assert_eq!(
+            input.parse::<EitherIpRange>(),
+            Err(ParseCidrError::InvalidAddress),
+            "{}",
+            input
+        );
+    }
 }
 
 #[test]
 fn parse_rejects_empty() {
     assert!("".parse::<EitherIpRange>().is_err());
+    assert_eq!(
+        "".parse::<EitherIpRange>(),
+        Err(ParseCidrError::InvalidAddress)
+    );
 }
 
 #[test]
 fn parse_rejects_invalid_cidr_too_large() {
     assert!("10.0.0.0/33".parse::<EitherIpRange>().is_err());
     assert!("::/129".parse::<EitherIpRange>().is_err());
+    assert_eq!(
+        "10.0.0.0/300".parse::<EitherIpRange>(),
+        Err(ParseCidrError::PrefixTooLong {
+            prefix_len: 300,
+            max_len: 32
+        })
+    );
+}
+
+#[test]
+fn parse_rejects_non_numeric_prefix() {
+    for input in ["10.0.0.0/abc", "10.0.0.0/-8", "10.0.0.0/+8", "::/ 64"] {
+        assert_eq!(
+            input.parse::<EitherIpRange>(),
+            Err(ParseCidrError::InvalidPrefixLength),
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn parse_error_display() {
+    assert_eq!(
+        ParseCidrError::PrefixTooLong {
+            prefix_len: 33,
+            max_len: 32
+        }
+        .to_string(),
+        "prefix length /33 exceeds the maximum of /32"
+    );
+    assert_eq!(
+        ParseCidrError::HostBitsSet { prefix_len: 24 }.to_string(),
+        "host bits are set beyond the /24 prefix"
+    );
+    assert_eq!(
+        ParseCidrError::InvalidPrefixLength.to_string(),
+        "prefix length is neither a number nor a dotted mask"
+    );
 }
 
 #[test]
@@ -71,6 +71,18 @@ pub fn ip_addr_trailing_zeros(ipa: IpAddr) -> u32 {
     }
 }
 
+/// Clears every bit of `ipa` beyond the first `prefix_len` bits.
+pub fn truncate_host_bits(ipa: IpAddr, prefix_len: u32) -> IpAddr {
+    match ipa {
+        IpAddr::V4(ip) => {
+            IpAddr::V4((u32::from(ip) & u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0)).into())
+        }
+        IpAddr::V6(ip) => IpAddr::V6(
+            (u128::from(ip) & u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0)).into(),
+        ),
+    }
+}
+
 /// Reserved IPv4 address blocks (RFC 5735, RFC 6890), pre-aggregated.
 pub static IPV4_RESERVED: LazyLock<Vec<Ipv4Range>> = LazyLock::new(|| {
     [
@@ -200,6 +212,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn truncate_host_bits_v4() {
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5));
+        assert_eq!(
+            truncate_host_bits(ip, 24),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 0))
+        );
+        assert_eq!(truncate_host_bits(ip, 32), ip);
+        assert_eq!(truncate_host_bits(ip, 0), IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    }
+
+    #[test]
+    fn truncate_host_bits_v6() {
+        let ip = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        assert_eq!(
+            truncate_host_bits(ip, 64),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0))
+        );
+        assert_eq!(truncate_host_bits(ip, 128), ip);
+        assert_eq!(truncate_host_bits(ip, 0), IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+    }
+
     #[test]
     fn to_string_overflow_normal() {
         assert_eq!(to_string_overflow(256u32, false), "256");
@@ -1,3 +1,4 @@
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
@@ -55,7 +56,7 @@ pub fn aggregate(cidrs: &str, reverse: bool, exclude_reserved: bool) -> JsValue
     serde_wasm_bindgen::to_value(&Output {
         v4: build_output(v4ranges, reverse, exclude_reserved),
         v6: build_output(v6ranges, reverse, exclude_reserved),
-        invalid: invalid_entries.join("\n"),
+        invalid: invalid_entries.iter().map(|entry| &entry.line).join("\n"),
     })
     .unwrap()
 }
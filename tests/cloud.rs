@@ -0,0 +1,96 @@
+//! Cloud provider IP range files, read from the fixtures in `tests/fixtures`.
+#![cfg(feature = "cloud-formats")]
+
+use std::fs;
+
+use cidr_aggregator::aggregator::Aggregator;
+use cidr_aggregator::formats::cloud::{
+    parse_aws_ip_ranges, parse_azure_service_tags, parse_cloud_ranges, parse_cloudflare_ips,
+    parse_gcp_ip_ranges, CloudFilter, CloudPrefix,
+};
+
+fn fixture(name: &str) -> String {
+    fs::read_to_string(format!(
+        "{}/tests/fixtures/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    ))
+    .unwrap()
+}
+
+fn filter(services: &[&str], regions: &[&str], tags: &[&str]) -> CloudFilter {
+    let owned = |values: &[&str]| values.iter().map(|&value| value.to_owned()).collect();
+    CloudFilter {
+        services: owned(services),
+        regions: owned(regions),
+        tags: owned(tags),
+    }
+}
+
+fn export(prefixes: &[CloudPrefix], filter: &CloudFilter) -> (String, String) {
+    let (v4, v6) = filter.collect(prefixes);
+    (v4.normalized().export(), v6.normalized().export())
+}
+
+#[test]
+fn aws_by_service_and_region() {
+    let prefixes = parse_aws_ip_ranges(&fixture("aws-ip-ranges.json")).unwrap();
+    assert_eq!(prefixes.len(), 10);
+
+    let (v4, v6) = export(&prefixes, &filter(&["EC2"], &["us-east-1"], &[]));
+    assert_eq!(v4, "52.95.245.0/24\n52.95.246.0/24");
+    assert_eq!(v6, "2600:1f18::/33");
+
+    let (v4, v6) = export(&prefixes, &filter(&["cloudfront", "s3"], &[], &[]));
+    assert_eq!(v4, "3.5.140.0/22\n13.32.0.0/15");
+    assert_eq!(v6, "2600:9000::/28");
+}
+
+#[test]
+fn gcp_by_region() {
+    let prefixes = parse_gcp_ip_ranges(&fixture("gcp-cloud.json")).unwrap();
+    let (v4, v6) = export(&prefixes, &filter(&[], &["asia-east1", "us-central1"], &[]));
+    assert_eq!(v4, "34.0.0.0/20\n34.80.0.0/15\n35.185.128.0/19");
+    assert_eq!(v6, "2600:1900:4000::/44");
+}
+
+#[test]
+fn azure_by_tag_and_service() {
+    let prefixes = parse_azure_service_tags(&fixture("azure-service-tags.json")).unwrap();
+    let (v4, v6) = export(&prefixes, &filter(&[], &[], &["AzureCloud.westus"]));
+    assert_eq!(v4, "13.64.0.0/15");
+    assert_eq!(v6, "2603:1030:a00::/46");
+
+    let (v4, _) = export(&prefixes, &filter(&["AzureStorage"], &["eastus"], &[]));
+    assert_eq!(v4, "20.38.98.0/24\n20.47.1.0/24");
+}
+
+#[test]
+fn cloudflare_everything() {
+    let prefixes = parse_cloudflare_ips(&fixture("cloudflare-ips.json")).unwrap();
+    let (v4, v6) = export(&prefixes, &CloudFilter::default());
+    assert_eq!(
+        v4,
+        "103.21.244.0/22\n103.22.200.0/22\n103.31.4.0/22\n173.245.48.0/20"
+    );
+    assert_eq!(v6, "2400:cb00::/32\n2606:4700::/32");
+    // Metadata filters exclude prefixes that have none
+    assert!(filter(&["CDN"], &[], &[]).collect(&prefixes).0.is_empty());
+}
+
+#[test]
+fn provider_detection() {
+    for (name, count) in [
+        ("aws-ip-ranges.json", 10),
+        ("gcp-cloud.json", 6),
+        ("azure-service-tags.json", 8),
+        ("cloudflare-ips.json", 6),
+    ] {
+        assert_eq!(
+            parse_cloud_ranges(&fixture(name)).unwrap().len(),
+            count,
+            "{}",
+            name
+        );
+    }
+}
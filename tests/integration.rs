@@ -1,6 +1,7 @@
 //! Integration tests exercising the full pipeline from parse → aggregate → normalize → export.
 
 use cidr_aggregator::aggregator::Aggregator;
+use cidr_aggregator::extract::extract_cidrs;
 use cidr_aggregator::{parse_cidrs, IpRange, Ipv4Range};
 
 #[test]
@@ -27,7 +28,8 @@ fn full_pipeline_with_comments_and_invalid() {
     let input = "# block list\n10.0.0.0/8\n# internal\n192.168.0.0/16\nbad-line\n172.16.0.0/12";
     let (mut v4, _v6, invalid) = parse_cidrs(input);
     assert_eq!(invalid.len(), 1);
-    assert_eq!(invalid[0], "bad-line");
+    assert_eq!(invalid[0].line, "bad-line");
+    assert_eq!(invalid[0].line_number, 5);
 
     v4.aggregate();
     v4.normalize();
@@ -120,6 +122,36 @@ fn bare_ip_treated_as_host() {
     assert_eq!(v4.export(), "10.0.0.1/32");
 }
 
+#[test]
+fn address_ranges_become_cidrs() {
+    let (mut v4, mut v6, invalid) =
+        parse_cidrs("10.0.0.0-10.0.1.255\n10.0.2.0 - 10.0.2.127\n2001:db8::-2001:db8::1:0");
+    assert!(invalid.is_empty());
+
+    v4.aggregate();
+    v4.normalize();
+    assert_eq!(v4.export(), "10.0.0.0/23\n10.0.2.0/25");
+
+    v6.aggregate();
+    v6.normalize();
+    assert_eq!(v6.export(), "2001:db8::/112\n2001:db8::1:0/128");
+}
+
+#[test]
+fn extracted_addresses_aggregate() {
+    let text = "| Source | Hits |\n|---|---|\n| 192.0.2.0 | 12 |\n| 192.0.2.1 | 7 |\n\
+                Reported by 2001:db8::1 running v1.2.3 at 12:30:45.";
+    let (mut v4, mut v6) = extract_cidrs(text);
+
+    v4.aggregate();
+    v4.normalize();
+    assert_eq!(v4.export(), "192.0.2.0/31");
+
+    v6.aggregate();
+    v6.normalize();
+    assert_eq!(v6.export(), "2001:db8::1/128");
+}
+
 #[test]
 fn full_range() {
     let (mut v4, _, _) = parse_cidrs("0.0.0.0/0");
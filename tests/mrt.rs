@@ -0,0 +1,95 @@
+//! MRT routing table dumps, read from the fixtures in `tests/fixtures`: a
+//! tiny `TABLE_DUMP_V2` file, as is and compressed.
+
+use std::fs;
+use std::io::{self, Read};
+use std::net::IpAddr;
+
+use cidr_aggregator::aggregator::Aggregator;
+use cidr_aggregator::formats::decompress;
+use cidr_aggregator::formats::mrt::{MrtReader, Route, RouteFilter};
+
+fn fixture(name: &str) -> Vec<u8> {
+    fs::read(format!(
+        "{}/tests/fixtures/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    ))
+    .unwrap()
+}
+
+fn routes<R: Read>(reader: R) -> Vec<Route> {
+    MrtReader::new(reader)
+        .map(|route| route.unwrap().unwrap())
+        .collect()
+}
+
+fn export(routes: &[Route], origins: &[u32]) -> (String, String) {
+    let filter = RouteFilter {
+        origins: origins.to_vec(),
+    };
+    let (v4, v6) = filter.collect(routes.iter().cloned());
+    (v4.normalized().export(), v6.normalized().export())
+}
+
+#[test]
+fn routes_and_peers() {
+    let input = fixture("rib.mrt");
+    let mut reader = MrtReader::new(&input[..]);
+    let routes: Vec<_> = reader
+        .by_ref()
+        .map(|route| route.unwrap().unwrap())
+        .collect();
+    // The BGP4MP record is skipped
+    assert_eq!(reader.record_number(), 9);
+    assert_eq!(routes.len(), 8);
+
+    let peers = reader.peers();
+    assert_eq!(peers.len(), 2);
+    assert_eq!(peers[1].address, "2001:db8::2".parse::<IpAddr>().unwrap());
+    assert_eq!(peers[1].asn, 4_200_000_000);
+
+    assert_eq!(routes[1].prefix, "1.0.0.0/24".parse().unwrap());
+    assert_eq!(routes[1].peer_index, 1);
+    assert_eq!(routes[1].as_path.to_string(), "4200000000 64501 13335");
+    assert_eq!(routes[4].as_path.to_string(), "64500 {64510,64511}");
+    // From the ADD-PATH record
+    assert_eq!(routes[7].prefix, "1.1.1.0/24".parse().unwrap());
+}
+
+#[test]
+fn prefixes_by_origin() {
+    let routes = routes(&fixture("rib.mrt")[..]);
+    assert_eq!(
+        export(&routes, &[13335]),
+        ("1.0.0.0/23\n1.1.1.0/24".into(), "2606:4700::/32".into())
+    );
+    assert_eq!(
+        export(&routes, &[15169, 64511]),
+        ("8.8.8.0/24\n192.0.2.0/24".into(), "2001:4860::/32".into())
+    );
+    // 64500 is on every path, but the origin of none
+    assert_eq!(export(&routes, &[64500]), ("".into(), "".into()));
+}
+
+#[test]
+fn compressed_input() {
+    let plain = routes(&fixture("rib.mrt")[..]);
+    for (name, feature_enabled) in [
+        ("rib.mrt", true),
+        ("rib.mrt.gz", cfg!(feature = "gzip")),
+        ("rib.mrt.bz2", cfg!(feature = "bzip2")),
+    ] {
+        let input = fixture(name);
+        match decompress(&input[..]) {
+            Ok(reader) => {
+                assert!(feature_enabled, "{}", name);
+                assert_eq!(routes(reader), plain, "{}", name);
+            }
+            Err(e) => {
+                assert!(!feature_enabled, "{}", name);
+                assert_eq!(e.kind(), io::ErrorKind::Unsupported, "{}", name);
+            }
+        };
+    }
+}